@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS refresh_token (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    label TEXT NOT NULL DEFAULT 'default',
+    refresh_token VARCHAR(64) NOT NULL
+);
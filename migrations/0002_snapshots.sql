@@ -0,0 +1,28 @@
+CREATE TABLE IF NOT EXISTS snapshots (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    taken_at TEXT NOT NULL,
+    total_cost REAL NOT NULL,
+    total_market_value REAL NOT NULL,
+    dataset TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS position_snapshots (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    snapshot_id INTEGER NOT NULL REFERENCES snapshots(id) ON DELETE CASCADE,
+    account_id TEXT NOT NULL,
+    symbol TEXT NOT NULL,
+    symbol_id INTEGER NOT NULL,
+    quantity REAL NOT NULL,
+    total_cost REAL NOT NULL,
+    market_value REAL NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS balance_snapshots (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    snapshot_id INTEGER NOT NULL REFERENCES snapshots(id) ON DELETE CASCADE,
+    account_id TEXT NOT NULL,
+    currency TEXT NOT NULL,
+    cash REAL NOT NULL,
+    market_value REAL NOT NULL,
+    total_equity REAL NOT NULL
+);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS price_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    symbol TEXT NOT NULL,
+    recorded_at TEXT NOT NULL,
+    price REAL NOT NULL
+);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS symbol_cache (
+    symbol_id INTEGER PRIMARY KEY,
+    symbol TEXT NOT NULL,
+    dividend REAL,
+    yield REAL,
+    prev_day_close_price REAL,
+    currency TEXT NOT NULL,
+    fetched_at TEXT NOT NULL
+);
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS notes (
+    symbol TEXT PRIMARY KEY,
+    note TEXT NOT NULL
+);
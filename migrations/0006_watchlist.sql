@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS watchlist (
+    symbol_id INTEGER PRIMARY KEY,
+    symbol TEXT NOT NULL
+);
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS asset_classification (
+    symbol TEXT PRIMARY KEY,
+    asset_class TEXT NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE position_snapshots ADD COLUMN currency TEXT NOT NULL DEFAULT '';
@@ -0,0 +1 @@
+ALTER TABLE snapshots ADD COLUMN complete INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,275 @@
+use crate::{
+    asset_tracker::{AccountID, SymbolID},
+    theme,
+};
+use chrono::{DateTime, FixedOffset};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt};
+
+/// Questrade rejects activity requests spanning more than 31 days.
+pub const MAX_ACTIVITY_WINDOW_DAYS: i64 = 30;
+const SUPERFICIAL_LOSS_WINDOW_DAYS: i64 = 30;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Activities {
+    pub activities: Vec<Activity>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Activity {
+    pub trade_date: DateTime<FixedOffset>,
+    pub action: String,
+    pub symbol: String,
+    pub symbol_id: SymbolID,
+    pub description: String,
+    pub currency: String,
+    pub quantity: f64,
+    pub price: f64,
+    pub net_amount: f64,
+    pub type_: String,
+    /// Which account the activity is in, filled in after fetching since Questrade lists
+    /// activities per account.
+    #[serde(skip)]
+    pub account_id: AccountID,
+}
+
+impl Activity {
+    fn is_trade(&self, action: &str) -> bool {
+        self.type_ == "Trades" && self.action.eq_ignore_ascii_case(action)
+    }
+
+    /// Whether this moves money into or out of the accounts.
+    pub fn is_contribution(&self) -> bool {
+        ["Deposits", "Withdrawals", "Transfers"].contains(&self.type_.as_str())
+    }
+}
+
+/// Money deposited minus money withdrawn in `activities`, in `currency` with amounts in the
+/// other one converted at `usd_to_cad`, or counted as they are if it isn't known. Transfers
+/// between the fetched accounts cancel out; securities transferred in carry no cash amount,
+/// so they aren't counted as contributions.
+pub fn net_contributions(activities: &[Activity], currency: &str, usd_to_cad: Option<f64>) -> f64 {
+    activities
+        .iter()
+        .filter(|activity| activity.is_contribution())
+        .map(
+            |activity| match (activity.currency.as_str(), currency, usd_to_cad) {
+                ("USD", "CAD", Some(rate)) => activity.net_amount * rate,
+                ("CAD", "USD", Some(rate)) => activity.net_amount / rate,
+                _ => activity.net_amount,
+            },
+        )
+        .sum()
+}
+
+pub struct SuperficialLossCandidate {
+    pub symbol: String,
+    pub sell_date: DateTime<FixedOffset>,
+    pub quantity: f64,
+    pub proceeds: f64,
+    pub loss: f64,
+    pub repurchases: Vec<(DateTime<FixedOffset>, f64)>,
+}
+
+/// Sells at a loss where the same symbol was bought within 30 days before or after,
+/// which makes the loss superficial under Canadian tax rules.
+pub struct WashCheck {
+    pub candidates: Vec<SuperficialLossCandidate>,
+    pub skipped_sells: usize,
+}
+
+impl WashCheck {
+    /// Losses are estimated against an average cost built from the buys in `activities`,
+    /// so sells whose shares were bought before the history window can't be checked
+    /// and are only counted in `skipped_sells`.
+    pub fn new(activities: &[Activity]) -> WashCheck {
+        let mut trades: Vec<_> = activities
+            .iter()
+            .filter(|activity| activity.is_trade("Buy") || activity.is_trade("Sell"))
+            .collect();
+        trades.sort_by_key(|activity| activity.trade_date);
+
+        let mut holdings: HashMap<&str, (f64, f64)> = HashMap::new();
+        let mut candidates = Vec::new();
+        let mut skipped_sells = 0;
+
+        for trade in trades.iter() {
+            let quantity = trade.quantity.abs();
+            let (held, cost) = holdings.entry(&trade.symbol).or_insert((0.0, 0.0));
+
+            if trade.is_trade("Buy") {
+                *held += quantity;
+                *cost += trade.net_amount.abs();
+                continue;
+            }
+
+            if *held <= 0.0 {
+                skipped_sells += 1;
+                continue;
+            }
+
+            let sold = quantity.min(*held);
+            let sold_cost = *cost / *held * sold;
+            *cost -= sold_cost;
+            *held -= sold;
+
+            let proceeds = trade.net_amount.abs();
+            if proceeds >= sold_cost {
+                continue;
+            }
+
+            // Earlier buys only matter if some of those shares are still held after the sale.
+            let still_held = *held > 0.0;
+            let repurchases: Vec<_> = trades
+                .iter()
+                .filter(|other| other.is_trade("Buy") && other.symbol == trade.symbol)
+                .filter(|other| other.trade_date > trade.trade_date || still_held)
+                .filter(|other| {
+                    (other.trade_date - trade.trade_date).num_days().abs()
+                        <= SUPERFICIAL_LOSS_WINDOW_DAYS
+                })
+                .map(|other| (other.trade_date, other.quantity.abs()))
+                .collect();
+
+            if !repurchases.is_empty() {
+                candidates.push(SuperficialLossCandidate {
+                    symbol: trade.symbol.clone(),
+                    sell_date: trade.trade_date,
+                    quantity,
+                    proceeds,
+                    loss: proceeds - sold_cost,
+                    repurchases,
+                });
+            }
+        }
+
+        WashCheck {
+            candidates,
+            skipped_sells,
+        }
+    }
+}
+
+impl fmt::Display for WashCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let title = format!("{}Superficial Loss Check{}", "-".repeat(40), "-".repeat(40));
+        writeln!(f, "{}", title.cyan())?;
+        writeln!(f)?;
+
+        if self.candidates.is_empty() {
+            writeln!(f, "No superficial loss candidates found")?;
+        } else {
+            writeln!(
+                f,
+                "{:<10} | {:<10} | {:<10} | {:<15} | {:<15} | Repurchases",
+                "Symbol", "Sell Date", "Quantity", "Proceeds", "Est. Loss"
+            )?;
+            writeln!(f, "{}", "-".repeat(101))?;
+
+            for candidate in self.candidates.iter() {
+                let repurchases: Vec<_> = candidate
+                    .repurchases
+                    .iter()
+                    .map(|(date, quantity)| format!("{} ({})", date.format("%Y-%m-%d"), quantity))
+                    .collect();
+
+                writeln!(
+                    f,
+                    "{:<10} | {:<10} | {:<10} | {:<15.2} | {:<15} | {}",
+                    candidate.symbol,
+                    candidate.sell_date.format("%Y-%m-%d"),
+                    candidate.quantity,
+                    candidate.proceeds,
+                    theme::current().loss(format!("{:.2}", candidate.loss)),
+                    repurchases.join(", ")
+                )?;
+            }
+        }
+
+        if self.skipped_sells > 0 {
+            writeln!(
+                f,
+                "{} sell(s) skipped because their purchases predate the history window",
+                self.skipped_sells
+            )?;
+        }
+        writeln!(f)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(date: &str, action: &str, quantity: f64, net_amount: f64) -> Activity {
+        Activity {
+            trade_date: DateTime::parse_from_rfc3339(&format!("{}T00:00:00-05:00", date)).unwrap(),
+            action: action.to_string(),
+            symbol: String::from("XEQT.TO"),
+            symbol_id: 1,
+            description: String::new(),
+            currency: String::from("CAD"),
+            quantity,
+            price: 0.0,
+            net_amount,
+            type_: String::from("Trades"),
+            account_id: String::new(),
+        }
+    }
+
+    #[test]
+    fn contributions_net_deposits_against_withdrawals() {
+        let cash = |type_: &str, currency: &str, net_amount: f64| Activity {
+            type_: type_.to_string(),
+            currency: currency.to_string(),
+            ..trade("2024-01-02", "", 0.0, net_amount)
+        };
+        let activities = vec![
+            cash("Deposits", "CAD", 1000.0),
+            cash("Deposits", "USD", 100.0),
+            cash("Withdrawals", "CAD", -200.0),
+            cash("Dividends", "CAD", 15.0),
+            trade("2024-01-03", "Buy", 10.0, -300.0),
+        ];
+
+        assert_eq!(net_contributions(&activities, "CAD", Some(1.35)), 935.0);
+        assert_eq!(net_contributions(&activities, "USD", Some(1.25)), 740.0);
+        assert_eq!(net_contributions(&activities, "CAD", None), 900.0);
+    }
+
+    #[test]
+    fn flags_loss_repurchased_within_window() {
+        let activities = vec![
+            trade("2024-01-02", "Buy", 10.0, -300.0),
+            trade("2024-02-01", "Sell", -10.0, 250.0),
+            trade("2024-02-20", "Buy", 10.0, -255.0),
+        ];
+
+        let check = WashCheck::new(&activities);
+
+        assert_eq!(check.candidates.len(), 1);
+        assert_eq!(check.candidates[0].loss, -50.0);
+        assert_eq!(check.candidates[0].repurchases.len(), 1);
+    }
+
+    #[test]
+    fn ignores_gains_and_repurchases_outside_window() {
+        let activities = vec![
+            trade("2024-01-02", "Buy", 10.0, -300.0),
+            trade("2024-03-01", "Sell", -5.0, 100.0),
+            trade("2024-05-01", "Buy", 5.0, -100.0),
+            trade("2024-05-02", "Sell", -5.0, 200.0),
+            trade("2024-06-01", "Sell", -5.0, 100.0),
+            trade("2024-06-02", "Sell", -5.0, 100.0),
+        ];
+
+        let check = WashCheck::new(&activities);
+
+        assert!(check.candidates.is_empty());
+        assert_eq!(check.skipped_sells, 1);
+    }
+}
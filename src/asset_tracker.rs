@@ -1,283 +1,4239 @@
 use crate::{
-    assets::Assets,
-    questrade_api::{QuestradeAPI, QuestradeAPIError},
+    activities::{self, Activities, Activity, WashCheck, MAX_ACTIVITY_WINDOW_DAYS},
+    assets::{self, colour_pnl, truncate_symbol, AssetClass, Assets, MinValue, SerializableAssets},
+    config::{Config, ExtendedQuotes, Profile, Targets, Tax},
+    db::{BalanceSnapshot, DatabaseAPI, Snapshot, WatchedSymbol},
+    questrade_api::{Pacer, QuestradeAPI, QuestradeAPIError, SymbolSearchResult},
+    table::{Align, Layout, Table},
+    theme, validation,
+};
+use chrono::{
+    DateTime, Datelike, FixedOffset, Local, NaiveDate, NaiveTime, TimeDelta, TimeZone, Utc, Weekday,
 };
 use colored::{ColoredString, Colorize};
+use futures::future;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use sqlx::FromRow;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::Display,
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{debug, instrument};
+
+pub type AccountID = String;
+pub type SymbolID = u32;
+
+/// How many symbols to look up per `v1/symbols?ids=` request.
+const SYMBOLS_PER_REQUEST: usize = 100;
 
-type AccountID = String;
-type SymbolID = u32;
+/// How many positions to ask for per `v1/accounts/{id}/positions` page.
+const POSITIONS_PER_PAGE: usize = 1000;
+
+/// The most positions pages fetched per account, in case the server never returns a short one.
+const MAX_POSITION_PAGES: usize = 20;
 
 pub struct AssetTracker {
-    questrade_api: QuestradeAPI,
+    db: DatabaseAPI,
+    questrade_api: Option<QuestradeAPI>,
     accounts: Vec<Account>,
     assets: Assets,
     positions: HashMap<AccountID, Vec<Position>>,
     balances: HashMap<AccountID, Balances>,
+    previous_balances: HashMap<AccountID, Balances>,
     symbols: HashMap<SymbolID, Symbol>,
+    /// Held symbols whose details couldn't be looked up, so their dividend and yield are
+    /// unknown rather than zero.
+    unresolved_symbols: HashSet<SymbolID>,
+    notes: HashMap<String, String>,
+    price_history: HashMap<String, Vec<f64>>,
+    targets: Targets,
+    asset_classes: HashMap<String, AssetClass>,
+    layout: Layout,
+    display_currency: String,
+    request_pacing: Duration,
+    request_retries: u32,
+    symbol_cache_max_age: TimeDelta,
+    error_policy: ErrorPolicy,
+    account_type: Option<String>,
+    min_value: Option<MinValue>,
+    tax: Tax,
+    server_clock: Option<ServerClock>,
+    extended_quotes: Option<ExtendedQuotes>,
+    extended_symbols: HashSet<String>,
+    mask_accounts: bool,
+    warnings: Warnings,
 }
 
-impl AssetTracker {
-    pub async fn new(questrade_api: QuestradeAPI) -> Result<Self, QuestradeAPIError> {
+/// Everything fetched from Questrade at startup, in a form that can be cached in the DB.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Dataset {
+    pub accounts: Vec<Account>,
+    pub positions: HashMap<AccountID, Vec<Position>>,
+    pub balances: HashMap<AccountID, Balances>,
+    pub symbols: HashMap<SymbolID, Symbol>,
+}
+
+impl Dataset {
+    /// Fetches every account's balances, positions and symbols, or just those of accounts of
+    /// `account_type`, waiting at least `pacing` between requests so large portfolios stay
+    /// under Questrade's rate limits. Accounts that fail to load are skipped, with a warning
+    /// to `warnings`, or abort the fetch depending on `policy`.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn fetch(
+        questrade_api: &QuestradeAPI,
+        pacing: Duration,
+        policy: ErrorPolicy,
+        cache: Option<SymbolCache<'_>>,
+        account_type: Option<&str>,
+        mask_accounts: bool,
+        warnings: &Warnings,
+    ) -> Result<Self, QuestradeAPIError> {
+        let started = Instant::now();
+        let pacer = Pacer::new(pacing);
+        let accounts = Self::fetch_accounts(questrade_api, &pacer, account_type).await?;
+        debug!(accounts = accounts.len(), "fetching accounts");
+        let results = future::join_all(accounts.into_iter().map(|account| async {
+            let account_id = account.id.clone();
+            let result =
+                Self::fetch_account(questrade_api, account, &pacer, mask_accounts, warnings).await;
+            (account_id, result)
+        }))
+        .await;
+
+        let mut dataset = Dataset::default();
+        for (account_id, result) in results {
+            match result {
+                Ok(account_dataset) => dataset.extend(account_dataset),
+                Err(err) if policy.skips(&err) => {
+                    warn_skipped_account(&account_id, &err, mask_accounts, warnings)
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        dataset
+            .fetch_symbols(questrade_api, &pacer, policy, cache, warnings)
+            .await?;
+        debug!(duration = ?started.elapsed(), "fetched dataset");
+        Ok(dataset)
+    }
+
+    /// Lists the accounts, leaving out ones not of `account_type` if it's given.
+    pub async fn fetch_accounts(
+        questrade_api: &QuestradeAPI,
+        pacer: &Pacer,
+        account_type: Option<&str>,
+    ) -> Result<Vec<Account>, QuestradeAPIError> {
+        pacer.wait().await;
         let resp = questrade_api
             .make_request(String::from("v1/accounts"))
             .await?;
-        let accounts = serde_json::from_str::<Accounts>(&resp)?.accounts;
-        let mut assets = Assets::new();
-        let mut balances = HashMap::new();
-        let mut positions = HashMap::new();
-        let mut symbols = HashMap::new();
-
-        for account in accounts.iter() {
-            let resp = questrade_api
-                .make_request(format!("v1/accounts/{}/balances", account.id))
-                .await?;
-            balances.insert(account.id.clone(), serde_json::from_str::<Balances>(&resp)?);
-
-            let resp = questrade_api
-                .make_request(format!("v1/accounts/{}/positions", account.id))
-                .await?;
-            let acct_positions = serde_json::from_str::<Positions>(&resp)?.positions;
-
-            for position in acct_positions.iter() {
-                let resp = questrade_api
-                    .make_request(format!("v1/symbols/{}", position.symbol_id))
-                    .await?;
-                let symbol = serde_json::from_str::<Symbols>(&resp)?;
 
-                if let Some(symbol) = symbol.symbols.first() {
-                    symbols.insert(symbol.symbol_id, symbol.clone());
+        let mut accounts = serde_json::from_str::<Accounts>(&resp)?.accounts;
+        if let Some(account_type) = account_type {
+            accounts.retain(|account| account.is_type(account_type));
+        }
+
+        Ok(accounts)
+    }
+
+    /// Drops every account not of `account_type`, along with its positions and balances.
+    pub fn retain_account_type(&mut self, account_type: &str) {
+        self.accounts
+            .retain(|account| account.is_type(account_type));
+
+        let kept: HashSet<_> = self
+            .accounts
+            .iter()
+            .map(|account| account.id.clone())
+            .collect();
+        self.positions
+            .retain(|account_id, _| kept.contains(account_id));
+        self.balances
+            .retain(|account_id, _| kept.contains(account_id));
+    }
+
+    /// Fetches a dataset holding just `accounts` and their balances, with no positions.
+    pub async fn fetch_balances(
+        questrade_api: &QuestradeAPI,
+        accounts: Vec<Account>,
+        pacer: &Pacer,
+    ) -> Result<Self, QuestradeAPIError> {
+        let balances = future::try_join_all(
+            accounts
+                .iter()
+                .map(|account| fetch_balances(questrade_api, &account.id, pacer)),
+        )
+        .await?;
+
+        let mut dataset = Dataset::default();
+        for (account, balances) in accounts.into_iter().zip(balances) {
+            dataset.balances.insert(account.id.clone(), balances);
+            dataset.accounts.push(account);
+        }
+
+        Ok(dataset)
+    }
+
+    /// Fetches a dataset holding just `account`'s balances and positions, without symbols.
+    #[instrument(
+        level = "debug",
+        skip_all,
+        fields(account = %display_account_id(&account.id, mask_accounts))
+    )]
+    pub async fn fetch_account(
+        questrade_api: &QuestradeAPI,
+        account: Account,
+        pacer: &Pacer,
+        mask_accounts: bool,
+        warnings: &Warnings,
+    ) -> Result<Self, QuestradeAPIError> {
+        let started = Instant::now();
+        let mut dataset = Dataset::default();
+
+        let (balances, acct_positions) = tokio::try_join!(
+            fetch_balances(questrade_api, &account.id, pacer),
+            fetch_positions(questrade_api, &account.id, pacer, mask_accounts, warnings)
+        )?;
+        dataset.balances.insert(account.id.clone(), balances);
+        dataset.positions.insert(account.id.clone(), acct_positions);
+        dataset.accounts.push(account);
+        debug!(duration = ?started.elapsed(), "fetched account");
+
+        Ok(dataset)
+    }
+
+    /// Looks up every distinct symbol held across the dataset's positions, in batches of
+    /// `SYMBOLS_PER_REQUEST`. Symbols fresh in `cache` aren't fetched again, and the rest are
+    /// saved to it. Batches that fail to load are left out or abort the fetch depending on
+    /// `policy`.
+    #[instrument(level = "debug", skip_all)]
+    pub async fn fetch_symbols(
+        &mut self,
+        questrade_api: &QuestradeAPI,
+        pacer: &Pacer,
+        policy: ErrorPolicy,
+        cache: Option<SymbolCache<'_>>,
+        warnings: &Warnings,
+    ) -> Result<(), QuestradeAPIError> {
+        let mut symbol_ids: Vec<SymbolID> = self
+            .positions
+            .values()
+            .flatten()
+            .map(|position| position.symbol_id)
+            .collect();
+        symbol_ids.sort_unstable();
+        symbol_ids.dedup();
+
+        if let Some(cache) = cache {
+            // The cache only saves requests, so if it can't be read every symbol is fetched.
+            let mut cached = match cache.db.get_cached_symbols(cache.fresh_since()).await {
+                Ok(cached) => cached,
+                Err(err) => {
+                    warnings.warn(format!("Error reading cached symbol details: {}", err));
+                    HashMap::new()
+                }
+            };
+            symbol_ids.retain(|id| match cached.remove(id) {
+                Some(symbol) => {
+                    self.symbols.insert(*id, symbol);
+                    false
+                }
+                None => true,
+            });
+        }
+
+        debug!(
+            cached = self.symbols.len(),
+            fetching = symbol_ids.len(),
+            "looking up symbols"
+        );
+        let batches = future::join_all(symbol_ids.chunks(SYMBOLS_PER_REQUEST).map(|ids| async {
+            pacer.wait().await;
+            fetch_symbols(questrade_api, ids).await
+        }))
+        .await;
+
+        for batch in batches {
+            match batch {
+                Ok(symbols) => {
+                    for symbol in symbols.symbols {
+                        self.symbols.insert(symbol.symbol_id, symbol);
+                    }
+                }
+                Err(err) if policy.skips(&err) => {
+                    let warning = format!("Skipping some symbol details: {}", err);
+                    warnings.warn(warning.yellow());
                 }
+                Err(err) => return Err(err),
             }
+        }
 
-            assets.add_positions(&acct_positions);
-            positions.insert(account.id.clone(), acct_positions);
+        if let Some(cache) = cache {
+            let fetched: Vec<_> = symbol_ids
+                .iter()
+                .filter_map(|id| self.symbols.get(id))
+                .collect();
+            if let Err(err) = cache.db.upsert_cached_symbols(&fetched).await {
+                warnings.warn(format!("Error caching symbol details: {}", err));
+            }
         }
 
-        Ok(Self {
-            questrade_api,
-            accounts,
-            assets,
-            positions,
-            balances,
-            symbols,
-        })
+        Ok(())
     }
 
-    pub fn display_accounts(&self) {
-        for account in self.accounts.iter() {
-            println!("{}", account);
+    /// Replaces each position's price with the one from `source`, returning the symbols that
+    /// were updated. Symbols the source can't price keep their Questrade price.
+    pub async fn apply_extended_quotes(
+        &mut self,
+        source: &ExtendedQuotes,
+        warnings: &Warnings,
+    ) -> HashSet<String> {
+        let client = reqwest::Client::new();
+        let symbols: HashSet<String> = self
+            .positions
+            .values()
+            .flatten()
+            .map(|position| position.symbol.clone())
+            .collect();
 
-            if let Some(balances) = self.balances.get(&account.id) {
-                balances.display_balances();
-            } else {
-                println!("No balances")
+        let mut prices = HashMap::new();
+        for symbol in symbols {
+            match fetch_extended_quote(&client, source, &symbol).await {
+                Ok(price) => {
+                    prices.insert(symbol, price);
+                }
+                Err(err) => warnings.warn(format!(
+                    "Error fetching extended-hours quote for {}: {}",
+                    symbol, err
+                )),
             }
         }
+
+        for position in self.positions.values_mut().flatten() {
+            if let Some(&price) = prices.get(&position.symbol) {
+                position.current_price = price;
+                position.current_market_value = price * position.open_quantity;
+                position.open_pnl = position.current_market_value - position.total_cost;
+            }
+        }
+
+        prices.into_keys().collect()
     }
 
-    pub fn display_home(&self) {
+    fn extend(&mut self, other: Dataset) {
+        self.accounts.extend(other.accounts);
+        self.positions.extend(other.positions);
+        self.balances.extend(other.balances);
+        self.symbols.extend(other.symbols);
+    }
+
+    /// Adds every account's positions to `assets`, converting holdings in the other currency
+    /// to `currency`.
+    pub fn add_positions_to(&self, assets: &mut Assets, currency: &str, warnings: &Warnings) {
+        let usd_to_cad = self.usd_to_cad();
+        let other = if currency == "USD" { "CAD" } else { "USD" };
+        if usd_to_cad.is_none() && self.holds_currency(other) {
+            let warning = format!(
+                "No USD/CAD rate in the balances; counting {} holdings as {}",
+                other, currency
+            );
+            warnings.warn(warning.yellow());
+        }
+
+        let rate = conversion_rate(&self.symbols, usd_to_cad, currency);
         for account in self.accounts.iter() {
-            println!("{}", account);
+            if let Some(acct_positions) = self.positions.get(&account.id) {
+                assets.add_positions(acct_positions, &rate);
+            }
+        }
+    }
 
-            if let Some(balances) = self.balances.get(&account.id) {
-                balances.display_balances();
-            } else {
-                println!("No balances")
+    /// The cash sitting uninvested across the accounts, in `currency`.
+    pub fn cash(&self, currency: &str) -> f64 {
+        self.balances
+            .values()
+            .map(|balances| cash_in(balances, currency))
+            .sum()
+    }
+
+    fn usd_to_cad(&self) -> Option<f64> {
+        usd_to_cad(self.balances.values())
+    }
+
+    fn holds_currency(&self, currency: &str) -> bool {
+        self.positions.values().flatten().any(|position| {
+            self.symbols
+                .get(&position.symbol_id)
+                .is_some_and(|symbol| symbol.currency == currency)
+        })
+    }
+
+    pub fn total_cost(&self) -> f64 {
+        self.positions
+            .values()
+            .flatten()
+            .map(|p| p.total_cost)
+            .sum()
+    }
+
+    pub fn total_market_value(&self) -> f64 {
+        self.positions
+            .values()
+            .flatten()
+            .map(|p| p.current_market_value)
+            .sum()
+    }
+}
+
+impl AssetTracker {
+    /// Builds the tracker from an already-loaded dataset. `questrade_api` is `None` when
+    /// the dataset came from the DB cache, in which case commands that need live data fail.
+    pub async fn new(
+        db: DatabaseAPI,
+        questrade_api: Option<QuestradeAPI>,
+        dataset: Dataset,
+        config: &Config,
+    ) -> Result<Self, QuestradeAPIError> {
+        let server_clock = match questrade_api.as_ref() {
+            Some(api) => api.get_server_time().await.ok().map(ServerClock::new),
+            None => None,
+        };
+
+        let notes = db
+            .get_notes()
+            .await?
+            .into_iter()
+            .map(|note| (note.symbol, note.note))
+            .collect();
+
+        let price_history = db.get_price_history().await?;
+        let asset_classes = asset_classes(&db, config).await?;
+
+        let previous_balances = match db.get_previous_snapshot().await? {
+            Some(snapshot) => snapshot
+                .dataset()
+                .map(|dataset| dataset.balances)
+                .unwrap_or_default(),
+            None => HashMap::new(),
+        };
+
+        let mut tracker = Self {
+            db,
+            questrade_api,
+            accounts: Vec::new(),
+            assets: Assets::new(
+                config.targets.clone(),
+                config.columns_width,
+                asset_classes.clone(),
+            )
+            .hiding_below(config.hide_below(None)),
+            positions: HashMap::new(),
+            balances: HashMap::new(),
+            previous_balances,
+            symbols: HashMap::new(),
+            notes,
+            price_history,
+            targets: config.targets.clone(),
+            asset_classes,
+            layout: config.columns_width,
+            display_currency: config.display_currency.clone(),
+            request_pacing: config.request_pacing(),
+            request_retries: config.request_retries,
+            symbol_cache_max_age: config.symbol_cache_max_age(),
+            error_policy: config.error_policy,
+            account_type: config.account_type.clone(),
+            min_value: config.hide_below(None),
+            tax: config.tax,
+            server_clock,
+            extended_quotes: config.extended_quotes.clone(),
+            extended_symbols: HashSet::new(),
+            unresolved_symbols: HashSet::new(),
+            mask_accounts: config.mask_accounts,
+            warnings: Warnings::default(),
+        };
+        tracker.load(dataset).await;
+
+        Ok(tracker)
+    }
+
+    /// Replaces the tracked accounts, positions, balances and symbols with `dataset`.
+    async fn load(&mut self, mut dataset: Dataset) {
+        self.extended_symbols = match (&self.extended_quotes, &self.server_clock) {
+            (Some(source), Some(clock)) if !is_market_open(clock.now()) => {
+                dataset.apply_extended_quotes(source, &self.warnings).await
             }
+            _ => HashSet::new(),
+        };
 
-            if let Some(_) = self.positions.get(&account.id) {
-                self.display_positions_with_dividends(Some(&account.id));
-            } else {
-                println!("No positions")
+        let mut assets = Assets::new(
+            self.targets.clone(),
+            self.layout,
+            self.asset_classes.clone(),
+        )
+        .hiding_below(self.min_value);
+        dataset.add_positions_to(&mut assets, &self.display_currency, &self.warnings);
+        assets.add_cash(dataset.cash(&self.display_currency));
+
+        self.unresolved_symbols = dataset
+            .positions
+            .values()
+            .flatten()
+            .map(|position| position.symbol_id)
+            .filter(|symbol_id| !dataset.symbols.contains_key(symbol_id))
+            .collect();
+
+        self.assets = assets;
+        self.accounts = dataset.accounts;
+        self.positions = dataset.positions;
+        self.balances = dataset.balances;
+        self.symbols = dataset.symbols;
+    }
+
+    /// Re-fetches everything from Questrade and snapshots it, keeping the data being replaced
+    /// as the previous balances to compare against.
+    pub async fn reload(&mut self) -> Result<(), QuestradeAPIError> {
+        let dataset = Dataset::fetch(
+            self.questrade_api()?,
+            self.request_pacing,
+            self.error_policy,
+            Some(SymbolCache::new(&self.db, self.symbol_cache_max_age)),
+            self.account_type.as_deref(),
+            self.mask_accounts,
+            &self.warnings,
+        )
+        .await?;
+
+        // A snapshot of some of the accounts would throw off the history and cached totals.
+        if self.account_type.is_none() {
+            if let Err(err) = self.db.insert_snapshot(&dataset).await {
+                self.warnings
+                    .warn(format!("Error caching dataset: {}", err));
             }
         }
+        if let Err(err) = self.db.append_prices(&dataset).await {
+            self.warnings
+                .warn(format!("Error recording price history: {}", err));
+        }
+        self.price_history = self.db.get_price_history().await?;
+
+        self.previous_balances = std::mem::take(&mut self.balances);
+        self.load(dataset).await;
+
+        Ok(())
+    }
+
+    /// Holds the warnings hit while loading for `take_warnings` instead of printing them, or
+    /// goes back to printing them.
+    pub fn collect_warnings(&mut self, collect: bool) {
+        self.warnings = if collect {
+            Warnings::collected()
+        } else {
+            Warnings::default()
+        };
+    }
 
-        self.display_summary();
+    /// Removes and returns the warnings collected since the last call.
+    pub fn take_warnings(&self) -> Vec<String> {
+        self.warnings.take()
     }
 
-    pub fn display_positions_with_dividends(&self, account_id: Option<&str>) {
-        let title = format!("{}Positions{}", "-".repeat(60), "-".repeat(60));
-        println!("{}", title.cyan());
+    /// Closes the database once any pending writes are done.
+    pub async fn close(self) {
+        self.db.close().await;
+    }
+
+    pub async fn refresh(&mut self) -> Result<(), CommandError> {
+        let start = Instant::now();
+
+        self.reload()
+            .await
+            .map_err(|err| command_error("Error refreshing", err))?;
+        println!("Refreshed in {:.1}s", start.elapsed().as_secs_f64());
         println!();
-        println!(
-            "{:<10} | {:<10} | {:<10} | {:<15} | {:<15} | {:<15} | {:<10} | {:<10} | {:>10}",
-            "Symbol",
-            "Quantity",
-            "Avg Price",
-            "Book Cost",
-            "Market Price",
-            "Market Value",
-            "Dividend",
-            "Yield",
-            "P&L"
-        );
-        println!("{}", "-".repeat(129));
+        Ok(())
+    }
+
+    /// Fetches, displays and snapshots one account at a time, dropping each account's data
+    /// before moving to the next so peak memory stays at a single account's worth. Nothing is
+    /// snapshotted when only one account type is shown.
+    pub async fn display_streamed(
+        db: &DatabaseAPI,
+        questrade_api: &QuestradeAPI,
+        config: &Config,
+    ) -> Result<(), LoadError> {
+        let pacer = Pacer::new(config.request_pacing());
+        let mut assets = Assets::new(
+            config.targets.clone(),
+            config.columns_width,
+            asset_classes(db, config).await?,
+        )
+        .hiding_below(config.hide_below(None));
+        let account_type = config.account_type.as_deref();
+        let snapshot_id = match account_type {
+            Some(_) => None,
+            None => Some(db.begin_snapshot().await?),
+        };
 
-        let mut total_cost = 0.0;
-        let mut total_mkt_val = 0.0;
+        // A snapshot that's missing accounts would throw off the history, so it's deleted
+        // rather than finished if any account fails or is skipped.
+        let streamed: Result<(f64, usize), LoadError> = async {
+            let mut cash = 0.0;
+            let mut skipped = 0;
+            for account in Dataset::fetch_accounts(questrade_api, &pacer, account_type).await? {
+                let account_id = account.id.clone();
+                let fetched = Dataset::fetch_account(
+                    questrade_api,
+                    account,
+                    &pacer,
+                    config.mask_accounts,
+                    &Warnings::default(),
+                )
+                .await;
+                let mut dataset = match fetched {
+                    Ok(dataset) => dataset,
+                    Err(err) if config.error_policy.skips(&err) => {
+                        warn_skipped_account(
+                            &account_id,
+                            &err,
+                            config.mask_accounts,
+                            &Warnings::default(),
+                        );
+                        skipped += 1;
+                        continue;
+                    }
+                    Err(err) => return Err(err.into()),
+                };
+                check_base_currency(dataset.balances.values(), &config.display_currency)
+                    .map_err(LoadError::BaseCurrency)?;
+                dataset
+                    .fetch_symbols(
+                        questrade_api,
+                        &pacer,
+                        config.error_policy,
+                        Some(SymbolCache::new(db, config.symbol_cache_max_age())),
+                        &Warnings::default(),
+                    )
+                    .await?;
+                if let Some(snapshot_id) = snapshot_id {
+                    db.insert_position_snapshots(snapshot_id, &dataset.positions, &dataset.symbols)
+                        .await?;
+                    db.insert_balance_snapshots(snapshot_id, &dataset.balances)
+                        .await?;
+                }
+                dataset.add_positions_to(
+                    &mut assets,
+                    &config.display_currency,
+                    &Warnings::default(),
+                );
+                cash += dataset.cash(&config.display_currency);
+
+                let tracker = AssetTracker::new(db.clone(), None, dataset, config).await?;
+                tracker.display_home_accounts(&mut io::stdout().lock())?;
+            }
+
+            Ok((cash, skipped))
+        }
+        .await;
 
-        let positions = match account_id {
-            Some(account_id) => self.positions.get(account_id).unwrap(),
-            None => &self.positions.values().flatten().cloned().collect(),
+        let (cash, skipped) = match streamed {
+            Ok(totals) => totals,
+            Err(err) => {
+                if let Some(snapshot_id) = snapshot_id {
+                    if let Err(err) = db.delete_snapshot(snapshot_id).await {
+                        eprintln!("Error deleting unfinished snapshot: {}", err);
+                    }
+                }
+                return Err(err);
+            }
         };
+        match snapshot_id {
+            Some(snapshot_id) if skipped > 0 => {
+                let warning = format!(
+                    "Not saving a snapshot, since {} account(s) were skipped",
+                    skipped
+                );
+                eprintln!("{}", warning.yellow());
+                db.delete_snapshot(snapshot_id).await?;
+            }
+            Some(snapshot_id) => {
+                db.finish_snapshot(
+                    snapshot_id,
+                    assets.total_costs(),
+                    assets.total_market_values(),
+                )
+                .await?
+            }
+            None => {}
+        }
+        // Added after the snapshot so its totals cover positions only, like other snapshots.
+        assets.add_cash(cash);
+        println!("{}", assets);
 
-        for position in positions {
-            let (dividend, yield_) = if let Some(symbol) = self.symbols.get(&position.symbol_id) {
-                (symbol.dividend, symbol.yield_)
-            } else {
-                (0.0, 0.0)
-            };
+        Ok(())
+    }
 
-            let quantity = if position.closed_quantity == 0.0 {
-                position.open_quantity
-            } else {
-                position.closed_quantity
-            };
+    /// Shows live balances, which are cheap to fetch, alongside the allocation from the
+    /// positions stored in the most recent snapshot.
+    pub async fn display_accounts_only_summary(
+        db: &DatabaseAPI,
+        questrade_api: &QuestradeAPI,
+        config: &Config,
+    ) -> Result<(), LoadError> {
+        let pacer = Pacer::new(config.request_pacing());
+        let account_type = config.account_type.as_deref();
+        let accounts = Dataset::fetch_accounts(questrade_api, &pacer, account_type).await?;
+        let mut dataset = Dataset::fetch_balances(questrade_api, accounts, &pacer).await?;
 
-            let pnl = if position.closed_pnl == 0.0 {
-                position.open_pnl
-            } else {
-                position.closed_pnl
-            };
+        let snapshot = db.get_latest_position_snapshot().await?;
+        if let Some(snapshot) = snapshot.as_ref() {
+            dataset.positions = db.reconstruct_positions(snapshot.id).await?;
+        }
+        if let Some(account_type) = account_type {
+            dataset.retain_account_type(account_type);
+        }
 
-            total_cost += position.total_cost;
-            total_mkt_val += position.current_market_value;
+        let tracker = AssetTracker::new(db.clone(), None, dataset, config).await?;
+        tracker
+            .check_base_currency()
+            .map_err(LoadError::BaseCurrency)?;
+        tracker.display_accounts(&mut io::stdout().lock(), None)?;
 
-            println!(
-                "{:<10} | {:<10} | {:<10.2} | {:<15.2} | {:<15.2} | {:<15.2} | {:<10.4} | {:<10.2} | {:>10}",
-                position.symbol, quantity, position.average_entry_price, position.total_cost, position.current_price, position.current_market_value, dividend, yield_, self.colour_pnl(pnl)
-            );
+        match snapshot {
+            Some(snapshot) => {
+                let taken_at = snapshot.taken_at.with_timezone(&Local);
+                println!(
+                    "{}",
+                    format!(
+                        "Allocation as of snapshot from {}",
+                        taken_at.format("%Y-%m-%d %H:%M")
+                    )
+                    .blue()
+                );
+                tracker.display_summary(&mut io::stdout().lock())?;
+            }
+            None => println!(
+                "No snapshot with positions yet, run without --accounts-only-summary first"
+            ),
         }
 
-        println!("{}", "=".repeat(129));
-        println!(
-            "{:<10} | {:<10} | {:<10} | {:<15.2} | {:<15} | {:<15.2} | {:<10} | {:<10} | {:>10}",
-            "Total",
-            "",
-            "",
-            total_cost,
-            "",
-            total_mkt_val,
-            "",
-            "",
-            self.colour_pnl(total_mkt_val - total_cost)
+        Ok(())
+    }
+
+    /// Converts positions to the base currency by their symbol's currency.
+    fn conversion_rate(&self) -> impl Fn(&Position) -> f64 + '_ {
+        conversion_rate(
+            &self.symbols,
+            usd_to_cad(self.balances.values()),
+            &self.display_currency,
+        )
+    }
+
+    /// Checks Questrade reported combined balances in the base currency, since everything is
+    /// totalled in it.
+    pub fn check_base_currency(&self) -> Result<(), String> {
+        check_base_currency(self.balances.values(), &self.display_currency)
+    }
+
+    fn questrade_api(&self) -> Result<&QuestradeAPI, QuestradeAPIError> {
+        self.questrade_api.as_ref().ok_or_else(|| {
+            QuestradeAPIError::APIError(String::from(
+                "not connected to Questrade while showing cached data; rerun with --fresh",
+            ))
+        })
+    }
+
+    /// The asset class breakdown of one account's positions and cash, converted to the base
+    /// currency.
+    fn account_assets(&self, account_id: &str) -> Assets {
+        let mut assets = Assets::new(
+            self.targets.clone(),
+            self.layout,
+            self.asset_classes.clone(),
         );
-        println!();
+        if let Some(positions) = self.positions.get(account_id) {
+            assets.add_positions(positions, self.conversion_rate());
+        }
+        if let Some(balances) = self.balances.get(account_id) {
+            assets.add_cash(cash_in(balances, &self.display_currency));
+        }
+        assets
     }
 
-    pub fn display_summary(&self) {
-        println!("{}", self.assets);
+    /// Shows the portfolio-wide asset class breakdown, or each account's own if `by_account`.
+    pub fn display_allocation(&self, by_account: bool) {
+        if !by_account {
+            self.assets.display_allocation();
+            println!();
+            return;
+        }
+
+        for account in self.accounts.iter() {
+            println!("{}", account.title(self.mask_accounts));
+            self.account_assets(&account.id).display_allocation();
+            println!();
+        }
     }
 
-    fn colour_pnl(&self, pnl: f64) -> ColoredString {
-        let pnl = (pnl * 100.0).round() / 100.0;
+    /// Shows each account's balances, or just those of accounts of `account_type`.
+    pub fn display_accounts(
+        &self,
+        out: &mut impl Write,
+        account_type: Option<&str>,
+    ) -> io::Result<()> {
+        let accounts: Vec<_> = self
+            .accounts
+            .iter()
+            .filter(|account| account_type.is_none_or(|account_type| account.is_type(account_type)))
+            .collect();
+        if let (Some(account_type), true) = (account_type, accounts.is_empty()) {
+            writeln!(out, "No {} accounts", account_type)?;
+            return Ok(());
+        }
+
+        for account in accounts {
+            writeln!(out, "{}", account.title(self.mask_accounts))?;
 
-        match 0.0.partial_cmp(&pnl).unwrap() {
-            std::cmp::Ordering::Less => pnl.to_string().green(),
-            std::cmp::Ordering::Equal => pnl.to_string().normal(),
-            std::cmp::Ordering::Greater => pnl.to_string().red(),
+            if let Some(balances) = self.balances.get(&account.id) {
+                balances.display_balances(
+                    out,
+                    &self.display_currency,
+                    self.previous_balances.get(&account.id),
+                )?;
+            } else {
+                writeln!(out, "No balances")?;
+            }
         }
+
+        Ok(())
     }
-}
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Accounts {
-    accounts: Vec<Account>,
-}
+    /// Notes whether prices are live or from the last close. Shows nothing for cached data,
+    /// since the server time is only known with a live connection.
+    pub fn display_market_status(&self, out: &mut impl Write) -> io::Result<()> {
+        let Some(server_clock) = self.server_clock.as_ref() else {
+            return Ok(());
+        };
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Account {
-    pub type_: String,
+        if is_market_open(server_clock.now()) {
+            writeln!(out, "{}", "Market open".green())?;
+        } else if !self.extended_symbols.is_empty() {
+            writeln!(
+                out,
+                "{}",
+                "Market closed — prices marked (ext) are extended-hours quotes".yellow()
+            )?;
+        } else {
+            writeln!(out, "{}", "Market closed — showing last close".yellow())?;
+        }
 
-    #[serde(rename = "number")]
-    pub id: AccountID,
-}
+        Ok(())
+    }
 
-impl std::fmt::Display for Account {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let account_title = format!("Account: {} — {}", self.type_, self.id);
-        write!(f, "{}", account_title.blue())
+    /// Whether the market is open right now, `None` for cached data without a server clock.
+    pub fn is_market_open(&self) -> Option<bool> {
+        self.server_clock
+            .as_ref()
+            .map(|server_clock| is_market_open(server_clock.now()))
     }
-}
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Balances {
-    per_currency_balances: Vec<Balance>,
-    combined_balances: Vec<Balance>,
-}
+    pub fn display_home(&self, out: &mut impl Write) -> io::Result<()> {
+        self.display_market_status(out)?;
+        self.display_home_accounts(out)?;
+        self.display_summary(out)
+    }
 
-impl Balances {
-    pub fn display_balances(&self) {
-        println!(
-            "{:<10} | {:<10} | {:<15} | {:>15}",
-            "Currency", "Cash", "Market Equity", "Total Equity"
-        );
-        println!("{}", "-".repeat(59));
-        for balance in self.per_currency_balances.iter() {
-            println!(
-                "{:<10} | {:<10.2} | {:<15.2} | {:>15.2}",
-                balance.currency, balance.cash, balance.market_value, balance.total_equity
-            );
+    fn display_home_accounts(&self, out: &mut impl Write) -> io::Result<()> {
+        for account in self.accounts.iter() {
+            writeln!(out, "{}", account.title(self.mask_accounts))?;
+
+            if let Some(balances) = self.balances.get(&account.id) {
+                balances.display_balances(
+                    out,
+                    &self.display_currency,
+                    self.previous_balances.get(&account.id),
+                )?;
+            } else {
+                writeln!(out, "No balances")?;
+            }
+
+            self.display_positions_with_dividends(
+                out,
+                PositionFilter::Account(&account.id),
+                false,
+                None,
+                None,
+            )?;
         }
 
-        println!("{}", "=".repeat(59));
-        self.combined_balances
-            .iter()
-            .find(|balance| balance.currency == "CAD")
-            .map(|balance| {
-                println!(
-                    "{:<10} | {:<10.2} | {:<15.2} | {:>15.2}",
-                    "Combined", balance.cash, balance.market_value, balance.total_equity
-                );
-            });
+        Ok(())
+    }
 
-        println!();
+    /// The positions `filter` selects, merged by symbol if `consolidate`, or `None` if it
+    /// matches nothing.
+    fn filtered_positions(
+        &self,
+        filter: PositionFilter,
+        consolidate: bool,
+    ) -> Option<Vec<Position>> {
+        let all_positions = || self.positions.values().flatten();
+        let positions: Vec<_> = match filter {
+            PositionFilter::All => all_positions().cloned().collect(),
+            PositionFilter::Account(account_id) => self.positions.get(account_id)?.clone(),
+            PositionFilter::Symbol(symbol) => {
+                let matching: Vec<_> = all_positions()
+                    .filter(|position| position.symbol.eq_ignore_ascii_case(symbol))
+                    .cloned()
+                    .collect();
+                if matching.is_empty() {
+                    return None;
+                }
+                matching
+            }
+        };
+
+        Some(if consolidate {
+            consolidate_positions(&positions)
+        } else {
+            positions
+        })
     }
-}
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Balance {
-    pub currency: String,
-    pub cash: f64,
-    pub market_value: f64,
-    pub total_equity: f64,
-}
+    /// The shares still held, leaving out positions worth less than `min_value`, or
+    /// `--min-value` if it's not given. Hidden positions still count towards the totals
+    /// unless `min_value` excludes them.
+    pub fn positions_data(
+        &self,
+        filter: PositionFilter,
+        consolidate: bool,
+        min_value: Option<MinValue>,
+    ) -> Option<PositionsData> {
+        let mut data = self.all_positions_data(filter, consolidate)?;
+        if let Some(min_value) = min_value.or(self.min_value) {
+            data.hide_below(min_value);
+        }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Positions {
-    positions: Vec<Position>,
-}
+        Some(data)
+    }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
-pub struct Position {
-    pub symbol: String,
-    pub symbol_id: SymbolID,
-    pub open_quantity: f64,
-    pub closed_quantity: f64,
-    pub current_market_value: f64,
-    pub current_price: f64,
-    pub average_entry_price: f64,
-    pub closed_pnl: f64,
-    pub open_pnl: f64,
-    pub total_cost: f64,
-}
+    /// The shares still held: quantity, book cost, market value and P&L all cover the open
+    /// quantity only, as Questrade reports them, so the totals match the balances. P&L
+    /// realized on shares sold today is reported separately.
+    fn all_positions_data(
+        &self,
+        filter: PositionFilter,
+        consolidate: bool,
+    ) -> Option<PositionsData> {
+        let positions = self.filtered_positions(filter, consolidate)?;
+        let mut data = PositionsData::default();
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Symbols {
-    symbols: Vec<Symbol>,
-}
+        for position in positions {
+            let symbol = self.symbols.get(&position.symbol_id);
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
-pub struct Symbol {
-    pub symbol: String,
-    pub symbol_id: SymbolID,
-    pub dividend: f64,
-    pub yield_: f64,
+            data.total_book_cost += position.total_cost;
+            data.total_market_value += position.current_market_value;
+            data.realized_pnl += position.closed_pnl;
+            data.positions.push(PositionData {
+                currency: symbol
+                    .map(|symbol| symbol.currency.clone())
+                    .unwrap_or_default(),
+                quantity: position.open_quantity,
+                avg_price: position.average_entry_price,
+                book_cost: position.total_cost,
+                market_price: position.current_price,
+                extended_hours: self.extended_symbols.contains(&position.symbol),
+                day_change_percent: day_change_percent(
+                    position.current_price,
+                    symbol.and_then(|symbol| symbol.prev_day_close_price),
+                ),
+                market_value: position.current_market_value,
+                dividend: symbol.and_then(|symbol| symbol.dividend),
+                yield_: symbol.and_then(|symbol| symbol.yield_),
+                pnl: position.open_pnl,
+                return_percent: return_percent(position.current_market_value, position.total_cost),
+                note: self.notes.get(&position.symbol).cloned(),
+                symbol_missing: self.unresolved_symbols.contains(&position.symbol_id),
+                symbol: position.symbol,
+            });
+        }
+        data.total_pnl = data.total_market_value - data.total_book_cost;
+        data.total_return_percent = return_percent(data.total_market_value, data.total_book_cost);
+
+        Some(data)
+    }
+
+    /// Fetches live quotes for every held symbol and works out each one's percent change from
+    /// the previous close, keyed by ticker. Symbols whose quote has no usable prices are left
+    /// out.
+    pub async fn fetch_day_changes(&self) -> Result<HashMap<String, f64>, QuestradeAPIError> {
+        let questrade_api = self.questrade_api()?;
+        let mut symbol_ids: Vec<_> = self
+            .positions
+            .values()
+            .flatten()
+            .map(|position| position.symbol_id)
+            .collect();
+        symbol_ids.sort_unstable();
+        symbol_ids.dedup();
+
+        let pacer = Pacer::new(self.request_pacing);
+        let mut day_changes = HashMap::new();
+        for ids in symbol_ids.chunks(SYMBOLS_PER_REQUEST) {
+            pacer.wait().await;
+            for quote in fetch_quotes(questrade_api, ids).await? {
+                if let Some(change) = quote_day_change(&quote) {
+                    day_changes.insert(quote.symbol, change);
+                }
+            }
+        }
+
+        Ok(day_changes)
+    }
+
+    /// Prints the positions `filter` selects, leaving out ones worth less than `min_value` in
+    /// their own currency, or `--min-value` if it's not given. With `day_changes` from
+    /// `fetch_day_changes`, the change column shows each symbol's live move today instead of
+    /// the one as of the last fetch.
+    pub fn display_positions_with_dividends(
+        &self,
+        out: &mut impl Write,
+        filter: PositionFilter,
+        consolidate: bool,
+        min_value: Option<MinValue>,
+        day_changes: Option<&HashMap<String, f64>>,
+    ) -> io::Result<()> {
+        let Some(mut data) = self.all_positions_data(filter, consolidate) else {
+            match filter {
+                PositionFilter::Symbol(symbol) => {
+                    writeln!(
+                        out,
+                        "No positions in {} in any account",
+                        symbol.to_uppercase()
+                    )?;
+                    writeln!(out)?;
+                }
+                _ => writeln!(out, "No positions")?,
+            }
+            return Ok(());
+        };
+        if let Some(day_changes) = day_changes {
+            for position in data.positions.iter_mut() {
+                position.day_change_percent = day_changes.get(&position.symbol).copied();
+            }
+        }
+
+        let table = Table::new(self.layout)
+            .title("Positions")
+            .column("Symbol", assets::SYMBOL_WIDTH, Align::Left)
+            .column("Currency", 8, Align::Left)
+            .optional()
+            .column("Quantity", 10, Align::Left)
+            .column("Avg Price", 10, Align::Left)
+            .optional()
+            .column("Book Cost", 15, Align::Left)
+            .column("Market Price", 15, Align::Left);
+        let mut table = match day_changes {
+            Some(_) => table.column("Live Chg %", 10, Align::Right),
+            None => table.column("Chg %", 8, Align::Right),
+        }
+        .optional()
+        .column("Market Value", 15, Align::Left)
+        .column("Dividend", 10, Align::Left)
+        .optional()
+        .column("Yield", 10, Align::Left)
+        .optional()
+        .column("P&L", 10, Align::Right)
+        .column("Return %", 9, Align::Right)
+        .column("Trend", 10, Align::Left)
+        .optional();
+
+        let min_value = min_value.or(self.min_value);
+        let hidden = match min_value {
+            Some(min_value) => data.hide_below(min_value),
+            None => Vec::new(),
+        };
+        for position in data.positions.iter() {
+            let marker = if position.note.is_some() { "*" } else { "" };
+            let symbol = match self.layout {
+                Layout::Fixed => {
+                    truncate_symbol(&position.symbol, assets::SYMBOL_WIDTH - marker.len())
+                }
+                Layout::Auto => position.symbol.clone(),
+            } + marker;
+
+            let trend = match self.price_history.get(&position.symbol) {
+                Some(prices) => sparkline(prices),
+                None => String::new(),
+            };
+
+            table.row(vec![
+                symbol.normal(),
+                match position.currency.as_str() {
+                    "USD" => position.currency.yellow(),
+                    _ => position.currency.normal(),
+                },
+                position.quantity.to_string().normal(),
+                format!("{:.2}", position.avg_price).normal(),
+                format!("{:.2}", position.book_cost).normal(),
+                if position.extended_hours {
+                    format!("{:.2} (ext)", position.market_price).yellow()
+                } else {
+                    format!("{:.2}", position.market_price).normal()
+                },
+                colour_change(position.day_change_percent),
+                format!("{:.2}", position.market_value).normal(),
+                if position.symbol_missing {
+                    "n/a".yellow()
+                } else {
+                    format_optional(position.dividend, 4).normal()
+                },
+                if position.symbol_missing {
+                    "n/a".yellow()
+                } else {
+                    format_optional(position.yield_, 2).normal()
+                },
+                colour_pnl(position.pnl),
+                colour_change(position.return_percent),
+                trend.normal(),
+            ]);
+        }
+        if !hidden.is_empty() && min_value.is_some_and(|min_value| !min_value.exclude_from_totals) {
+            let book_cost: f64 = hidden.iter().map(|position| position.book_cost).sum();
+            let market_value: f64 = hidden.iter().map(|position| position.market_value).sum();
+            table.row(vec![
+                format!("{} hidden", hidden.len()).dimmed(),
+                "".normal(),
+                "".normal(),
+                "".normal(),
+                format!("{:.2}", book_cost).dimmed(),
+                "".normal(),
+                "".normal(),
+                format!("{:.2}", market_value).dimmed(),
+                "".normal(),
+                "".normal(),
+                format!("{:.2}", market_value - book_cost).dimmed(),
+            ]);
+        }
+
+        table.rule();
+        table.row(vec![
+            "Total".normal(),
+            "".normal(),
+            "".normal(),
+            "".normal(),
+            format!("{:.2}", data.total_book_cost).normal(),
+            "".normal(),
+            "".normal(),
+            format!("{:.2}", data.total_market_value).normal(),
+            "".normal(),
+            "".normal(),
+            colour_pnl(data.total_pnl),
+            colour_change(data.total_return_percent),
+        ]);
+        write!(out, "{}", table)?;
+
+        if data.realized_pnl != 0.0 {
+            writeln!(
+                out,
+                "Realized P&L on shares sold today: {}",
+                colour_pnl(data.realized_pnl)
+            )?;
+        }
+        if data
+            .positions
+            .iter()
+            .any(|position| position.note.is_some())
+        {
+            writeln!(out, "* has a note, see `notes`")?;
+        }
+        let missing: Vec<_> = data
+            .positions
+            .iter()
+            .filter(|position| position.symbol_missing)
+            .map(|position| position.symbol.as_str())
+            .collect();
+        if !missing.is_empty() {
+            let note = format!(
+                "n/a: couldn't look up {}, so its dividend and yield are unknown",
+                missing.join(", ")
+            );
+            writeln!(out, "{}", note.yellow())?;
+        }
+        writeln!(out)?;
+
+        Ok(())
+    }
+
+    /// Estimates the capital gains tax owed if each taxable account's unrealized gains were
+    /// realized today. Registered accounts are skipped since their gains aren't taxed this way.
+    pub fn display_after_tax_estimate(&self) {
+        let Some(marginal_rate) = self.tax.marginal_rate else {
+            println!("Set marginal_rate under [tax] in the config file to estimate after-tax P&L");
+            println!();
+            return;
+        };
+        let rate = self.tax.inclusion_rate / 100.0 * marginal_rate / 100.0;
+
+        println!(
+            "{}",
+            format!(
+                "Estimated after-tax P&L ({}% inclusion at a {}% marginal rate)",
+                self.tax.inclusion_rate, marginal_rate
+            )
+            .cyan()
+        );
+        println!(
+            "{:<25} | {:<15} | {:<15} | {:>15}",
+            "Account", "Unrealized P&L", "Est. Tax", "After-Tax P&L"
+        );
+        println!("{}", "-".repeat(78));
+
+        let mut taxable_accounts = 0;
+        for account in self.accounts.iter().filter(|account| account.is_taxable()) {
+            let pnl: f64 = self
+                .positions
+                .get(&account.id)
+                .map(|positions| positions.iter().map(|p| p.open_pnl).sum())
+                .unwrap_or(0.0);
+            let tax = pnl.max(0.0) * rate;
+
+            println!(
+                "{:<25} | {:<15.2} | {:<15.2} | {:>15}",
+                format!(
+                    "{} {}",
+                    account.type_,
+                    display_account_id(&account.id, self.mask_accounts)
+                ),
+                pnl,
+                tax,
+                colour_pnl(pnl - tax)
+            );
+            taxable_accounts += 1;
+        }
+
+        if taxable_accounts == 0 {
+            println!("No taxable accounts");
+        }
+        println!("This is an estimate only and ignores losses carried forward and other credits");
+        println!();
+    }
+
+    /// Totals the projected dividend income by account and by asset class, treating each
+    /// symbol's `dividend` as the annual amount per share.
+    fn dividend_income(&self) -> DividendIncome {
+        let mut income = DividendIncome::default();
+
+        for account in self.accounts.iter() {
+            let label = format!(
+                "{} {}",
+                account.type_,
+                display_account_id(&account.id, self.mask_accounts)
+            );
+            for position in self.positions.get(&account.id).into_iter().flatten() {
+                let annual = match self.symbols.get(&position.symbol_id) {
+                    Some(symbol) => symbol.dividend.unwrap_or(0.0) * position.open_quantity,
+                    None => {
+                        income.missing.insert(position.symbol.clone());
+                        0.0
+                    }
+                };
+                let asset_class = self
+                    .asset_classes
+                    .get(&position.symbol)
+                    .cloned()
+                    .unwrap_or_else(AssetClass::cash);
+
+                for (group, key) in [
+                    (&mut income.by_account, label.clone()),
+                    (&mut income.by_asset_class, String::from(&asset_class)),
+                ] {
+                    let (total, value) = group.entry(key).or_default();
+                    *total += annual;
+                    *value += position.current_market_value;
+                }
+            }
+        }
+
+        income
+    }
+
+    pub fn display_dividend_income(&self) {
+        let income = self.dividend_income();
+        let (annual, value) = income
+            .by_account
+            .values()
+            .fold((0.0, 0.0), |(annual, value), (a, v)| {
+                (annual + a, value + v)
+            });
+
+        for (title, heading, group) in [
+            ("Dividend Income by Account", "Account", &income.by_account),
+            (
+                "Dividend Income by Asset Class",
+                "Asset",
+                &income.by_asset_class,
+            ),
+        ] {
+            let mut table = Table::new(self.layout)
+                .title(title)
+                .column(heading, 25, Align::Left)
+                .column("Annual", 12, Align::Right)
+                .column("Monthly", 12, Align::Right)
+                .column("Yield", 8, Align::Right);
+            for (name, (group_annual, group_value)) in group.iter() {
+                table.row(vec![
+                    name.normal(),
+                    format!("{:.2}", group_annual).normal(),
+                    format!("{:.2}", group_annual / 12.0).normal(),
+                    format_optional(weighted_yield(*group_annual, *group_value), 2).normal(),
+                ]);
+            }
+            table.rule();
+            table.row(vec![
+                "Total".normal(),
+                format!("{:.2}", annual).normal(),
+                format!("{:.2}", annual / 12.0).normal(),
+                format_optional(weighted_yield(annual, value), 2).normal(),
+            ]);
+            println!("{}", table);
+        }
+
+        if !income.missing.is_empty() {
+            let missing: Vec<_> = income.missing.into_iter().collect();
+            println!(
+                "No symbol details for {}; counted as paying no dividend",
+                missing.join(", ")
+            );
+        }
+        println!();
+    }
+
+    /// Shows, per symbol across all accounts, how far the price must rise to get back to the
+    /// average entry price, with the positions furthest underwater first.
+    pub fn display_breakeven(&self) {
+        let Some(mut positions) = self.filtered_positions(PositionFilter::All, true) else {
+            return;
+        };
+        if positions.is_empty() {
+            println!("No positions");
+            println!();
+            return;
+        }
+        positions.sort_by(|a, b| {
+            let recovery = |position: &Position| {
+                breakeven_percent(position.average_entry_price, position.current_price)
+            };
+            recovery(b)
+                .unwrap_or(f64::NEG_INFINITY)
+                .total_cmp(&recovery(a).unwrap_or(f64::NEG_INFINITY))
+                .then_with(|| a.symbol.cmp(&b.symbol))
+        });
+
+        let mut table = Table::new(self.layout)
+            .title("Distance to Break-Even")
+            .column("Symbol", 12, Align::Left)
+            .column("Avg Price", 12, Align::Right)
+            .column("Price", 12, Align::Right)
+            .column("To Break-Even %", 16, Align::Right);
+        for position in &positions {
+            let breakeven =
+                match breakeven_percent(position.average_entry_price, position.current_price) {
+                    Some(percent) => theme::current().gain_or_loss(
+                        format!("{:.2}", percent),
+                        position.current_price - position.average_entry_price,
+                    ),
+                    None => format_optional(None, 2).normal(),
+                };
+            table.row(vec![
+                position.symbol.normal(),
+                format!("{:.2}", position.average_entry_price).normal(),
+                format!("{:.2}", position.current_price).normal(),
+                breakeven,
+            ]);
+        }
+        println!("{}", table);
+        println!();
+    }
+
+    pub fn display_validation(&self) {
+        let anomalies = validation::find_anomalies(&self.positions, &self.balances);
+        let non_finite_totals = !self.assets.total_market_values().is_finite()
+            || !self.assets.total_costs().is_finite();
+
+        if anomalies.is_empty() && !non_finite_totals {
+            println!("{}", "No data anomalies found".green());
+            println!();
+            return;
+        }
+
+        println!("{:<10} | {:<15} | Issue", "Account", "Symbol");
+        println!("{}", "-".repeat(79));
+        for anomaly in anomalies.iter() {
+            println!(
+                "{:<10} | {:<15} | {}",
+                display_account_id(&anomaly.account_id, self.mask_accounts),
+                anomaly.subject,
+                anomaly.issue.yellow()
+            );
+        }
+        if non_finite_totals {
+            println!(
+                "{:<10} | {:<15} | {}",
+                "All",
+                "Summary",
+                "portfolio totals are not finite".yellow()
+            );
+        }
+        println!();
+    }
+
+    pub fn summary_data(&self) -> SerializableAssets {
+        self.assets.to_serializable()
+    }
+
+    /// The market-value-weighted average yield of the positions, weighted by their value in
+    /// the base currency. Positions whose symbol details weren't loaded are left out, and
+    /// known symbols without a yield count as yielding nothing.
+    fn portfolio_yield(&self) -> Option<f64> {
+        let rate = self.conversion_rate();
+        let (weighted, market_value) = self
+            .positions
+            .values()
+            .flatten()
+            .filter_map(|position| {
+                let symbol = self.symbols.get(&position.symbol_id)?;
+                let market_value = position.current_market_value * rate(position);
+                Some((market_value * symbol.yield_.unwrap_or(0.0), market_value))
+            })
+            .fold(
+                (0.0, 0.0),
+                |(weighted, total), (row_weighted, row_value)| {
+                    (weighted + row_weighted, total + row_value)
+                },
+            );
+
+        if market_value == 0.0 {
+            None
+        } else {
+            Some(weighted / market_value)
+        }
+    }
+
+    pub fn display_summary(&self, out: &mut impl Write) -> io::Result<()> {
+        write!(out, "{}", self.assets)?;
+        if let Some(portfolio_yield) = self.portfolio_yield() {
+            writeln!(
+                out,
+                "{:<26} {:.2}%",
+                "Weighted average yield:", portfolio_yield
+            )?;
+        }
+        writeln!(out)
+    }
+
+    /// The total equity across all accounts in the display currency.
+    pub fn total_equity(&self) -> f64 {
+        self.balances
+            .values()
+            .filter_map(|balances| balances.combined(&self.display_currency))
+            .map(|balance| balance.total_equity)
+            .sum()
+    }
+
+    /// Prints the total equity across all accounts in the display currency as a bare number,
+    /// for status bars and scripts.
+    pub fn display_total_equity(&self) {
+        println!("{:.2}", self.total_equity());
+    }
+
+    /// Suggests buys and sells to reach the targets, with `deploy_cash` also investing the
+    /// cash sitting in every account.
+    pub fn display_rebalance(&self, explain: bool, deploy_cash: bool) {
+        if self.assets.total_market_values() == 0.0 {
+            println!("Nothing to rebalance");
+            return;
+        }
+
+        self.assets.display_rebalance(explain, deploy_cash);
+    }
+
+    /// Compares the rebalancing deltas under each of the named `strategies`.
+    pub fn display_rebalance_comparison(
+        &self,
+        strategies: &BTreeMap<String, Targets>,
+        names: &[&str],
+    ) -> Result<(), CommandError> {
+        let mut compared = Vec::new();
+        for name in names {
+            match strategies.get(*name) {
+                Some(targets) => compared.push((*name, targets.clone())),
+                None => {
+                    return Err((
+                        "invalid_command",
+                        format!(
+                        "Unknown strategy {}; define it under [strategies.{}] in the config file",
+                        name, name
+                    ),
+                    ))
+                }
+            }
+        }
+
+        self.assets.display_rebalance_comparison(&compared);
+        Ok(())
+    }
+
+    /// Displays the summary from the latest snapshot taken on or before `date`.
+    pub async fn display_summary_as_of(&self, date: NaiveDate) -> Result<(), CommandError> {
+        let Some(snapshot) = self.snapshot_on_or_before(date).await? else {
+            return Ok(());
+        };
+
+        // Older snapshots predate per-position rows, so fall back to their cached dataset.
+        let dataset = match self.db.reconstruct_dataset(snapshot.id).await {
+            Ok(dataset) if !dataset.accounts.is_empty() => dataset,
+            Ok(_) => snapshot.dataset().map_err(|err| {
+                let context = format!("Error reading snapshot from {}", snapshot.taken_at);
+                command_error(context, err)
+            })?,
+            Err(err) => return Err(command_error("Error reading snapshot positions", err)),
+        };
+        let mut assets = Assets::new(
+            self.targets.clone(),
+            self.layout,
+            self.asset_classes.clone(),
+        );
+        dataset.add_positions_to(&mut assets, &self.display_currency, &self.warnings);
+        assets.add_cash(dataset.cash(&self.display_currency));
+
+        let taken_at = snapshot.taken_at.with_timezone(&Local);
+        println!(
+            "{}",
+            format!("As of snapshot from {}", taken_at.format("%Y-%m-%d %H:%M")).blue()
+        );
+        println!("{}", assets);
+        Ok(())
+    }
+
+    /// The latest snapshot taken on or before `date` in local time, or `None` after saying
+    /// there isn't one.
+    async fn snapshot_on_or_before(
+        &self,
+        date: NaiveDate,
+    ) -> Result<Option<Snapshot>, CommandError> {
+        let before = date
+            .succ_opt()
+            .and_then(|next_day| next_day.and_hms_opt(0, 0, 0))
+            .and_then(|midnight| Local.from_local_datetime(&midnight).earliest());
+        let Some(before) = before else {
+            return Err(("invalid_command", format!("Invalid date: {}", date)));
+        };
+
+        let snapshot = self
+            .db
+            .get_snapshot_before(before.to_utc())
+            .await
+            .map_err(|err| command_error("Error reading snapshots", err))?;
+        if snapshot.is_none() {
+            println!("No snapshot on or before {}", date);
+        }
+        Ok(snapshot)
+    }
+
+    /// Prints how each symbol's quantity and market value moved since the last snapshot taken
+    /// on or before `days` ago, including positions opened or closed since. Values in both
+    /// are converted to the base currency at today's rate, so exchange rate moves don't show
+    /// up as value changes.
+    pub async fn display_diff(&self, days: i64) -> Result<(), CommandError> {
+        let date = Local::now().date_naive() - TimeDelta::days(days);
+        let Some(snapshot) = self.snapshot_on_or_before(date).await? else {
+            return Ok(());
+        };
+
+        // Older snapshots predate per-position rows, so fall back to their cached dataset.
+        let then = match self.db.reconstruct_positions(snapshot.id).await {
+            Ok(positions) if !positions.is_empty() => positions,
+            Ok(_) => match snapshot.dataset() {
+                Ok(dataset) => dataset.positions,
+                Err(err) => {
+                    let context = format!("Error reading snapshot from {}", snapshot.taken_at);
+                    return Err(command_error(context, err));
+                }
+            },
+            Err(err) => return Err(command_error("Error reading snapshot positions", err)),
+        };
+
+        let taken_at = snapshot.taken_at.with_timezone(&Local);
+        let heading = if taken_at.date_naive() == date {
+            format!("Changes since {}", taken_at.format("%Y-%m-%d %H:%M"))
+        } else {
+            format!(
+                "No snapshot on {}, showing changes since the one from {}",
+                date,
+                taken_at.format("%Y-%m-%d %H:%M")
+            )
+        };
+        println!("{}", heading.blue());
+
+        let mut table = Table::new(self.layout)
+            .column("Symbol", assets::SYMBOL_WIDTH, Align::Left)
+            .column("Quantity", 10, Align::Left)
+            .column("Qty Change", 10, Align::Right)
+            .column("Market Value", 15, Align::Left)
+            .column("Value Change", 12, Align::Right)
+            .column("Status", 6, Align::Left);
+        for change in position_changes(&then, &self.positions, self.conversion_rate()) {
+            let status = if change.quantity_before == 0.0 {
+                "new"
+            } else if change.quantity_after == 0.0 {
+                "closed"
+            } else {
+                ""
+            };
+            table.row(vec![
+                change.symbol.normal(),
+                change.quantity_after.to_string().normal(),
+                colour_change(Some(change.quantity_after - change.quantity_before)),
+                format!("{:.2}", change.value_after).normal(),
+                colour_change(Some(change.value_after - change.value_before)),
+                status.normal(),
+            ]);
+        }
+        println!("{}", table);
+        Ok(())
+    }
+
+    pub fn display_notes(&self) {
+        if self.notes.is_empty() {
+            println!("No notes. Add one with `set-note <symbol> \"text\"`");
+            return;
+        }
+
+        println!("{:<10} | Note", "Symbol");
+        println!("{}", "-".repeat(59));
+
+        let mut notes: Vec<_> = self.notes.iter().collect();
+        notes.sort();
+        for (symbol, note) in notes {
+            println!("{:<10} | {}", symbol, note);
+        }
+        println!();
+    }
+
+    /// Sets the note for `symbol`, or removes it if `note` is empty.
+    pub async fn set_note(&mut self, symbol: &str, note: &str) -> Result<(), QuestradeAPIError> {
+        let symbol = symbol.to_uppercase();
+
+        if note.is_empty() {
+            self.db.delete_note(&symbol).await?;
+            self.notes.remove(&symbol);
+        } else {
+            self.db.set_note(&symbol, note).await?;
+            self.notes.insert(symbol, note.to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Saves `asset_class` as the class of `symbol`, taking effect on the next refresh. Only
+    /// classes that already have a target or appear in the asset classes file are accepted.
+    pub async fn classify(&mut self, symbol: &str, asset_class: &str) -> Result<(), CommandError> {
+        let symbol = symbol.to_uppercase();
+        let mut known: Vec<_> = self
+            .targets
+            .classes()
+            .into_iter()
+            .map(|(asset_class, _)| asset_class)
+            .chain(self.asset_classes.values().cloned())
+            .collect();
+        known.sort();
+        known.dedup();
+
+        let Some(asset_class) = known
+            .iter()
+            .find(|known| known.name().eq_ignore_ascii_case(asset_class))
+        else {
+            let names: Vec<_> = known.iter().map(AssetClass::name).collect();
+            return Err((
+                "invalid_command",
+                format!(
+                    "Unknown asset class {}, expected one of {}",
+                    asset_class,
+                    names.join(", ")
+                ),
+            ));
+        };
+
+        self.db
+            .set_asset_classification(&symbol, asset_class.name())
+            .await
+            .map_err(|err| command_error("Error saving asset class", err))?;
+        println!(
+            "Classified {} as {}, run `refresh` to update the summary",
+            symbol,
+            asset_class.name()
+        );
+        self.asset_classes.insert(symbol, asset_class.clone());
+        Ok(())
+    }
+
+    /// Writes every position across accounts to a CSV at `path`, returning how many rows
+    /// were written.
+    pub fn export_positions(&self, path: &Path) -> Result<usize, csv::Error> {
+        let mut writer = csv::Writer::from_path(path)?;
+        let mut accounts: Vec<_> = self.positions.iter().collect();
+        accounts.sort_by_key(|(account_id, _)| *account_id);
+
+        let mut rows = 0;
+        for (account_id, positions) in accounts {
+            for position in positions {
+                let symbol = self.symbols.get(&position.symbol_id);
+                writer.serialize(PositionRow {
+                    symbol: &position.symbol,
+                    account: account_id,
+                    quantity: position.open_quantity,
+                    avg_price: position.average_entry_price,
+                    book_cost: position.total_cost,
+                    market_price: position.current_price,
+                    market_value: position.current_market_value,
+                    dividend: symbol.and_then(|symbol| symbol.dividend),
+                    yield_: symbol.and_then(|symbol| symbol.yield_),
+                    pnl: position.open_pnl,
+                    return_percent: return_percent(
+                        position.current_market_value,
+                        position.total_cost,
+                    ),
+                })?;
+                rows += 1;
+            }
+        }
+        writer.flush()?;
+
+        Ok(rows)
+    }
+
+    /// Writes the accounts, balances, positions and portfolio composition to `path` as one
+    /// pretty-printed JSON document.
+    pub fn export_json(&self, path: &Path) -> std::io::Result<()> {
+        let export = JsonExport {
+            accounts: &self.accounts,
+            balances: &self.balances,
+            positions: &self.positions,
+            composition: self.assets.to_serializable(),
+        };
+        serde_json::to_writer_pretty(std::fs::File::create(path)?, &export)?;
+
+        Ok(())
+    }
+
+    /// Deletes, or with `dry_run` just counts, the snapshots older than `keep_days`, returning
+    /// how many snapshots and position rows that covers.
+    pub async fn prune_snapshots(
+        &self,
+        keep_days: i64,
+        dry_run: bool,
+    ) -> Result<(i64, i64), sqlx::Error> {
+        let before = Utc::now() - TimeDelta::days(keep_days);
+        self.db.prune_snapshots(before, dry_run).await
+    }
+
+    pub async fn prune(&self, keep_days: i64, dry_run: bool) -> Result<(), CommandError> {
+        let (snapshots, positions) = self
+            .prune_snapshots(keep_days, dry_run)
+            .await
+            .map_err(|err| command_error("Error pruning snapshots", err))?;
+        if dry_run {
+            println!(
+                "Would remove {} snapshots and {} position rows older than {} days",
+                snapshots, positions, keep_days
+            );
+        } else {
+            println!(
+                "Removed {} snapshots and {} position rows older than {} days",
+                snapshots, positions, keep_days
+            );
+        }
+        println!();
+        Ok(())
+    }
+
+    /// Prints the total equity recorded by each snapshot in the last `days`, with a sparkline
+    /// of how it moved.
+    pub async fn display_history(&self, days: i64) -> Result<(), CommandError> {
+        let end = Utc::now();
+        let rows = self
+            .db
+            .get_snapshots_between(end - TimeDelta::days(days), end)
+            .await
+            .map_err(|err| command_error("Error loading snapshots", err))?;
+
+        let history = total_equity_history(&rows, &self.display_currency);
+        if history.len() < 2 {
+            println!(
+                "Only {} snapshot(s) in the last {} days; history needs at least two, so check back after a few more runs",
+                history.len(),
+                days
+            );
+            println!();
+            return Ok(());
+        }
+
+        let mut table = Table::new(self.layout)
+            .title("History")
+            .column("Date", 16, Align::Left)
+            .column("Total Equity", 15, Align::Right);
+        for (taken_at, total_equity) in history.iter() {
+            table.row(vec![
+                taken_at
+                    .with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+                    .normal(),
+                format!("{:.2}", total_equity).normal(),
+            ]);
+        }
+        println!("{}", table);
+
+        let totals: Vec<f64> = history.values().cloned().collect();
+        println!("{} {}", self.display_currency, sparkline(&totals));
+        println!();
+        Ok(())
+    }
+
+    /// Looks up `ticker` and prints its live quote, or the matching symbols if it's ambiguous.
+    pub async fn display_quote(&self, ticker: &str) -> Result<(), CommandError> {
+        let lookup = match self.questrade_api() {
+            Ok(api) => lookup_quote(api, ticker).await,
+            Err(err) => Err(err),
+        };
+
+        match lookup {
+            Ok(SymbolLookup::Found(quote)) => {
+                let mut table = self.quote_table();
+                table.row(quote_row(&quote));
+                print!("{}", table);
+            }
+            Ok(SymbolLookup::Ambiguous(matches)) => print_ambiguous(ticker, &matches),
+            Ok(SymbolLookup::NotFound) => println!("No symbol matches {}", ticker),
+            Err(err) => {
+                return Err(command_error(
+                    format!("Error fetching quote for {}", ticker),
+                    err,
+                ))
+            }
+        }
+        println!();
+        Ok(())
+    }
+
+    fn quote_table(&self) -> Table {
+        Table::new(self.layout)
+            .column("Symbol", assets::SYMBOL_WIDTH, Align::Left)
+            .column("Bid", 10, Align::Right)
+            .column("Ask", 10, Align::Right)
+            .column("Last", 10, Align::Right)
+            .column("Volume", 12, Align::Right)
+            .column("High", 10, Align::Right)
+            .column("Low", 10, Align::Right)
+    }
+
+    /// Resolves `ticker` and adds it to the watchlist.
+    pub async fn watch(&self, ticker: &str) -> Result<(), CommandError> {
+        let lookup = match self.questrade_api() {
+            Ok(api) => resolve_symbol(api, ticker).await,
+            Err(err) => Err(err),
+        };
+
+        match lookup {
+            Ok(SymbolLookup::Found(symbol)) => {
+                let watched = WatchedSymbol {
+                    symbol_id: symbol.symbol_id,
+                    symbol: symbol.symbol,
+                };
+                self.db
+                    .watch_symbol(&watched)
+                    .await
+                    .map_err(|err| command_error("Error saving watchlist", err))?;
+                println!("Watching {}", watched.symbol);
+            }
+            Ok(SymbolLookup::Ambiguous(matches)) => print_ambiguous(ticker, &matches),
+            Ok(SymbolLookup::NotFound) => println!("No symbol matches {}", ticker),
+            Err(err) => return Err(command_error(format!("Error looking up {}", ticker), err)),
+        }
+        Ok(())
+    }
+
+    pub async fn unwatch(&self, ticker: &str) -> Result<(), CommandError> {
+        let removed = self
+            .db
+            .unwatch_symbol(ticker)
+            .await
+            .map_err(|err| command_error("Error saving watchlist", err))?;
+        if removed {
+            println!("Stopped watching {}", ticker.to_uppercase());
+        } else {
+            println!("{} isn't on the watchlist", ticker.to_uppercase());
+        }
+        Ok(())
+    }
+
+    /// Prints a live quote for every watched symbol.
+    pub async fn display_watchlist(&self) -> Result<(), CommandError> {
+        let watchlist = self
+            .db
+            .get_watchlist()
+            .await
+            .map_err(|err| command_error("Error loading watchlist", err))?;
+        if watchlist.is_empty() {
+            println!("The watchlist is empty. Add to it with `watch <symbol>`");
+            println!();
+            return Ok(());
+        }
+
+        let symbol_ids: Vec<_> = watchlist.iter().map(|watched| watched.symbol_id).collect();
+        let quotes = match self.questrade_api() {
+            Ok(api) => fetch_quotes(api, &symbol_ids).await,
+            Err(err) => Err(err),
+        };
+        let quotes = quotes.map_err(|err| command_error("Error fetching watchlist quotes", err))?;
+
+        let mut table = self.quote_table().title("Watchlist");
+        for watched in &watchlist {
+            match quotes
+                .iter()
+                .find(|quote| quote.symbol_id == watched.symbol_id)
+            {
+                Some(quote) => table.row(quote_row(quote)),
+                None => table.row(vec![watched.symbol.normal(), "no quote".normal()]),
+            }
+        }
+        print!("{}", table);
+        println!();
+        Ok(())
+    }
+
+    /// Prints the symbols whose ticker or description starts with `prefix`.
+    pub async fn display_search(&self, prefix: &str) -> Result<(), CommandError> {
+        let results = match self.questrade_api() {
+            Ok(api) => api.search_symbols(prefix).await,
+            Err(err) => Err(err),
+        };
+
+        match results {
+            Ok(results) if results.is_empty() => println!("No symbols match {}", prefix),
+            Ok(results) => {
+                let mut table = Table::new(self.layout)
+                    .column("Symbol", assets::SYMBOL_WIDTH, Align::Left)
+                    .column("Description", 40, Align::Left)
+                    .column("Type", 10, Align::Left)
+                    .column("Exchange", 10, Align::Left)
+                    .column("Currency", 8, Align::Left)
+                    .column("ID", 10, Align::Right);
+                for result in results {
+                    table.row(vec![
+                        result.symbol.normal(),
+                        result.description.normal(),
+                        result.security_type.normal(),
+                        result.listing_exchange.normal(),
+                        result.currency.normal(),
+                        result.symbol_id.to_string().normal(),
+                    ]);
+                }
+                print!("{}", table);
+            }
+            Err(err) => {
+                return Err(command_error(
+                    format!("Error searching for {}", prefix),
+                    err,
+                ))
+            }
+        }
+        println!();
+        Ok(())
+    }
+
+    /// Loads every profile's live positions and prints one combined allocation summary.
+    pub async fn display_household(
+        &self,
+        profiles: &BTreeMap<String, Profile>,
+    ) -> Result<(), CommandError> {
+        if profiles.is_empty() {
+            println!("No profiles configured. Add a [profiles.<name>] entry with a db_path to the config file");
+            return Ok(());
+        }
+
+        let mut assets = Assets::new(
+            self.targets.clone(),
+            self.layout,
+            self.asset_classes.clone(),
+        );
+
+        for (name, profile) in profiles.iter() {
+            let dataset = self
+                .fetch_profile(profile)
+                .await
+                .map_err(|err| command_error(format!("Error loading profile {}", name), err))?;
+            dataset.add_positions_to(&mut assets, &self.display_currency, &self.warnings);
+            assets.add_cash(dataset.cash(&self.display_currency));
+        }
+
+        let names: Vec<_> = profiles.keys().map(String::as_str).collect();
+        println!("{}", format!("Household: {}", names.join(", ")).blue());
+        println!("{}", assets);
+        Ok(())
+    }
+
+    /// Loads a profile's live dataset, closing its database on the way out, as `close` does
+    /// for the tracker's own, so the rotated refresh token and cached symbols are flushed.
+    async fn fetch_profile(&self, profile: &Profile) -> Result<Dataset, QuestradeAPIError> {
+        let db = DatabaseAPI::new(&profile.db_path).await?;
+        let dataset = self.fetch_profile_from(&db).await;
+        db.close().await;
+
+        dataset
+    }
+
+    async fn fetch_profile_from(&self, db: &DatabaseAPI) -> Result<Dataset, QuestradeAPIError> {
+        let questrade_api = QuestradeAPI::new(db, None)
+            .await?
+            .with_retries(self.request_retries)
+            .with_masked_accounts(self.mask_accounts);
+
+        Dataset::fetch(
+            &questrade_api,
+            self.request_pacing,
+            self.error_policy,
+            Some(SymbolCache::new(db, self.symbol_cache_max_age)),
+            self.account_type.as_deref(),
+            self.mask_accounts,
+            &self.warnings,
+        )
+        .await
+    }
+
+    /// Fetches the last `days` of activities across all accounts, split into the
+    /// windows Questrade accepts. A long history takes many windows, so the requests are
+    /// spaced out by `request_pacing`.
+    pub async fn fetch_activities(&self, days: i64) -> Result<Vec<Activity>, QuestradeAPIError> {
+        let end = Utc::now();
+        let pacer = Pacer::new(self.request_pacing);
+        let mut activities = Vec::new();
+
+        for account in self.accounts.iter() {
+            let mut window_end = end;
+            let start = end - TimeDelta::days(days);
+
+            while window_end > start {
+                let window_start = std::cmp::max(
+                    start,
+                    window_end - TimeDelta::days(MAX_ACTIVITY_WINDOW_DAYS),
+                );
+                pacer.wait().await;
+                let resp = self
+                    .questrade_api()?
+                    .make_request(format!(
+                        "v1/accounts/{}/activities?startTime={}&endTime={}",
+                        account.id,
+                        window_start.format("%Y-%m-%dT%H:%M:%SZ"),
+                        window_end.format("%Y-%m-%dT%H:%M:%SZ")
+                    ))
+                    .await?;
+                activities.extend(
+                    serde_json::from_str::<Activities>(&resp)?
+                        .activities
+                        .into_iter()
+                        .map(|activity| Activity {
+                            account_id: account.id.clone(),
+                            ..activity
+                        }),
+                );
+                window_end = window_start;
+            }
+        }
+
+        Ok(activities)
+    }
+
+    /// Prints the last `days` of trades, dividends, deposits and fees across accounts,
+    /// oldest first.
+    pub async fn display_activities(&self, days: i64) -> Result<(), CommandError> {
+        let mut activities = self
+            .fetch_activities(days)
+            .await
+            .map_err(|err| command_error("Error fetching activities", err))?;
+        if activities.is_empty() {
+            println!("No activities in the last {} days", days);
+            println!();
+            return Ok(());
+        }
+        activities.sort_by_key(|activity| activity.trade_date);
+
+        let mut table = Table::new(self.layout)
+            .title("Activities")
+            .column("Date", 10, Align::Left)
+            .column("Account", 10, Align::Left)
+            .column("Type", 12, Align::Left)
+            .column("Action", 8, Align::Left)
+            .column("Symbol", assets::SYMBOL_WIDTH, Align::Left)
+            .column("Quantity", 10, Align::Right)
+            .column("Price", 10, Align::Right)
+            .column("Net Amount", 12, Align::Right)
+            .column("Currency", 8, Align::Left);
+        for activity in &activities {
+            table.row(vec![
+                activity.trade_date.format("%Y-%m-%d").to_string().normal(),
+                display_account_id(&activity.account_id, self.mask_accounts).normal(),
+                activity.type_.normal(),
+                activity.action.normal(),
+                activity.symbol.normal(),
+                activity.quantity.to_string().normal(),
+                format!("{:.2}", activity.price).normal(),
+                colour_pnl(activity.net_amount),
+                activity.currency.normal(),
+            ]);
+        }
+        print!("{}", table);
+        println!();
+        Ok(())
+    }
+
+    /// Prints current equity against the money deposited in the last `days`, a rough return on
+    /// contributed capital. Deposits made before the window aren't counted, so it overstates
+    /// the gain for accounts older than `days`.
+    pub async fn display_performance(&self, days: i64) -> Result<(), CommandError> {
+        let activities = self
+            .fetch_activities(days)
+            .await
+            .map_err(|err| command_error("Error fetching activities", err))?;
+
+        let usd_to_cad = usd_to_cad(self.balances.values());
+        let other = if self.display_currency == "USD" {
+            "CAD"
+        } else {
+            "USD"
+        };
+        let converts = activities
+            .iter()
+            .any(|activity| activity.is_contribution() && activity.currency == other);
+        if usd_to_cad.is_none() && converts {
+            let warning = format!(
+                "No USD/CAD rate in the balances; counting {} contributions as {}",
+                other, self.display_currency
+            );
+            eprintln!("{}", warning.yellow());
+        }
+        let contributions =
+            activities::net_contributions(&activities, &self.display_currency, usd_to_cad);
+        let equity: f64 = self
+            .balances
+            .values()
+            .filter_map(|balances| balances.combined(&self.display_currency))
+            .map(|balance| balance.total_equity)
+            .sum();
+        let gain = equity - contributions;
+
+        println!(
+            "{}",
+            format!(
+                "Performance against contributions over the last {} days",
+                days
+            )
+            .blue()
+        );
+        let currency = &self.display_currency;
+        println!(
+            "{:<26} {:.2} {}",
+            "Net contributions:", contributions, currency
+        );
+        println!("{:<26} {:.2} {}", "Current equity:", equity, currency);
+        match return_percent(equity, contributions) {
+            Some(percent) if contributions > 0.0 => println!(
+                "{:<26} {} ({}%)",
+                "Gain over contributions:",
+                colour_pnl(gain),
+                colour_change(Some(percent))
+            ),
+            _ => println!("{:<26} {}", "Gain over contributions:", colour_pnl(gain)),
+        }
+        println!(
+            "{}",
+            "This is an approximation, not a time-weighted or money-weighted return: it ignores \
+            when money was added and any deposits from before the window."
+                .dimmed()
+        );
+        println!();
+        Ok(())
+    }
+
+    pub async fn display_wash_check(&self, days: i64) -> Result<(), CommandError> {
+        let activities = self
+            .fetch_activities(days)
+            .await
+            .map_err(|err| command_error("Error fetching activities", err))?;
+        println!("{}", WashCheck::new(&activities));
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Accounts {
+    pub accounts: Vec<Account>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Account {
+    pub type_: String,
+
+    #[serde(rename = "number")]
+    pub id: AccountID,
+}
+
+impl Account {
+    /// Whether this is a `type_` account, ignoring case, e.g. `tfsa` matches TFSA.
+    pub fn is_type(&self, type_: &str) -> bool {
+        self.type_.eq_ignore_ascii_case(type_)
+    }
+
+    /// Cash and margin accounts are non-registered, so their capital gains are taxable.
+    pub fn is_taxable(&self) -> bool {
+        matches!(self.type_.as_str(), "Cash" | "Margin")
+    }
+}
+
+/// An account number as it should be printed: just its last 3 digits, e.g. `***456`, with
+/// `--mask-accounts`. The stored id is left alone so lookups still use the real number.
+pub fn display_account_id(id: &str, mask_accounts: bool) -> String {
+    if mask_accounts {
+        mask_account_id(id)
+    } else {
+        id.to_string()
+    }
+}
+
+/// Where problems that don't stop a load, like a skipped account, are reported. They're
+/// printed to stderr unless collected, which the TUI does since printing would draw over it.
+#[derive(Debug, Default)]
+pub struct Warnings {
+    collected: Option<Mutex<Vec<String>>>,
+}
+
+impl Warnings {
+    /// Warnings held for `take` rather than printed.
+    pub fn collected() -> Self {
+        Self {
+            collected: Some(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn warn(&self, warning: impl Display) {
+        match &self.collected {
+            Some(collected) => collected.lock().unwrap().push(warning.to_string()),
+            None => eprintln!("{}", warning),
+        }
+    }
+
+    /// Removes and returns the warnings collected so far.
+    pub fn take(&self) -> Vec<String> {
+        match &self.collected {
+            Some(collected) => std::mem::take(&mut *collected.lock().unwrap()),
+            None => Vec::new(),
+        }
+    }
+}
+
+fn mask_account_id(id: &str) -> String {
+    let hidden = id.chars().count().saturating_sub(3);
+    format!("***{}", id.chars().skip(hidden).collect::<String>())
+}
+
+impl Account {
+    /// The heading printed above the account's balances or allocation.
+    pub fn title(&self, mask_accounts: bool) -> ColoredString {
+        format!(
+            "Account: {} — {}",
+            self.type_,
+            display_account_id(&self.id, mask_accounts)
+        )
+        .blue()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Balances {
+    pub per_currency_balances: Vec<Balance>,
+    pub combined_balances: Vec<Balance>,
+}
+
+impl Balances {
+    /// The combined balance in `currency`, if Questrade reported one.
+    pub fn combined(&self, currency: &str) -> Option<&Balance> {
+        self.combined_balances
+            .iter()
+            .find(|balance| balance.currency == currency)
+    }
+
+    /// Prints the per-currency balances and a combined row per currency, `combined_currency`
+    /// first. Each combined total equity is green or red depending on whether it's up or down
+    /// from `previous`.
+    pub fn display_balances(
+        &self,
+        out: &mut impl Write,
+        combined_currency: &str,
+        previous: Option<&Balances>,
+    ) -> io::Result<()> {
+        writeln!(
+            out,
+            "{:<12} | {:<10} | {:<15} | {:>15}",
+            "Currency", "Cash", "Market Equity", "Total Equity"
+        )?;
+        writeln!(out, "{}", "-".repeat(61))?;
+        for balance in self.per_currency_balances.iter() {
+            writeln!(
+                out,
+                "{:<12} | {:<10.2} | {:<15.2} | {:>15.2}",
+                balance.currency, balance.cash, balance.market_value, balance.total_equity
+            )?;
+        }
+
+        writeln!(out, "{}", "=".repeat(61))?;
+        let mut combined: Vec<_> = self.combined_balances.iter().collect();
+        combined.sort_by_key(|balance| balance.currency != combined_currency);
+        for balance in combined {
+            let total_equity = format!("{:.2}", balance.total_equity);
+            let previous_equity = previous
+                .and_then(|previous| previous.combined(&balance.currency))
+                .map(|previous| previous.total_equity);
+            let total_equity = match previous_equity {
+                Some(previous) => {
+                    theme::current().gain_or_loss(total_equity, balance.total_equity - previous)
+                }
+                None => total_equity.normal(),
+            };
+
+            writeln!(
+                out,
+                "{:<12} | {:<10.2} | {:<15.2} | {:>15}",
+                format!("Combined {}", balance.currency),
+                balance.cash,
+                balance.market_value,
+                total_equity
+            )?;
+        }
+
+        writeln!(out)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Balance {
+    pub currency: String,
+    pub cash: f64,
+    pub market_value: f64,
+    pub total_equity: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Positions {
+    positions: Vec<Position>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Position {
+    pub symbol: String,
+    pub symbol_id: SymbolID,
+    pub open_quantity: f64,
+    pub closed_quantity: f64,
+    pub current_market_value: f64,
+    pub current_price: f64,
+    pub average_entry_price: f64,
+    pub closed_pnl: f64,
+    pub open_pnl: f64,
+    pub total_cost: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Symbols {
+    symbols: Vec<Symbol>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Symbol {
+    pub symbol: String,
+    pub symbol_id: SymbolID,
+    pub dividend: Option<f64>,
+    pub yield_: Option<f64>,
+    pub prev_day_close_price: Option<f64>,
+    /// Empty for symbols cached before currencies were recorded.
+    #[serde(default)]
+    pub currency: String,
+}
+
+/// Projected annual dividends and the market value they're earned on, per group.
+#[derive(Default)]
+struct DividendIncome {
+    by_account: BTreeMap<String, (f64, f64)>,
+    by_asset_class: BTreeMap<String, (f64, f64)>,
+    /// Held symbols with no details loaded, so no known dividend.
+    missing: BTreeSet<String>,
+}
+
+/// Annual income as a percentage of the market value earning it.
+fn weighted_yield(annual: f64, market_value: f64) -> Option<f64> {
+    if market_value == 0.0 {
+        None
+    } else {
+        Some(annual / market_value * 100.0)
+    }
+}
+
+/// One row of `positions`, as shown in the table or printed as JSON.
+#[derive(Debug, Serialize)]
+pub struct PositionData {
+    pub symbol: String,
+    pub currency: String,
+    pub quantity: f64,
+    pub avg_price: f64,
+    pub book_cost: f64,
+    pub market_price: f64,
+    /// Whether `market_price` is an extended-hours quote.
+    pub extended_hours: bool,
+    pub day_change_percent: Option<f64>,
+    pub market_value: f64,
+    pub dividend: Option<f64>,
+    #[serde(rename = "yield")]
+    pub yield_: Option<f64>,
+    pub pnl: f64,
+    pub return_percent: Option<f64>,
+    pub note: Option<String>,
+    /// Whether the symbol's details couldn't be looked up, leaving the currency, dividend and
+    /// yield unknown.
+    pub symbol_missing: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct PositionsData {
+    pub positions: Vec<PositionData>,
+    pub total_book_cost: f64,
+    pub total_market_value: f64,
+    pub total_pnl: f64,
+    pub total_return_percent: Option<f64>,
+    pub realized_pnl: f64,
+}
+
+impl PositionsData {
+    /// Takes out the positions `min_value` hides and returns them, dropping them from the
+    /// totals too if it excludes them.
+    fn hide_below(&mut self, min_value: MinValue) -> Vec<PositionData> {
+        let (shown, hidden) = std::mem::take(&mut self.positions)
+            .into_iter()
+            .partition(|position| !min_value.hides(position.market_value));
+        self.positions = shown;
+
+        if min_value.exclude_from_totals {
+            self.total_book_cost = self.positions.iter().map(|p| p.book_cost).sum();
+            self.total_market_value = self.positions.iter().map(|p| p.market_value).sum();
+            self.total_pnl = self.total_market_value - self.total_book_cost;
+            self.total_return_percent =
+                return_percent(self.total_market_value, self.total_book_cost);
+        }
+        hidden
+    }
+}
+
+/// The USD/CAD rate implied by an account's combined balances, which Questrade reports in
+/// both currencies.
+fn usd_to_cad<'a>(balances: impl IntoIterator<Item = &'a Balances>) -> Option<f64> {
+    balances.into_iter().find_map(|balances| {
+        match (balances.combined("CAD"), balances.combined("USD")) {
+            (Some(cad), Some(usd)) if cad.total_equity != 0.0 && usd.total_equity != 0.0 => {
+                Some(cad.total_equity / usd.total_equity)
+            }
+            _ => None,
+        }
+    })
+}
+
+/// An account's cash across currencies, from the `currency` total Questrade combines them
+/// into.
+fn cash_in(balances: &Balances, currency: &str) -> f64 {
+    balances
+        .combined(currency)
+        .map_or(0.0, |balance| balance.cash)
+}
+
+/// Converts a position's values to `currency` by its symbol's currency. Positions are counted
+/// as they are if `usd_to_cad` isn't known or their symbol's details weren't loaded.
+fn conversion_rate<'a>(
+    symbols: &'a HashMap<SymbolID, Symbol>,
+    usd_to_cad: Option<f64>,
+    currency: &'a str,
+) -> impl Fn(&Position) -> f64 + 'a {
+    move |position| {
+        let symbol_currency = symbols
+            .get(&position.symbol_id)
+            .map(|symbol| symbol.currency.as_str());
+        match (symbol_currency, usd_to_cad) {
+            (Some("USD"), Some(rate)) if currency == "CAD" => rate,
+            (Some("CAD"), Some(rate)) if currency == "USD" => 1.0 / rate,
+            _ => 1.0,
+        }
+    }
+}
+
+/// Which positions `display_positions_with_dividends` shows.
+#[derive(Clone, Copy)]
+pub enum PositionFilter<'a> {
+    All,
+    Account(&'a str),
+    /// One ticker across all accounts, matched case-insensitively.
+    Symbol(&'a str),
+}
+
+/// Where `fetch_symbols` looks for symbol details before asking Questrade.
+#[derive(Clone, Copy)]
+pub struct SymbolCache<'a> {
+    db: &'a DatabaseAPI,
+    max_age: TimeDelta,
+}
+
+impl<'a> SymbolCache<'a> {
+    pub fn new(db: &'a DatabaseAPI, max_age: TimeDelta) -> Self {
+        SymbolCache { db, max_age }
+    }
+
+    /// Rows fetched before this are stale. Previous closes change daily, so rows from before
+    /// today are always stale however long `max_age` is.
+    fn fresh_since(&self) -> DateTime<Utc> {
+        let start_of_today = Local::now()
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .and_then(|midnight| midnight.and_local_timezone(Local).earliest())
+            .map(|midnight| midnight.to_utc());
+
+        match start_of_today {
+            Some(start_of_today) => (Utc::now() - self.max_age).max(start_of_today),
+            None => Utc::now() - self.max_age,
+        }
+    }
+}
+
+/// One line of `export positions`.
+#[derive(Serialize)]
+struct PositionRow<'a> {
+    symbol: &'a str,
+    account: &'a str,
+    quantity: f64,
+    avg_price: f64,
+    book_cost: f64,
+    market_price: f64,
+    market_value: f64,
+    dividend: Option<f64>,
+    #[serde(rename = "yield")]
+    yield_: Option<f64>,
+    pnl: f64,
+    return_percent: Option<f64>,
+}
+
+/// The document written by `export json`.
+#[derive(Serialize)]
+struct JsonExport<'a> {
+    accounts: &'a [Account],
+    balances: &'a HashMap<AccountID, Balances>,
+    positions: &'a HashMap<AccountID, Vec<Position>>,
+    composition: SerializableAssets,
+}
+
+/// How one symbol's holdings, summed across accounts, moved between two sets of positions.
+#[derive(Debug, PartialEq)]
+struct PositionChange {
+    symbol: String,
+    quantity_before: f64,
+    quantity_after: f64,
+    value_before: f64,
+    value_after: f64,
+}
+
+/// Every symbol held in `before` or `after`, in ticker order, with market values converted to
+/// the base currency at `rate`. Symbols only in one of them have a quantity and market value
+/// of zero in the other.
+fn position_changes(
+    before: &HashMap<AccountID, Vec<Position>>,
+    after: &HashMap<AccountID, Vec<Position>>,
+    rate: impl Fn(&Position) -> f64,
+) -> Vec<PositionChange> {
+    let mut changes: BTreeMap<&str, PositionChange> = BTreeMap::new();
+    let before = before.values().flatten().map(|position| (position, false));
+    let after = after.values().flatten().map(|position| (position, true));
+    for (position, is_after) in before.chain(after) {
+        let change = changes
+            .entry(&position.symbol)
+            .or_insert_with(|| PositionChange {
+                symbol: position.symbol.clone(),
+                quantity_before: 0.0,
+                quantity_after: 0.0,
+                value_before: 0.0,
+                value_after: 0.0,
+            });
+        let value = position.current_market_value * rate(position);
+        if is_after {
+            change.quantity_after += position.open_quantity;
+            change.value_after += value;
+        } else {
+            change.quantity_before += position.open_quantity;
+            change.value_before += value;
+        }
+    }
+
+    changes.into_values().collect()
+}
+
+/// The percent change from `prev_close`, or `None` if there's no usable previous close.
+fn day_change_percent(current_price: f64, prev_close: Option<f64>) -> Option<f64> {
+    match prev_close {
+        Some(prev_close) if prev_close != 0.0 => {
+            Some((current_price - prev_close) / prev_close * 100.0)
+        }
+        _ => None,
+    }
+}
+
+/// The percent change of `quote`'s last trade from the previous close, or from today's open
+/// if there's no previous close.
+fn quote_day_change(quote: &Quote) -> Option<f64> {
+    day_change_percent(
+        quote.last_trade_price?,
+        quote.prev_day_close_price.or(quote.open_price),
+    )
+}
+
+fn quote_row(quote: &Quote) -> Vec<ColoredString> {
+    vec![
+        quote.symbol.normal(),
+        format_optional(quote.bid_price, 2).normal(),
+        format_optional(quote.ask_price, 2).normal(),
+        format_optional(quote.last_trade_price, 2).normal(),
+        quote.volume.to_string().normal(),
+        format_optional(quote.high_price, 2).normal(),
+        format_optional(quote.low_price, 2).normal(),
+    ]
+}
+
+fn print_ambiguous(ticker: &str, matches: &[SymbolSearchResult]) {
+    println!("{} matches several symbols, be more specific:", ticker);
+    for symbol in matches {
+        println!(
+            "{:<10} {} ({})",
+            symbol.symbol, symbol.description, symbol.listing_exchange
+        );
+    }
+}
+
+/// The unrealized return on `book_cost` as a percent, or `None` with no cost basis to
+/// measure it against.
+fn return_percent(market_value: f64, book_cost: f64) -> Option<f64> {
+    if book_cost == 0.0 {
+        None
+    } else {
+        Some((market_value - book_cost) / book_cost * 100.0)
+    }
+}
+
+/// The percent gain needed to get from `current_price` back to `average_entry_price`, zero if
+/// the price is already at or above it. `None` when either price is zero, negative or not a
+/// number, since no gain gets a worthless holding back.
+fn breakeven_percent(average_entry_price: f64, current_price: f64) -> Option<f64> {
+    let valid = |price: f64| price.is_finite() && price > 0.0;
+    if !valid(average_entry_price) || !valid(current_price) {
+        return None;
+    }
+
+    Some(((average_entry_price / current_price - 1.0) * 100.0).max(0.0))
+}
+
+/// Checks `balances` include combined balances in `currency`, listing the currencies they do
+/// have if not. No balances at all pass, since there's nothing to total.
+fn check_base_currency<'a>(
+    balances: impl IntoIterator<Item = &'a Balances>,
+    currency: &str,
+) -> Result<(), String> {
+    let mut available: Vec<_> = balances
+        .into_iter()
+        .flat_map(|balances| balances.combined_balances.iter())
+        .map(|balance| balance.currency.as_str())
+        .collect();
+    if available.is_empty() || available.contains(&currency) {
+        return Ok(());
+    }
+
+    available.sort_unstable();
+    available.dedup();
+    Err(format!(
+        "no combined {} balances from Questrade, expected one of {}",
+        currency,
+        available.join(", ")
+    ))
+}
+
+/// Why `display_streamed` or `display_accounts_only_summary` stopped.
+#[derive(Debug)]
+pub enum LoadError {
+    Questrade(QuestradeAPIError),
+    /// Questrade has no combined balances in the base currency.
+    BaseCurrency(String),
+}
+
+impl LoadError {
+    /// A short, stable name for the kind of error, for machine-readable output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LoadError::Questrade(err) => err.kind(),
+            LoadError::BaseCurrency(_) => "config",
+        }
+    }
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LoadError::Questrade(err) => write!(f, "{}", err),
+            LoadError::BaseCurrency(message) => write!(f, "Invalid base currency: {}", message),
+        }
+    }
+}
+
+impl From<QuestradeAPIError> for LoadError {
+    fn from(err: QuestradeAPIError) -> Self {
+        LoadError::Questrade(err)
+    }
+}
+
+impl From<sqlx::Error> for LoadError {
+    fn from(err: sqlx::Error) -> Self {
+        LoadError::Questrade(err.into())
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        LoadError::Questrade(err.into())
+    }
+}
+
+/// Why a command failed: the kind of error, as reported with `--format json`, and a message.
+pub type CommandError = (&'static str, String);
+
+/// A `CommandError` for `err`, its message prefixed with what was being done.
+fn command_error(
+    context: impl std::fmt::Display,
+    err: impl Into<QuestradeAPIError>,
+) -> CommandError {
+    let err = err.into();
+    (err.kind(), format!("{}: {}", context, err))
+}
+
+/// The configured asset classes with those set by `classify` on top.
+async fn asset_classes(
+    db: &DatabaseAPI,
+    config: &Config,
+) -> Result<HashMap<String, AssetClass>, sqlx::Error> {
+    let mut asset_classes = config.asset_classes.clone();
+    for classification in db.get_asset_classifications().await? {
+        asset_classes.insert(
+            classification.symbol,
+            AssetClass::new(&classification.asset_class),
+        );
+    }
+
+    Ok(asset_classes)
+}
+
+fn colour_change(change: Option<f64>) -> ColoredString {
+    match change {
+        Some(change) => theme::current().gain_or_loss(format!("{:.2}", change), change),
+        None => format_optional(None, 2).normal(),
+    }
+}
+
+/// Formats a value that Questrade may not report, using `—` for missing data
+/// so it can't be mistaken for a genuine zero.
+fn format_optional(value: Option<f64>, precision: usize) -> String {
+    match value {
+        Some(value) => format!("{:.*}", precision, value),
+        None => String::from("—"),
+    }
+}
+
+/// Questrade's clock, captured once at startup so market status stays current without
+/// another request.
+struct ServerClock {
+    skew: TimeDelta,
+    offset: FixedOffset,
+}
+
+impl ServerClock {
+    fn new(server_time: DateTime<FixedOffset>) -> Self {
+        ServerClock {
+            skew: server_time.to_utc() - Utc::now(),
+            offset: *server_time.offset(),
+        }
+    }
+
+    fn now(&self) -> DateTime<FixedOffset> {
+        (Utc::now() + self.skew).with_timezone(&self.offset)
+    }
+}
+
+#[derive(Deserialize)]
+struct ExtendedQuote {
+    price: f64,
+}
+
+async fn fetch_extended_quote(
+    client: &reqwest::Client,
+    source: &ExtendedQuotes,
+    symbol: &str,
+) -> Result<f64, QuestradeAPIError> {
+    let url = source.url.replace("{symbol}", symbol);
+    let response = client.get(url).send().await?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        return Err(QuestradeAPIError::HTTPError(status, response.text().await?));
+    }
+
+    let quote: ExtendedQuote = serde_json::from_str(&response.text().await?)?;
+    Ok(quote.price)
+}
+
+#[derive(Debug, Deserialize)]
+struct Quotes {
+    quotes: Vec<Quote>,
+}
+
+/// A live Level 1 quote from `v1/markets/quotes`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Quote {
+    pub symbol: String,
+    #[serde(default)]
+    pub symbol_id: SymbolID,
+    pub bid_price: Option<f64>,
+    pub ask_price: Option<f64>,
+    pub last_trade_price: Option<f64>,
+    #[serde(default)]
+    pub volume: u64,
+    pub high_price: Option<f64>,
+    pub low_price: Option<f64>,
+    pub open_price: Option<f64>,
+    pub prev_day_close_price: Option<f64>,
+}
+
+/// What looking up a ticker turned up.
+pub enum SymbolLookup<T> {
+    Found(T),
+    Ambiguous(Vec<SymbolSearchResult>),
+    NotFound,
+}
+
+pub type QuoteLookup = SymbolLookup<Quote>;
+
+/// Resolves `ticker` with a symbol search, preferring an exact match among the results.
+async fn resolve_symbol(
+    questrade_api: &QuestradeAPI,
+    ticker: &str,
+) -> Result<SymbolLookup<SymbolSearchResult>, QuestradeAPIError> {
+    let mut matches = questrade_api.search_symbols(ticker).await?;
+
+    let exact = matches
+        .iter()
+        .enumerate()
+        .filter(|(_, symbol)| symbol.symbol.eq_ignore_ascii_case(ticker))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    Ok(match (exact.as_slice(), matches.len()) {
+        ([i], _) => SymbolLookup::Found(matches.swap_remove(*i)),
+        (_, 0) => SymbolLookup::NotFound,
+        (_, 1) => SymbolLookup::Found(matches.remove(0)),
+        _ => SymbolLookup::Ambiguous(matches),
+    })
+}
+
+/// Fetches live quotes for `symbol_ids` in one request.
+async fn fetch_quotes(
+    questrade_api: &QuestradeAPI,
+    symbol_ids: &[SymbolID],
+) -> Result<Vec<Quote>, QuestradeAPIError> {
+    let ids: Vec<_> = symbol_ids.iter().map(|id| id.to_string()).collect();
+    let resp = questrade_api
+        .make_request(format!("v1/markets/quotes?ids={}", ids.join(",")))
+        .await?;
+
+    Ok(serde_json::from_str::<Quotes>(&resp)?.quotes)
+}
+
+/// Resolves `ticker`, then fetches its quote.
+async fn lookup_quote(
+    questrade_api: &QuestradeAPI,
+    ticker: &str,
+) -> Result<QuoteLookup, QuestradeAPIError> {
+    let symbol_id = match resolve_symbol(questrade_api, ticker).await? {
+        SymbolLookup::Found(symbol) => symbol.symbol_id,
+        SymbolLookup::Ambiguous(matches) => return Ok(SymbolLookup::Ambiguous(matches)),
+        SymbolLookup::NotFound => return Ok(SymbolLookup::NotFound),
+    };
+
+    let resp = questrade_api
+        .make_request(format!("v1/markets/quotes/{}", symbol_id))
+        .await?;
+    match serde_json::from_str::<Quotes>(&resp)?.quotes.pop() {
+        Some(quote) => Ok(SymbolLookup::Found(quote)),
+        None => Ok(SymbolLookup::NotFound),
+    }
+}
+
+/// Sums each snapshot's combined total equity in `currency` across accounts.
+fn total_equity_history(rows: &[BalanceSnapshot], currency: &str) -> BTreeMap<DateTime<Utc>, f64> {
+    let mut history = BTreeMap::new();
+    for row in rows.iter().filter(|row| row.currency == currency) {
+        *history.entry(row.taken_at).or_default() += row.total_equity;
+    }
+
+    history
+}
+
+/// Draws the stored prices as a row of block characters scaled between their min and max.
+fn sparkline(prices: &[f64]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    let min = prices.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = prices.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+
+    prices
+        .iter()
+        .map(|price| {
+            if max == min {
+                BARS[BARS.len() / 2]
+            } else {
+                BARS[((price - min) / (max - min) * (BARS.len() - 1) as f64).round() as usize]
+            }
+        })
+        .collect()
+}
+
+/// Regular North American trading hours, 9:30 to 16:00 Eastern on weekdays. `now` should be
+/// in Eastern time, which Questrade's server time already is. Holidays aren't accounted for.
+fn is_market_open(now: DateTime<FixedOffset>) -> bool {
+    let open = NaiveTime::from_hms_opt(9, 30, 0).unwrap();
+    let close = NaiveTime::from_hms_opt(16, 0, 0).unwrap();
+
+    !matches!(now.weekday(), Weekday::Sat | Weekday::Sun) && (open..close).contains(&now.time())
+}
+
+/// What to do when one account or symbol fails to load.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ErrorPolicy {
+    /// Abort the whole load on the first error.
+    FailFast,
+    /// Warn and carry on without the failing account or symbol.
+    #[default]
+    BestEffort,
+}
+
+impl ErrorPolicy {
+    /// Whether `err` should be skipped rather than propagated. Expired tokens always
+    /// propagate so the caller can log in again.
+    fn skips(&self, err: &QuestradeAPIError) -> bool {
+        *self == ErrorPolicy::BestEffort && !err.is_unauthorized()
+    }
+}
+
+async fn fetch_balances(
+    questrade_api: &QuestradeAPI,
+    account_id: &str,
+    pacer: &Pacer,
+) -> Result<Balances, QuestradeAPIError> {
+    pacer.wait().await;
+    let resp = questrade_api
+        .make_request(format!("v1/accounts/{}/balances", account_id))
+        .await?;
+
+    Ok(serde_json::from_str::<Balances>(&resp)?)
+}
+
+/// Fetches every page of `account_id`'s positions, stopping at the first page that comes back
+/// short of `POSITIONS_PER_PAGE`. Questrade doesn't document paging this endpoint, so a page
+/// that repeats the previous one or adds no new symbols also ends the fetch, as does reaching
+/// `MAX_POSITION_PAGES`.
+async fn fetch_positions(
+    questrade_api: &QuestradeAPI,
+    account_id: &str,
+    pacer: &Pacer,
+    mask_accounts: bool,
+    warnings: &Warnings,
+) -> Result<Vec<Position>, QuestradeAPIError> {
+    let mut positions: Vec<Position> = Vec::new();
+    let mut seen = HashSet::new();
+    let mut previous_first = None;
+    for page_number in 1.. {
+        pacer.wait().await;
+        let resp = questrade_api
+            .make_request(format!(
+                "v1/accounts/{}/positions?offset={}&limit={}",
+                account_id,
+                positions.len(),
+                POSITIONS_PER_PAGE
+            ))
+            .await?;
+        let page = serde_json::from_str::<Positions>(&resp)?.positions;
+        let last_page = page.len() < POSITIONS_PER_PAGE;
+
+        let first = page.first().map(|position| position.symbol_id);
+        if first.is_some() && first == previous_first {
+            debug!(
+                account = %display_account_id(account_id, mask_accounts),
+                "positions page repeats the previous one, stopping"
+            );
+            break;
+        }
+        previous_first = first;
+
+        let fetched = positions.len();
+        positions.extend(
+            page.into_iter()
+                .filter(|position| seen.insert(position.symbol_id)),
+        );
+        if last_page || positions.len() == fetched {
+            break;
+        }
+        if page_number == MAX_POSITION_PAGES {
+            let warning = format!(
+                "Stopped fetching positions for account {} after {} pages",
+                display_account_id(account_id, mask_accounts),
+                MAX_POSITION_PAGES
+            );
+            warnings.warn(warning.yellow());
+            break;
+        }
+        debug!(fetched = positions.len(), "fetching next page of positions");
+    }
+
+    Ok(positions)
+}
+
+async fn fetch_symbols(
+    questrade_api: &QuestradeAPI,
+    symbol_ids: &[SymbolID],
+) -> Result<Symbols, QuestradeAPIError> {
+    let ids: Vec<_> = symbol_ids.iter().map(SymbolID::to_string).collect();
+    let resp = questrade_api
+        .make_request(format!("v1/symbols?ids={}", ids.join(",")))
+        .await?;
+
+    Ok(serde_json::from_str::<Symbols>(&resp)?)
+}
+
+fn warn_skipped_account(
+    account_id: &str,
+    err: &QuestradeAPIError,
+    mask_accounts: bool,
+    warnings: &Warnings,
+) {
+    let account_id = display_account_id(account_id, mask_accounts);
+    let warning = if err.is_permission_denied() {
+        format!(
+            "Skipping account {}: access denied for this token",
+            account_id
+        )
+    } else {
+        format!("Skipping account {}: {}", account_id, err)
+    };
+    warnings.warn(warning.yellow());
+}
+
+/// Merges positions in the same symbol, e.g. an ETF held in several accounts, into one
+/// row per symbol in the order each was first seen.
+fn consolidate_positions(positions: &[Position]) -> Vec<Position> {
+    let mut consolidated: Vec<Position> = Vec::new();
+
+    for position in positions {
+        let Some(merged) = consolidated
+            .iter_mut()
+            .find(|merged| merged.symbol_id == position.symbol_id)
+        else {
+            consolidated.push(position.clone());
+            continue;
+        };
+
+        let open_quantity = merged.open_quantity + position.open_quantity;
+        if open_quantity != 0.0 {
+            merged.average_entry_price = (merged.average_entry_price * merged.open_quantity
+                + position.average_entry_price * position.open_quantity)
+                / open_quantity;
+        }
+
+        merged.open_quantity = open_quantity;
+        merged.closed_quantity += position.closed_quantity;
+        merged.current_market_value += position.current_market_value;
+        merged.closed_pnl += position.closed_pnl;
+        merged.open_pnl += position.open_pnl;
+        merged.total_cost += position.total_cost;
+    }
+
+    consolidated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::TestDb;
+
+    fn position(symbol_id: SymbolID, quantity: f64, average_price: f64) -> Position {
+        Position {
+            symbol: format!("SYM{}", symbol_id),
+            symbol_id,
+            open_quantity: quantity,
+            closed_quantity: 0.0,
+            current_market_value: quantity * 30.0,
+            current_price: 30.0,
+            average_entry_price: average_price,
+            closed_pnl: 0.0,
+            open_pnl: quantity * (30.0 - average_price),
+            total_cost: quantity * average_price,
+        }
+    }
+
+    /// A tracker over `dataset` backed by a fresh database file unique to `name`, which is
+    /// deleted once the returned `TestDb` is dropped.
+    async fn test_tracker(name: &str, dataset: Dataset) -> (AssetTracker, TestDb) {
+        let db = TestDb::new(name).await;
+        let tracker = AssetTracker::new(db.clone(), None, dataset, &Config::default())
+            .await
+            .unwrap();
+
+        (tracker, db)
+    }
+
+    #[tokio::test]
+    async fn activities_are_fetched_in_windows_and_tagged_with_account() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/v1/accounts/1/activities"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "activities": [{
+                    "tradeDate": "2024-06-03T00:00:00.000000-04:00",
+                    "action": "Buy",
+                    "symbol": "XEQT.TO",
+                    "symbolId": 1,
+                    "description": "",
+                    "currency": "CAD",
+                    "quantity": 10.0,
+                    "price": 30.0,
+                    "netAmount": -300.0,
+                    "type": "Trades",
+                }]
+            })))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let dataset = Dataset {
+            accounts: vec![Account {
+                type_: String::from("TFSA"),
+                id: String::from("1"),
+            }],
+            ..Dataset::default()
+        };
+        let (mut tracker, _db) = test_tracker("activities", dataset).await;
+        tracker.questrade_api = Some(QuestradeAPI::for_server(&server.uri()));
+        tracker.request_pacing = Duration::from_millis(50);
+
+        let start = Instant::now();
+        let activities = tracker.fetch_activities(45).await.unwrap();
+        assert_eq!(activities.len(), 2);
+        assert_eq!(activities[0].account_id, "1");
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn positions_for_account_without_entry_dont_panic() {
+        let (tracker, _db) = test_tracker("no_positions", Dataset::default()).await;
+        let mut out = Vec::new();
+        tracker
+            .display_positions_with_dividends(
+                &mut out,
+                PositionFilter::Account("123"),
+                false,
+                None,
+                None,
+            )
+            .unwrap();
+        tracker
+            .display_positions_with_dividends(
+                &mut out,
+                PositionFilter::Symbol("XEQT.TO"),
+                false,
+                None,
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "No positions\nNo positions in XEQT.TO in any account\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn dividend_income_groups_by_account_and_asset_class() {
+        let account = |id: &str| Account {
+            type_: String::from("TFSA"),
+            id: id.to_string(),
+        };
+        let symbol = |symbol_id: SymbolID, dividend: f64| Symbol {
+            symbol: format!("SYM{}", symbol_id),
+            symbol_id,
+            dividend: Some(dividend),
+            yield_: None,
+            prev_day_close_price: None,
+            currency: String::from("CAD"),
+        };
+        let dataset = Dataset {
+            accounts: vec![account("1"), account("2")],
+            positions: HashMap::from([
+                (String::from("1"), vec![position(1, 10.0, 25.0)]),
+                (
+                    String::from("2"),
+                    vec![position(1, 20.0, 25.0), position(3, 5.0, 25.0)],
+                ),
+            ]),
+            symbols: HashMap::from([(1, symbol(1, 1.5))]),
+            ..Dataset::default()
+        };
+        let (tracker, _db) = test_tracker("dividends", dataset).await;
+
+        let income = tracker.dividend_income();
+        assert_eq!(income.by_account["TFSA 1"], (15.0, 300.0));
+        assert_eq!(income.by_account["TFSA 2"], (30.0, 750.0));
+        assert_eq!(income.by_asset_class["Cash"], (45.0, 1050.0));
+        assert_eq!(income.missing.into_iter().collect::<Vec<_>>(), ["SYM3"]);
+        assert_eq!(weighted_yield(45.0, 0.0), None);
+    }
+
+    #[tokio::test]
+    async fn base_currency_must_be_in_the_combined_balances() {
+        let usd_only = Balances {
+            per_currency_balances: Vec::new(),
+            combined_balances: vec![Balance {
+                currency: String::from("USD"),
+                cash: 10.0,
+                market_value: 0.0,
+                total_equity: 10.0,
+            }],
+        };
+        let (empty, _empty_db) = test_tracker("base_currency_empty", Dataset::default()).await;
+        let (tracker, _db) = test_tracker(
+            "base_currency_usd_only",
+            Dataset {
+                balances: HashMap::from([(String::from("1"), usd_only)]),
+                ..Dataset::default()
+            },
+        )
+        .await;
+
+        assert_eq!(empty.check_base_currency(), Ok(()));
+        assert_eq!(
+            tracker.check_base_currency(),
+            Err(String::from(
+                "no combined CAD balances from Questrade, expected one of USD"
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn each_account_gets_its_own_allocation() {
+        let account = |id: &str| Account {
+            type_: String::from("TFSA"),
+            id: id.to_string(),
+        };
+        let dataset = Dataset {
+            accounts: vec![account("1"), account("2")],
+            positions: HashMap::from([
+                (String::from("1"), vec![position(1, 10.0, 25.0)]),
+                (
+                    String::from("2"),
+                    vec![position(2, 20.0, 25.0), position(3, 5.0, 25.0)],
+                ),
+            ]),
+            ..Dataset::default()
+        };
+        let (tracker, _db) = test_tracker("allocation_by_account", dataset).await;
+
+        assert_eq!(tracker.account_assets("1").total_market_values(), 300.0);
+        assert_eq!(tracker.account_assets("2").total_market_values(), 750.0);
+        assert_eq!(tracker.account_assets("3").total_market_values(), 0.0);
+        assert_eq!(tracker.assets.total_market_values(), 1050.0);
+    }
+
+    #[tokio::test]
+    async fn portfolio_yield_is_weighted_by_market_value() {
+        let symbol = |symbol_id: SymbolID, yield_: Option<f64>| Symbol {
+            symbol: format!("SYM{}", symbol_id),
+            symbol_id,
+            dividend: None,
+            yield_,
+            prev_day_close_price: None,
+            currency: String::from("CAD"),
+        };
+        let dataset = Dataset {
+            accounts: vec![Account {
+                type_: String::from("TFSA"),
+                id: String::from("1"),
+            }],
+            positions: HashMap::from([(
+                String::from("1"),
+                vec![
+                    position(1, 30.0, 25.0),
+                    position(2, 10.0, 25.0),
+                    position(3, 50.0, 25.0),
+                ],
+            )]),
+            symbols: HashMap::from([(1, symbol(1, Some(4.0))), (2, symbol(2, None))]),
+            ..Dataset::default()
+        };
+        let (tracker, _db) = test_tracker("portfolio_yield", dataset).await;
+
+        // SYM3's details weren't loaded, so only SYM1 (900 at 4%) and SYM2 (300 at 0%) count.
+        assert_eq!(tracker.portfolio_yield(), Some(3.0));
+        let (empty, _empty_db) = test_tracker("portfolio_yield_empty", Dataset::default()).await;
+        assert_eq!(empty.portfolio_yield(), None);
+    }
+
+    #[tokio::test]
+    async fn positions_data_totals_only_filtered_rows() {
+        let dataset = Dataset {
+            positions: HashMap::from([
+                (String::from("1"), vec![position(1, 10.0, 25.0)]),
+                (
+                    String::from("2"),
+                    vec![position(1, 5.0, 20.0), position(2, 4.0, 20.0)],
+                ),
+            ]),
+            ..Dataset::default()
+        };
+        let (tracker, _db) = test_tracker("positions_data", dataset).await;
+
+        let data = tracker
+            .positions_data(PositionFilter::Symbol("sym1"), false, None)
+            .unwrap();
+        assert_eq!(data.positions.len(), 2);
+        assert_eq!(data.total_book_cost, 350.0);
+        assert_eq!(data.total_pnl, 100.0);
+        assert_eq!(data.total_return_percent, Some(100.0 / 350.0 * 100.0));
+        assert_eq!(return_percent(10.0, 0.0), None);
+
+        let data = tracker
+            .positions_data(PositionFilter::Symbol("sym1"), true, None)
+            .unwrap();
+        assert_eq!(data.positions.len(), 1);
+        assert_eq!(data.positions[0].quantity, 15.0);
+
+        assert!(tracker
+            .positions_data(PositionFilter::Account("3"), false, None)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn positions_data_hides_positions_below_min_value() {
+        let dataset = Dataset {
+            positions: HashMap::from([
+                (String::from("1"), vec![position(1, 10.0, 25.0)]),
+                (String::from("2"), vec![position(2, 4.0, 20.0)]),
+            ]),
+            ..Dataset::default()
+        };
+        let (tracker, _db) = test_tracker("positions_data_min_value", dataset).await;
+        let min_value = |exclude_from_totals: bool| MinValue {
+            amount: 200.0,
+            exclude_from_totals,
+        };
+
+        // SYM2 is worth 120, so it's hidden but still counted in the totals.
+        let data = tracker
+            .positions_data(PositionFilter::All, false, Some(min_value(false)))
+            .unwrap();
+        let symbols: Vec<_> = data.positions.iter().map(|p| p.symbol.as_str()).collect();
+        assert_eq!(symbols, ["SYM1"]);
+        assert_eq!(data.total_market_value, 420.0);
+
+        let data = tracker
+            .positions_data(PositionFilter::All, false, Some(min_value(true)))
+            .unwrap();
+        assert_eq!(data.positions.len(), 1);
+        assert_eq!(data.total_market_value, 300.0);
+        assert_eq!(data.total_book_cost, 250.0);
+    }
+
+    #[tokio::test]
+    async fn classify_accepts_only_known_asset_classes() {
+        let dataset = Dataset {
+            positions: HashMap::from([(String::from("1"), vec![position(1, 10.0, 25.0)])]),
+            ..Dataset::default()
+        };
+        let (mut tracker, _db) = test_tracker("classify", dataset).await;
+
+        let (kind, _) = tracker.classify("sym1", "reits").await.unwrap_err();
+        assert_eq!(kind, "invalid_command");
+        tracker.classify("sym1", "stocks").await.unwrap();
+        assert_eq!(tracker.asset_classes["SYM1"], AssetClass::stocks());
+        assert_eq!(
+            tracker.db.get_asset_classifications().await.unwrap().len(),
+            1
+        );
+
+        let reloaded = AssetTracker::new(
+            tracker.db.clone(),
+            None,
+            Dataset::default(),
+            &Config::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(reloaded.asset_classes["SYM1"], AssetClass::stocks());
+    }
+
+    #[tokio::test]
+    async fn positions_without_symbol_details_are_marked_missing() {
+        let dataset = Dataset {
+            positions: HashMap::from([(
+                String::from("1"),
+                vec![position(1, 10.0, 25.0), position(2, 4.0, 20.0)],
+            )]),
+            symbols: HashMap::from([(
+                1,
+                Symbol {
+                    symbol: String::from("SYM1"),
+                    symbol_id: 1,
+                    dividend: Some(0.0),
+                    yield_: Some(0.0),
+                    prev_day_close_price: None,
+                    currency: String::from("CAD"),
+                },
+            )]),
+            ..Dataset::default()
+        };
+        let (tracker, _db) = test_tracker("symbol_missing", dataset).await;
+
+        let data = tracker
+            .positions_data(PositionFilter::All, false, None)
+            .unwrap();
+        let missing: Vec<_> = data
+            .positions
+            .iter()
+            .map(|position| (position.symbol.as_str(), position.symbol_missing))
+            .collect();
+        assert!(missing.contains(&("SYM1", false)));
+        assert!(missing.contains(&("SYM2", true)));
+
+        colored::control::set_override(false);
+        let mut out = Vec::new();
+        tracker
+            .display_positions_with_dividends(&mut out, PositionFilter::All, false, None, None)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("n/a: couldn't look up SYM2"));
+    }
+
+    #[tokio::test]
+    async fn export_positions_writes_one_row_per_position() {
+        let dataset = Dataset {
+            positions: HashMap::from([
+                (String::from("1"), vec![position(1, 10.0, 25.0)]),
+                (String::from("2"), vec![position(2, 4.0, 20.0)]),
+            ]),
+            symbols: HashMap::from([(
+                1,
+                Symbol {
+                    symbol: String::from("SYM1"),
+                    symbol_id: 1,
+                    dividend: Some(0.5),
+                    yield_: Some(2.0),
+                    prev_day_close_price: None,
+                    currency: String::from("CAD"),
+                },
+            )]),
+            ..Dataset::default()
+        };
+        let (tracker, _db) = test_tracker("export", dataset).await;
+
+        let path = std::env::temp_dir().join(format!(
+            "questrade_asset_tracker_export_{}.csv",
+            std::process::id()
+        ));
+        assert_eq!(tracker.export_positions(&path).unwrap(), 2);
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "symbol,account,quantity,avg_price,book_cost,market_price,market_value,dividend,yield,pnl,return_percent",
+                "SYM1,1,10.0,25.0,250.0,30.0,300.0,0.5,2.0,50.0,20.0",
+                "SYM2,2,4.0,20.0,80.0,30.0,120.0,,,40.0,50.0",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_skips_accounts_without_permission() {
+        use wiremock::{
+            matchers::{method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        let respond = |route: &str, status: u16, body: serde_json::Value| {
+            Mock::given(method("GET"))
+                .and(path(route.to_string()))
+                .respond_with(ResponseTemplate::new(status).set_body_json(body))
+        };
+
+        respond(
+            "/v1/accounts",
+            200,
+            serde_json::json!({"accounts": [
+                {"type": "TFSA", "number": "1"},
+                {"type": "Margin", "number": "2"},
+                {"type": "RRSP", "number": "3"},
+            ]}),
+        )
+        .mount(&server)
+        .await;
+        for route in ["/v1/accounts/2/balances", "/v1/accounts/2/positions"] {
+            respond(
+                route,
+                403,
+                serde_json::json!({"code": 1016, "message": "Access denied"}),
+            )
+            .mount(&server)
+            .await;
+        }
+        for account in ["1", "3"] {
+            respond(
+                &format!("/v1/accounts/{}/balances", account),
+                200,
+                serde_json::json!({"perCurrencyBalances": [], "combinedBalances": []}),
+            )
+            .mount(&server)
+            .await;
+            respond(
+                &format!("/v1/accounts/{}/positions", account),
+                200,
+                serde_json::json!({"positions": []}),
+            )
+            .mount(&server)
+            .await;
+        }
+
+        let questrade_api = QuestradeAPI::for_server(&server.uri());
+        let warnings = Warnings::collected();
+        let dataset = Dataset::fetch(
+            &questrade_api,
+            Duration::ZERO,
+            ErrorPolicy::BestEffort,
+            None,
+            None,
+            false,
+            &warnings,
+        )
+        .await
+        .unwrap();
+
+        let ids: Vec<_> = dataset.accounts.iter().map(|a| a.id.as_str()).collect();
+        assert_eq!(ids, vec!["1", "3"]);
+        assert_eq!(
+            warnings.take(),
+            vec!["Skipping account 2: access denied for this token"]
+        );
+        assert!(!dataset.balances.contains_key("2"));
+
+        let err = Dataset::fetch(
+            &questrade_api,
+            Duration::ZERO,
+            ErrorPolicy::FailFast,
+            None,
+            None,
+            false,
+            &Warnings::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.is_permission_denied());
+    }
+
+    #[tokio::test]
+    async fn positions_are_collected_across_pages() {
+        use wiremock::{
+            matchers::{method, path, query_param},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        let page = |offset: usize, count: usize| {
+            let positions: Vec<_> = (offset..offset + count)
+                .map(|i| {
+                    serde_json::json!({
+                        "symbol": format!("S{}", i),
+                        "symbolId": i,
+                        "openQuantity": 1.0,
+                        "closedQuantity": 0.0,
+                        "currentMarketValue": 10.0,
+                        "currentPrice": 10.0,
+                        "averageEntryPrice": 10.0,
+                        "closedPnl": 0.0,
+                        "openPnl": 0.0,
+                        "totalCost": 10.0,
+                    })
+                })
+                .collect();
+            Mock::given(method("GET"))
+                .and(path("/v1/accounts/1/positions"))
+                .and(query_param("offset", offset.to_string()))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_json(serde_json::json!({ "positions": positions })),
+                )
+        };
+        page(0, POSITIONS_PER_PAGE).mount(&server).await;
+        page(POSITIONS_PER_PAGE, 2).mount(&server).await;
+        Mock::given(method("GET"))
+            .and(path("/v1/accounts/1/balances"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(
+                serde_json::json!({"perCurrencyBalances": [], "combinedBalances": []}),
+            ))
+            .mount(&server)
+            .await;
+
+        let questrade_api = QuestradeAPI::for_server(&server.uri());
+        let account = Account {
+            type_: String::from("Margin"),
+            id: String::from("1"),
+        };
+        let dataset = Dataset::fetch_account(
+            &questrade_api,
+            account,
+            &Pacer::new(Duration::ZERO),
+            false,
+            &Warnings::default(),
+        )
+        .await
+        .unwrap();
+
+        let positions = &dataset.positions["1"];
+        assert_eq!(positions.len(), POSITIONS_PER_PAGE + 2);
+        assert_eq!(
+            positions.last().unwrap().symbol,
+            format!("S{}", POSITIONS_PER_PAGE + 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn positions_paging_stops_when_the_server_ignores_offset() {
+        use wiremock::{
+            matchers::{method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        let positions: Vec<_> = (0..POSITIONS_PER_PAGE)
+            .map(|i| {
+                serde_json::json!({
+                    "symbol": format!("S{}", i),
+                    "symbolId": i,
+                    "openQuantity": 1.0,
+                    "closedQuantity": 0.0,
+                    "currentMarketValue": 10.0,
+                    "currentPrice": 10.0,
+                    "averageEntryPrice": 10.0,
+                    "closedPnl": 0.0,
+                    "openPnl": 0.0,
+                    "totalCost": 10.0,
+                })
+            })
+            .collect();
+        Mock::given(method("GET"))
+            .and(path("/v1/accounts/1/positions"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "positions": positions })),
+            )
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let questrade_api = QuestradeAPI::for_server(&server.uri());
+        let positions = fetch_positions(
+            &questrade_api,
+            "1",
+            &Pacer::new(Duration::ZERO),
+            false,
+            &Warnings::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(positions.len(), POSITIONS_PER_PAGE);
+    }
+
+    #[tokio::test]
+    async fn tracker_is_built_from_mocked_questrade_responses() {
+        use wiremock::{
+            matchers::{method, path, query_param},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        let respond = |route: &str, body: serde_json::Value| {
+            Mock::given(method("GET"))
+                .and(path(route.to_string()))
+                .respond_with(ResponseTemplate::new(200).set_body_json(body))
+        };
+        let position = |symbol: &str, symbol_id: SymbolID, value: f64| {
+            serde_json::json!({
+                "symbol": symbol,
+                "symbolId": symbol_id,
+                "openQuantity": 10.0,
+                "closedQuantity": 0.0,
+                "currentMarketValue": value,
+                "currentPrice": value / 10.0,
+                "averageEntryPrice": 20.0,
+                "closedPnl": 0.0,
+                "openPnl": value - 200.0,
+                "totalCost": 200.0,
+            })
+        };
+        let balance = |currency: &str, cash: f64, total_equity: f64| {
+            serde_json::json!({
+                "currency": currency,
+                "cash": cash,
+                "marketValue": total_equity - cash,
+                "totalEquity": total_equity,
+            })
+        };
+
+        respond(
+            "/v1/accounts",
+            serde_json::json!({"accounts": [
+                {"type": "TFSA", "number": "1"},
+                {"type": "Margin", "number": "2"},
+            ]}),
+        )
+        .mount(&server)
+        .await;
+        respond(
+            "/v1/accounts/1/positions",
+            serde_json::json!({"positions": [position("XEQT.TO", 10, 300.0)]}),
+        )
+        .mount(&server)
+        .await;
+        respond(
+            "/v1/accounts/2/positions",
+            serde_json::json!({"positions": [position("VFV", 20, 240.0)]}),
+        )
+        .mount(&server)
+        .await;
+        respond(
+            "/v1/accounts/1/balances",
+            serde_json::json!({
+                "perCurrencyBalances": [balance("CAD", 50.0, 350.0)],
+                "combinedBalances": [balance("CAD", 50.0, 350.0), balance("USD", 40.0, 280.0)],
+            }),
+        )
+        .mount(&server)
+        .await;
+        respond(
+            "/v1/accounts/2/balances",
+            serde_json::json!({
+                "perCurrencyBalances": [balance("USD", 0.0, 240.0)],
+                "combinedBalances": [balance("CAD", 0.0, 300.0), balance("USD", 0.0, 240.0)],
+            }),
+        )
+        .mount(&server)
+        .await;
+        Mock::given(method("GET"))
+            .and(path("/v1/symbols"))
+            .and(query_param("ids", "10,20"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "symbols": [
+                    {"symbol": "XEQT.TO", "symbolId": 10, "dividend": 0.5, "yield": 1.8, "currency": "CAD"},
+                    {"symbol": "VFV", "symbolId": 20, "dividend": null, "yield": null, "currency": "USD"},
+                ]
+            })))
+            .mount(&server)
+            .await;
+
+        let questrade_api = QuestradeAPI::for_server(&server.uri());
+        let dataset = Dataset::fetch(
+            &questrade_api,
+            Duration::ZERO,
+            ErrorPolicy::FailFast,
+            None,
+            None,
+            false,
+            &Warnings::default(),
+        )
+        .await
+        .unwrap();
+        let (tracker, _db) = test_tracker("mocked_questrade", dataset).await;
+
+        let ids: Vec<_> = tracker.accounts.iter().map(|a| a.id.as_str()).collect();
+        assert_eq!(ids, vec!["1", "2"]);
+        assert_eq!(tracker.positions["2"][0].symbol, "VFV");
+        assert_eq!(tracker.symbols[&10].dividend, Some(0.5));
+        assert_eq!(tracker.symbols[&20].currency, "USD");
+        // VFV is converted at the 1.25 USD/CAD rate the balances imply, and the CAD cash is
+        // counted alongside the positions.
+        assert_eq!(
+            tracker.assets.total_market_values(),
+            300.0 + 240.0 * 1.25 + 50.0
+        );
+        assert_eq!(tracker.assets.cash_balance(), 50.0);
+    }
+
+    #[tokio::test]
+    async fn symbols_are_fetched_in_one_batch() {
+        use wiremock::{
+            matchers::{method, path, query_param},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/symbols"))
+            .and(query_param("ids", "1,2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "symbols": [
+                    {"symbol": "SYM1", "symbolId": 1, "dividend": 0.1, "yield": 1.0},
+                    {"symbol": "SYM2", "symbolId": 2, "dividend": 0.2, "yield": 2.0},
+                ]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut dataset = Dataset {
+            positions: HashMap::from([
+                (
+                    String::from("1"),
+                    vec![position(1, 1.0, 1.0), position(2, 1.0, 1.0)],
+                ),
+                (String::from("2"), vec![position(1, 1.0, 1.0)]),
+            ]),
+            ..Dataset::default()
+        };
+
+        let questrade_api = QuestradeAPI::for_server(&server.uri());
+        dataset
+            .fetch_symbols(
+                &questrade_api,
+                &Pacer::new(Duration::ZERO),
+                ErrorPolicy::FailFast,
+                None,
+                &Warnings::default(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(dataset.symbols.len(), 2);
+        assert_eq!(dataset.symbols[&2].dividend, Some(0.2));
+    }
+
+    #[test]
+    fn sparkline_scales_between_min_and_max() {
+        assert_eq!(sparkline(&[1.0, 2.0, 3.0]), "▁▅█");
+        assert_eq!(sparkline(&[5.0, 5.0]), "▅▅");
+        assert_eq!(sparkline(&[]), "");
+    }
+
+    #[test]
+    fn total_equity_history_sums_accounts_per_snapshot() {
+        let at = |time: &str| DateTime::parse_from_rfc3339(time).unwrap().to_utc();
+        let row =
+            |taken_at: &str, account_id: &str, currency: &str, total_equity: f64| BalanceSnapshot {
+                taken_at: at(taken_at),
+                account_id: account_id.to_string(),
+                currency: currency.to_string(),
+                cash: 0.0,
+                market_value: total_equity,
+                total_equity,
+            };
+        let rows = [
+            row("2024-06-03T12:00:00Z", "1", "CAD", 100.0),
+            row("2024-06-03T12:00:00Z", "2", "CAD", 50.0),
+            row("2024-06-03T12:00:00Z", "1", "USD", 73.0),
+            row("2024-06-04T12:00:00Z", "1", "CAD", 120.0),
+        ];
+
+        let history = total_equity_history(&rows, "CAD");
+        assert_eq!(
+            history.into_iter().collect::<Vec<_>>(),
+            [
+                (at("2024-06-03T12:00:00Z"), 150.0),
+                (at("2024-06-04T12:00:00Z"), 120.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn breakeven_is_the_gain_back_to_the_entry_price() {
+        assert_eq!(breakeven_percent(100.0, 80.0), Some(25.0));
+        assert_eq!(breakeven_percent(100.0, 120.0), Some(0.0));
+        assert_eq!(breakeven_percent(100.0, 0.0), None);
+        assert_eq!(breakeven_percent(0.0, 50.0), None);
+        assert_eq!(breakeven_percent(-5.0, 50.0), None);
+        assert_eq!(breakeven_percent(100.0, f64::NAN), None);
+    }
+
+    #[test]
+    fn market_hours() {
+        let at = |time: &str| DateTime::parse_from_rfc3339(time).unwrap();
+
+        assert!(is_market_open(at("2024-06-03T09:30:00-04:00")));
+        assert!(is_market_open(at("2024-06-03T15:59:59-04:00")));
+        assert!(!is_market_open(at("2024-06-03T09:29:59-04:00")));
+        assert!(!is_market_open(at("2024-06-03T16:00:00-04:00")));
+        assert!(!is_market_open(at("2024-06-01T12:00:00-04:00")));
+    }
+
+    #[tokio::test]
+    async fn quote_lookup_prefers_exact_match() {
+        use wiremock::{
+            matchers::{path, query_param},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(path("/v1/symbols/search"))
+            .and(query_param("prefix", "VFV"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "symbols": [
+                    {"symbol": "VFV.TO", "symbolId": 10, "description": "Vanguard S&P 500"},
+                    {"symbol": "VFV", "symbolId": 11, "description": "Other"},
+                ]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(path("/v1/symbols/search"))
+            .and(query_param("prefix", "VF"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "symbols": [
+                    {"symbol": "VFV.TO", "symbolId": 10},
+                    {"symbol": "VFV", "symbolId": 11},
+                ]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(path("/v1/markets/quotes/11"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "quotes": [{
+                    "symbol": "VFV",
+                    "bidPrice": 99.5,
+                    "askPrice": 100.5,
+                    "lastTradePrice": 100.0,
+                    "volume": 1200,
+                    "highPrice": 101.0,
+                    "lowPrice": null,
+                }]
+            })))
+            .mount(&server)
+            .await;
+
+        let api = QuestradeAPI::for_server(&server.uri());
+        match lookup_quote(&api, "vfv").await.unwrap() {
+            SymbolLookup::Found(quote) => {
+                assert_eq!(quote.symbol, "VFV");
+                assert_eq!(quote.volume, 1200);
+                assert_eq!(quote.low_price, None);
+            }
+            _ => panic!("expected a quote"),
+        }
+        match lookup_quote(&api, "VF").await.unwrap() {
+            SymbolLookup::Ambiguous(matches) => assert_eq!(matches.len(), 2),
+            _ => panic!("expected several matches"),
+        }
+    }
+
+    #[tokio::test]
+    async fn extended_quotes_override_prices_they_cover() {
+        use wiremock::{
+            matchers::{method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/quote/SYM1"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"price": 32.0})),
+            )
+            .mount(&server)
+            .await;
+
+        let mut dataset = Dataset {
+            positions: HashMap::from([(
+                String::from("1"),
+                vec![position(1, 10.0, 20.0), position(2, 5.0, 10.0)],
+            )]),
+            ..Dataset::default()
+        };
+        let source = ExtendedQuotes {
+            url: format!("{}/quote/{{symbol}}", server.uri()),
+        };
+
+        let updated = dataset
+            .apply_extended_quotes(&source, &Warnings::default())
+            .await;
+
+        assert_eq!(updated, HashSet::from([String::from("SYM1")]));
+        let positions = &dataset.positions["1"];
+        assert_eq!(positions[0].current_price, 32.0);
+        assert_eq!(positions[0].current_market_value, 320.0);
+        assert_eq!(positions[0].open_pnl, 120.0);
+        assert_eq!(positions[1].current_price, 30.0);
+    }
+
+    #[test]
+    fn consolidate_merges_same_symbol_across_accounts() {
+        let positions = vec![
+            position(1, 10.0, 20.0),
+            position(2, 5.0, 10.0),
+            position(1, 30.0, 28.0),
+        ];
+
+        let consolidated = consolidate_positions(&positions);
+
+        assert_eq!(consolidated.len(), 2);
+        assert_eq!(consolidated[0].symbol_id, 1);
+        assert_eq!(consolidated[0].open_quantity, 40.0);
+        assert_eq!(consolidated[0].total_cost, 1040.0);
+        assert_eq!(consolidated[0].current_market_value, 1200.0);
+        assert_eq!(consolidated[0].average_entry_price, 26.0);
+        assert_eq!(consolidated[1], positions[1]);
+    }
+
+    #[test]
+    fn usd_positions_are_converted_to_cad() {
+        let balance = |currency: &str, total_equity: f64| Balance {
+            currency: currency.to_string(),
+            cash: 0.0,
+            market_value: total_equity,
+            total_equity,
+        };
+        let symbol = |symbol_id: SymbolID, currency: &str| Symbol {
+            symbol: format!("SYM{}", symbol_id),
+            symbol_id,
+            dividend: None,
+            yield_: None,
+            prev_day_close_price: None,
+            currency: currency.to_string(),
+        };
+        let dataset = Dataset {
+            accounts: vec![Account {
+                type_: String::from("TFSA"),
+                id: String::from("1"),
+            }],
+            positions: HashMap::from([(
+                String::from("1"),
+                vec![position(1, 10.0, 30.0), position(2, 10.0, 30.0)],
+            )]),
+            balances: HashMap::from([(
+                String::from("1"),
+                Balances {
+                    per_currency_balances: Vec::new(),
+                    combined_balances: vec![balance("CAD", 1370.0), balance("USD", 1000.0)],
+                },
+            )]),
+            symbols: HashMap::from([(1, symbol(1, "CAD")), (2, symbol(2, "USD"))]),
+        };
+
+        let mut assets = Assets::new(Targets::default(), Layout::Auto, HashMap::new());
+        dataset.add_positions_to(&mut assets, "CAD", &Warnings::default());
+        assert_eq!(assets.total_market_values(), 300.0 + 300.0 * 1.37);
+
+        let mut assets = Assets::new(Targets::default(), Layout::Auto, HashMap::new());
+        dataset.add_positions_to(&mut assets, "USD", &Warnings::default());
+        assert!((assets.total_market_values() - (300.0 / 1.37 + 300.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn symbol_without_dividend_data_deserializes_to_none() {
+        let symbol: Symbol =
+            serde_json::from_str(r#"{"symbol": "VWRL.L", "symbolId": 1, "dividend": null}"#)
+                .unwrap();
+
+        assert_eq!(symbol.dividend, None);
+        assert_eq!(symbol.yield_, None);
+        assert_eq!(format_optional(symbol.dividend, 4), "—");
+        assert_eq!(format_optional(symbol.yield_, 2), "—");
+    }
+
+    #[test]
+    fn account_title_uses_em_dash() {
+        colored::control::set_override(false);
+        let account = Account {
+            type_: String::from("TFSA"),
+            id: String::from("12345678"),
+        };
+
+        assert_eq!(account.title(false).to_string(), "Account: TFSA — 12345678");
+        assert_eq!(account.title(true).to_string(), "Account: TFSA — ***678");
+        assert_eq!(mask_account_id("12345678"), "***678");
+        assert_eq!(mask_account_id("12"), "***12");
+    }
+
+    #[test]
+    fn retain_account_type_drops_other_accounts_and_their_data() {
+        let account = |type_: &str, id: &str| Account {
+            type_: type_.to_string(),
+            id: id.to_string(),
+        };
+        let mut dataset = Dataset {
+            accounts: vec![account("TFSA", "1"), account("Margin", "2")],
+            positions: HashMap::from([
+                (String::from("1"), Vec::new()),
+                (String::from("2"), Vec::new()),
+            ]),
+            ..Dataset::default()
+        };
+
+        dataset.retain_account_type("tfsa");
+
+        assert_eq!(dataset.accounts.len(), 1);
+        assert_eq!(dataset.accounts[0].id, "1");
+        assert!(!dataset.positions.contains_key("2"));
+    }
+
+    #[test]
+    fn symbol_currency_is_optional() {
+        let symbol: Symbol =
+            serde_json::from_str(r#"{"symbol": "VFV.TO", "symbolId": 3, "currency": "USD"}"#)
+                .unwrap();
+        assert_eq!(symbol.currency, "USD");
+
+        let symbol: Symbol =
+            serde_json::from_str(r#"{"symbol": "VFV.TO", "symbolId": 3}"#).unwrap();
+        assert_eq!(symbol.currency, "");
+    }
+
+    #[test]
+    fn day_change_needs_a_nonzero_previous_close() {
+        let symbol: Symbol = serde_json::from_str(
+            r#"{"symbol": "XEQT.TO", "symbolId": 2, "prevDayClosePrice": 25.0}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            day_change_percent(30.0, symbol.prev_day_close_price),
+            Some(20.0)
+        );
+        assert_eq!(day_change_percent(30.0, Some(0.0)), None);
+        assert_eq!(day_change_percent(30.0, None), None);
+    }
+
+    #[test]
+    fn position_changes_cover_new_and_closed_symbols() {
+        let position = |symbol: &str, quantity: f64, value: f64| Position {
+            symbol: String::from(symbol),
+            symbol_id: 1,
+            open_quantity: quantity,
+            closed_quantity: 0.0,
+            current_market_value: value,
+            current_price: 0.0,
+            average_entry_price: 0.0,
+            closed_pnl: 0.0,
+            open_pnl: 0.0,
+            total_cost: 0.0,
+        };
+        let before = HashMap::from([
+            (String::from("1"), vec![position("XEQT.TO", 10.0, 300.0)]),
+            (String::from("2"), vec![position("ZAG.TO", 5.0, 70.0)]),
+        ]);
+        let after = HashMap::from([
+            (
+                String::from("1"),
+                vec![
+                    position("XEQT.TO", 10.0, 310.0),
+                    position("VFV.TO", 2.0, 240.0),
+                ],
+            ),
+            (String::from("2"), vec![position("XEQT.TO", 4.0, 124.0)]),
+        ]);
+
+        // VFV.TO stands in for a USD holding converted at 1.25.
+        let rate = |position: &Position| {
+            if position.symbol == "VFV.TO" {
+                1.25
+            } else {
+                1.0
+            }
+        };
+        let changes = position_changes(&before, &after, rate);
+        let summary: Vec<_> = changes
+            .iter()
+            .map(|change| {
+                (
+                    change.symbol.as_str(),
+                    change.quantity_after - change.quantity_before,
+                    change.value_after - change.value_before,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("VFV.TO", 2.0, 300.0),
+                ("XEQT.TO", 4.0, 134.0),
+                ("ZAG.TO", -5.0, -70.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn live_day_change_falls_back_to_the_open() {
+        let quote = |json: &str| -> Quote { serde_json::from_str(json).unwrap() };
+
+        let closed_at_25 = quote(
+            r#"{"symbol": "XEQT.TO", "lastTradePrice": 30.0, "openPrice": 24.0, "prevDayClosePrice": 25.0}"#,
+        );
+        assert_eq!(quote_day_change(&closed_at_25), Some(20.0));
+        let opened_at_24 =
+            quote(r#"{"symbol": "XEQT.TO", "lastTradePrice": 30.0, "openPrice": 24.0}"#);
+        assert_eq!(quote_day_change(&opened_at_24), Some(25.0));
+        let no_trades = quote(r#"{"symbol": "XEQT.TO", "prevDayClosePrice": 25.0}"#);
+        assert_eq!(quote_day_change(&no_trades), None);
+    }
+
+    #[test]
+    fn symbol_with_zero_dividend_keeps_zero() {
+        let symbol: Symbol = serde_json::from_str(
+            r#"{"symbol": "XEQT.TO", "symbolId": 2, "dividend": 0.0, "yield": 0.0}"#,
+        )
+        .unwrap();
+
+        assert_eq!(symbol.dividend, Some(0.0));
+        assert_eq!(symbol.yield_, Some(0.0));
+        assert_eq!(format_optional(symbol.dividend, 4), "0.0000");
+        assert_eq!(format_optional(symbol.yield_, 2), "0.00");
+    }
 }
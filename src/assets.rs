@@ -1,89 +1,211 @@
-use crate::asset_tracker;
+use crate::{
+    asset_tracker,
+    config::{ConfigError, Targets},
+    table::{Align, Layout, Table},
+    theme,
+};
 use colored::{Color, ColoredString, Colorize};
-use std::{collections::HashMap, fmt};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, path::Path};
 
-const STOCK_TARGET: f64 = 50.0;
-const BOND_TARGET: f64 = 50.0;
-const CASH_TARGET: f64 = 0.0;
-const MARGIN_OF_WARNING: f64 = 2.5;
-const MARGIN_OF_ERROR: f64 = 5.0;
+/// Width of the symbol column in the positions and summary tables.
+pub const SYMBOL_WIDTH: usize = 10;
 
-#[derive(Eq, Hash, PartialEq, Clone)]
-enum AssetClass {
-    Stocks,
-    Bonds,
-    Cash,
+/// Shortens `symbol` to at most `width` characters, ending in an ellipsis if it was cut.
+pub fn truncate_symbol(symbol: &str, width: usize) -> String {
+    if symbol.chars().count() <= width {
+        symbol.to_string()
+    } else {
+        let mut truncated: String = symbol.chars().take(width - 1).collect();
+        truncated.push('…');
+        truncated
+    }
+}
+
+/// A category of holdings with its own target, e.g. Stocks or REITs. Stocks, Bonds and Cash
+/// are built in; any other name used in the asset classes file or targets works too.
+#[derive(Debug, Eq, Hash, PartialEq, Ord, PartialOrd, Clone, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct AssetClass(String);
+
+impl AssetClass {
+    pub const STOCKS: &'static str = "Stocks";
+    pub const BONDS: &'static str = "Bonds";
+    pub const CASH: &'static str = "Cash";
+
+    pub fn new(name: &str) -> Self {
+        AssetClass(name.to_string())
+    }
+
+    pub fn stocks() -> Self {
+        AssetClass::new(AssetClass::STOCKS)
+    }
+
+    pub fn bonds() -> Self {
+        AssetClass::new(AssetClass::BONDS)
+    }
+
+    /// Also the class of any symbol missing from the asset classes file.
+    pub fn cash() -> Self {
+        AssetClass::new(AssetClass::CASH)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
 }
 
 impl From<&AssetClass> for String {
     fn from(asset_class: &AssetClass) -> String {
-        String::from(match asset_class {
-            AssetClass::Stocks => "Stocks",
-            AssetClass::Bonds => "Bonds",
-            AssetClass::Cash => "Cash",
-        })
+        asset_class.0.clone()
+    }
+}
+
+/// The symbol to asset class mapping used when there's no asset classes file.
+pub fn default_asset_classes() -> HashMap<String, AssetClass> {
+    HashMap::from([
+        ("XEQT.TO".to_string(), AssetClass::stocks()),
+        ("ZEQT.TO".to_string(), AssetClass::stocks()),
+        ("ZAG.TO".to_string(), AssetClass::bonds()),
+    ])
+}
+
+/// Loads a TOML file of `"SYMBOL" = "<asset class>"` entries, e.g. `"VRE.TO" = "REITs"`,
+/// falling back to the defaults if the file doesn't exist.
+pub fn load_asset_classes(path: &Path) -> Result<HashMap<String, AssetClass>, ConfigError> {
+    if !path.exists() {
+        return Ok(default_asset_classes());
+    }
+
+    Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+/// A composition table row's name, book cost, market value and percent.
+type CompRow<T> = (T, f64, f64, f64);
+
+/// Hides holdings worth less than `amount` from the symbol composition and positions tables.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MinValue {
+    pub amount: f64,
+    /// Leaves hidden holdings out of the totals too, rather than summing them in a row of
+    /// their own.
+    pub exclude_from_totals: bool,
+}
+
+impl MinValue {
+    pub fn hides(&self, market_value: f64) -> bool {
+        market_value < self.amount
     }
 }
 
+/// One symbol's or asset class's share of the portfolio.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Composition {
+    pub name: String,
+    pub book_cost: f64,
+    pub market_value: f64,
+    pub percent: f64,
+}
+
+/// The portfolio summary as plain data, for exporting.
+#[derive(Debug, Serialize)]
+pub struct SerializableAssets {
+    pub total_book_cost: f64,
+    pub total_market_value: f64,
+    pub unrealized_pnl: f64,
+    pub realized_pnl: f64,
+    pub symbols: Vec<Composition>,
+    pub asset_classes: Vec<Composition>,
+}
+
 pub struct Assets {
+    targets: Targets,
+    layout: Layout,
     total_costs: f64,
     total_market_values: f64,
+    /// P&L on shares sold today, which Questrade reports per position as `closedPnl`.
+    realized_pnl: f64,
+    /// Uninvested money in the accounts, in the base currency, counted in the Cash class and
+    /// the totals.
+    cash_balance: f64,
     asset_to_class_map: HashMap<String, AssetClass>,
     class_to_colour_map: HashMap<AssetClass, Color>,
     asset_map: HashMap<String, (f64, f64)>,
     class_map: HashMap<AssetClass, (f64, f64)>,
+    min_value: Option<MinValue>,
 }
 
 impl Assets {
-    pub fn new() -> Assets {
-        let mut asset_class_map = HashMap::new();
-        asset_class_map.insert("XEQT.TO".to_string(), AssetClass::Stocks);
-        asset_class_map.insert("ZEQT.TO".to_string(), AssetClass::Stocks);
-        asset_class_map.insert("ZAG.TO".to_string(), AssetClass::Bonds);
-
-        let mut asset_colour_map = HashMap::new();
-        asset_colour_map.insert(
-            AssetClass::Stocks,
-            Color::TrueColor {
-                r: 245,
-                g: 169,
-                b: 184,
-            },
-        );
-        asset_colour_map.insert(
-            AssetClass::Bonds,
-            Color::TrueColor {
-                r: 91,
-                g: 206,
-                b: 250,
-            },
-        );
-        asset_colour_map.insert(
-            AssetClass::Cash,
-            Color::TrueColor {
-                r: 186,
-                g: 218,
-                b: 85,
-            },
+    /// Symbols missing from `asset_classes` are counted as cash.
+    pub fn new(
+        targets: Targets,
+        layout: Layout,
+        asset_classes: HashMap<String, AssetClass>,
+    ) -> Assets {
+        let asset_colour_map = theme::current().class_colours(
+            asset_classes.values().cloned().chain(
+                targets
+                    .classes()
+                    .into_iter()
+                    .map(|(asset_class, _)| asset_class),
+            ),
         );
 
         Assets {
+            targets,
+            layout,
             total_costs: 0.0,
             total_market_values: 0.0,
-            asset_to_class_map: asset_class_map,
+            realized_pnl: 0.0,
+            cash_balance: 0.0,
+            asset_to_class_map: asset_classes,
             class_to_colour_map: asset_colour_map,
             asset_map: HashMap::new(),
             class_map: HashMap::new(),
+            min_value: None,
         }
     }
 
-    pub fn add_positions(&mut self, positions: &Vec<asset_tracker::Position>) {
+    /// Hides symbols worth less than `min_value` from the composition table. The asset class
+    /// table and exported data still cover every holding.
+    pub fn hiding_below(mut self, min_value: Option<MinValue>) -> Self {
+        self.min_value = min_value;
+        self
+    }
+
+    pub fn total_costs(&self) -> f64 {
+        self.total_costs
+    }
+
+    pub fn total_market_values(&self) -> f64 {
+        self.total_market_values
+    }
+
+    #[cfg(test)]
+    pub fn cash_balance(&self) -> f64 {
+        self.cash_balance
+    }
+
+    /// Market value less book cost across the shares still held.
+    pub fn unrealized_pnl(&self) -> f64 {
+        self.total_market_values - self.total_costs
+    }
+
+    /// Adds `positions` with their values converted to the base currency by multiplying by
+    /// `rate`, so holdings in different currencies can be summed.
+    pub fn add_positions(
+        &mut self,
+        positions: &Vec<asset_tracker::Position>,
+        rate: impl Fn(&asset_tracker::Position) -> f64,
+    ) {
         for position in positions {
-            let book_cost = position.total_cost;
-            let mkt_val = position.current_market_value;
+            let rate = rate(position);
+            let book_cost = position.total_cost * rate;
+            let mkt_val = position.current_market_value * rate;
 
-            self.total_costs += position.total_cost;
-            self.total_market_values += position.current_market_value;
+            self.total_costs += book_cost;
+            self.total_market_values += mkt_val;
+            self.realized_pnl += position.closed_pnl * rate;
 
             self.asset_map
                 .entry(position.symbol.clone())
@@ -96,10 +218,11 @@ impl Assets {
             let asset_class = self
                 .asset_to_class_map
                 .get(&position.symbol)
-                .unwrap_or(&AssetClass::Cash);
+                .cloned()
+                .unwrap_or_else(AssetClass::cash);
 
             self.class_map
-                .entry(asset_class.clone())
+                .entry(asset_class)
                 .and_modify(|(cost, val)| {
                     *cost += book_cost;
                     *val += mkt_val;
@@ -108,12 +231,40 @@ impl Assets {
         }
     }
 
+    /// Adds uninvested account cash, already in the base currency, to the Cash class and a row
+    /// of its own. It counts at face value, so book cost rises by the same amount and P&L is
+    /// unchanged.
+    pub fn add_cash(&mut self, amount: f64) {
+        if amount == 0.0 {
+            return;
+        }
+
+        self.total_costs += amount;
+        self.total_market_values += amount;
+        self.cash_balance += amount;
+        for (cost, val) in [
+            self.asset_map
+                .entry(String::from(AssetClass::CASH))
+                .or_insert((0.0, 0.0)),
+            self.class_map
+                .entry(AssetClass::cash())
+                .or_insert((0.0, 0.0)),
+        ] {
+            *cost += amount;
+            *val += amount;
+        }
+    }
+
     fn colour_symbol(&self, symbol: &String) -> ColoredString {
         let colour = match self.asset_to_class_map.get(symbol) {
             Some(asset_class) => self.class_to_colour_map.get(asset_class),
-            None => self.class_to_colour_map.get(&AssetClass::Cash),
+            None => self.class_to_colour_map.get(&AssetClass::cash()),
         };
 
+        let symbol = match self.layout {
+            Layout::Fixed => truncate_symbol(symbol, SYMBOL_WIDTH),
+            Layout::Auto => symbol.clone(),
+        };
         match colour {
             Some(&colour) => symbol.color(colour),
             None => symbol.normal(),
@@ -128,109 +279,778 @@ impl Assets {
     }
 
     fn colour_percent(&self, percent: f64, asset_class: &AssetClass) -> ColoredString {
-        let percent = (percent * 100.0).round() / 100.0;
-
-        let diff = match asset_class {
-            AssetClass::Stocks => STOCK_TARGET - percent,
-            AssetClass::Bonds => BOND_TARGET - percent,
-            AssetClass::Cash => CASH_TARGET - percent,
-        };
+        let diff = self.targets.target(asset_class) - percent;
 
+        let text = format!("{:.2}", percent);
         match diff.abs() {
-            x if x < MARGIN_OF_WARNING => percent.to_string().green(),
-            x if x >= MARGIN_OF_ERROR => percent.to_string().red(),
-            _ => percent.to_string().yellow(),
+            x if x < self.targets.warning_margin => text.green(),
+            x if x >= self.targets.error_margin => text.red(),
+            _ => text.yellow(),
         }
     }
 
-    fn get_asset_comp(&self) -> Vec<(String, f64, f64)> {
-        let mut asset_comp: Vec<_> = self
+    /// Each symbol's book cost, market value and percent of the portfolio, largest first.
+    fn get_asset_comp(&self) -> Vec<(String, f64, f64, f64)> {
+        with_percents(
+            self.asset_map
+                .iter()
+                .map(|(symbol, (cost, val))| (symbol.clone(), *cost, *val)),
+            self.total_market_values,
+        )
+    }
+
+    /// The composition table's symbol rows, leaving out ones below the minimum value. Unless
+    /// they're excluded from the totals, the hidden symbols' count, book cost, market value and
+    /// percent follow so the rows still add up to the totals.
+    fn shown_asset_comp(&self) -> (Vec<CompRow<String>>, Option<CompRow<usize>>) {
+        let Some(min_value) = self.min_value else {
+            return (self.get_asset_comp(), None);
+        };
+
+        let (mut shown, hidden): (Vec<_>, Vec<_>) = self
             .asset_map
             .iter()
             .map(|(symbol, (cost, val))| (symbol.clone(), *cost, *val))
+            .partition(|(_, _, val)| !min_value.hides(*val));
+        if min_value.exclude_from_totals || hidden.is_empty() {
+            let total = if min_value.exclude_from_totals {
+                shown.iter().map(|(_, _, val)| val).sum()
+            } else {
+                self.total_market_values
+            };
+            return (with_percents(shown.into_iter(), total), None);
+        }
+
+        shown.sort_by(|a, b| b.2.total_cmp(&a.2));
+        let hidden_cost = hidden.iter().map(|(_, cost, _)| cost).sum();
+        let hidden_val = hidden.iter().map(|(_, _, val)| val).sum();
+        let values: Vec<_> = shown
+            .iter()
+            .map(|(_, _, val)| *val)
+            .chain([hidden_val])
             .collect();
+        let mut percents = rounded_percents(&values, self.total_market_values);
+        let hidden_percent = percents.pop().unwrap_or(0.0);
 
-        asset_comp.sort_by(|a, b| b.2.total_cmp(&a.2));
-        asset_comp
+        let rows = shown
+            .into_iter()
+            .zip(percents)
+            .map(|((symbol, cost, val), percent)| (symbol, cost, val, percent))
+            .collect();
+        (
+            rows,
+            Some((hidden.len(), hidden_cost, hidden_val, hidden_percent)),
+        )
     }
 
-    fn get_simplified_comp(&self) -> Vec<(AssetClass, f64, f64)> {
-        let mut simplified_comp: Vec<_> = self
-            .class_map
-            .iter()
-            .map(|(asset_class, (cost, val))| (asset_class.clone(), *cost, *val))
-            .collect();
+    /// Each asset class's book cost, market value and percent of the portfolio, largest first.
+    fn get_simplified_comp(&self) -> Vec<(AssetClass, f64, f64, f64)> {
+        with_percents(
+            self.class_map
+                .iter()
+                .map(|(asset_class, (cost, val))| (asset_class.clone(), *cost, *val)),
+            self.total_market_values,
+        )
+    }
+
+    /// The symbol and asset class compositions shown in the summary tables, largest first.
+    pub fn to_serializable(&self) -> SerializableAssets {
+        let composition =
+            |name: String, book_cost: f64, market_value: f64, percent: f64| Composition {
+                name,
+                book_cost,
+                market_value,
+                percent,
+            };
 
-        simplified_comp.sort_by(|a, b| b.2.total_cmp(&a.2));
-        simplified_comp
+        SerializableAssets {
+            total_book_cost: self.total_costs,
+            total_market_value: self.total_market_values,
+            unrealized_pnl: self.unrealized_pnl(),
+            realized_pnl: self.realized_pnl,
+            symbols: self
+                .get_asset_comp()
+                .into_iter()
+                .map(|(symbol, cost, val, percent)| composition(symbol, cost, val, percent))
+                .collect(),
+            asset_classes: self
+                .get_simplified_comp()
+                .into_iter()
+                .map(|(asset_class, cost, val, percent)| {
+                    composition(String::from(&asset_class), cost, val, percent)
+                })
+                .collect(),
+        }
     }
 
     fn display_asset_comp(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let header = format!(
-            "\n{:<10} | {:<15} | {:<15} | {:>10}\n",
-            "Symbol", "Book Cost", "Market Value", "Percent"
-        );
-        write!(f, "{}", header)?;
-        write!(f, "{}\n", "-".repeat(59))?;
-
-        for (symbol, book_cost, mkt_val) in &self.get_asset_comp() {
-            let percent = mkt_val / self.total_market_values * 100.0;
-            write!(
-                f,
-                "{:<10} | {:<15.2} | {:<15.2} | {:>10.2}\n",
+        let mut table = Table::new(self.layout)
+            .column("Symbol", SYMBOL_WIDTH, Align::Left)
+            .column("Book Cost", 15, Align::Left)
+            .column("Market Value", 15, Align::Left)
+            .column("Percent", 10, Align::Right);
+
+        let (rows, hidden) = self.shown_asset_comp();
+        for (symbol, book_cost, mkt_val, percent) in &rows {
+            table.row(vec![
                 self.colour_symbol(symbol),
-                book_cost,
-                mkt_val,
-                percent
-            )?;
+                format!("{:.2}", book_cost).normal(),
+                format!("{:.2}", mkt_val).normal(),
+                format!("{:.2}", percent).normal(),
+            ]);
+        }
+        if let Some((count, book_cost, mkt_val, percent)) = hidden {
+            table.row(vec![
+                format!("{} hidden", count).dimmed(),
+                format!("{:.2}", book_cost).dimmed(),
+                format!("{:.2}", mkt_val).dimmed(),
+                format!("{:.2}", percent).dimmed(),
+            ]);
         }
-        write!(f, "{}\n", "=".repeat(59))?;
-        write!(
-            f,
-            "{:<10} | {:<15.2} | {:<15.2}\n",
-            "Total", self.total_costs, self.total_market_values
-        )?;
 
-        Ok(())
+        match self.min_value {
+            Some(min_value) if min_value.exclude_from_totals => add_total_row(
+                &mut table,
+                rows.iter().map(|(_, cost, _, _)| cost).sum(),
+                rows.iter().map(|(_, _, val, _)| val).sum(),
+            ),
+            _ => add_total_row(&mut table, self.total_costs, self.total_market_values),
+        }
+
+        write!(f, "\n{}", table)
     }
 
-    fn display_simplified_comp(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let header = format!(
-            "\n{:<10} | {:<15} | {:<15} | {:>10}\n",
-            "Asset", "Book Cost", "Market Value", "Percent"
-        );
-        write!(f, "{}", header)?;
-        write!(f, "{}\n", "-".repeat(59))?;
-
-        for (asset_class, book_cost, mkt_val) in &self.get_simplified_comp() {
-            let percent = mkt_val / self.total_market_values * 100.0;
-            write!(
-                f,
-                "{:<10} | {:<15.2} | {:<15.2} | {:>10}\n",
+    /// Prints just the asset class table, e.g. for a single account's holdings.
+    pub fn display_allocation(&self) {
+        if self.total_market_values == 0.0 {
+            println!("No holdings");
+        } else {
+            print!("{}", self.simplified_comp_table());
+        }
+    }
+
+    fn simplified_comp_table(&self) -> Table {
+        let mut table = Table::new(self.layout)
+            .column("Asset", 10, Align::Left)
+            .column("Book Cost", 15, Align::Left)
+            .column("Market Value", 15, Align::Left)
+            .column("P&L", 12, Align::Left)
+            .column("Percent", 10, Align::Right);
+
+        // Summed from the rounded per-class figures so the total always matches the rows.
+        let mut total_pnl = 0.0;
+        for (asset_class, book_cost, mkt_val, percent) in &self.get_simplified_comp() {
+            let pnl = ((mkt_val - book_cost) * 100.0).round() / 100.0;
+            total_pnl += pnl;
+            table.row(vec![
                 self.colour_asset(asset_class),
-                book_cost,
-                mkt_val,
-                self.colour_percent(percent, asset_class)
-            )?;
+                format!("{:.2}", book_cost).normal(),
+                format!("{:.2}", mkt_val).normal(),
+                colour_pnl(pnl),
+                self.colour_percent(*percent, asset_class),
+            ]);
         }
-        write!(f, "{}\n", "=".repeat(59))?;
-        write!(
+        table.rule();
+        table.row(vec![
+            "Total".normal(),
+            format!("{:.2}", self.total_costs).normal(),
+            format!("{:.2}", self.total_market_values).normal(),
+            colour_pnl(total_pnl),
+        ]);
+
+        table
+    }
+
+    fn display_pnl(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f)?;
+        writeln!(
             f,
-            "{:<10} | {:<15.2} | {:<15.2}\n",
-            "Total", self.total_costs, self.total_market_values
+            "{:<26} {}",
+            "Unrealized P&L:",
+            colour_pnl(self.unrealized_pnl())
         )?;
+        writeln!(
+            f,
+            "{:<26} {}",
+            "Realized P&L (sold today):",
+            colour_pnl(self.realized_pnl)
+        )?;
+        writeln!(
+            f,
+            "{:<26} {}",
+            "Total P&L:",
+            colour_pnl(self.unrealized_pnl() + self.realized_pnl)
+        )
+    }
+}
 
-        Ok(())
+/// Formats a dollar gain or loss to the cent in the theme's gain or loss colour.
+pub fn colour_pnl(pnl: f64) -> ColoredString {
+    theme::current().gain_or_loss(format!("{:.2}", pnl), pnl)
+}
+
+fn add_total_row(table: &mut Table, book_cost: f64, market_value: f64) {
+    table.rule();
+    table.row(vec![
+        "Total".normal(),
+        format!("{:.2}", book_cost).normal(),
+        format!("{:.2}", market_value).normal(),
+    ]);
+}
+
+/// Sorts `(name, book cost, market value)` rows largest first and adds each one's share of
+/// `total` as a percent.
+fn with_percents<T>(
+    rows: impl Iterator<Item = (T, f64, f64)>,
+    total: f64,
+) -> Vec<(T, f64, f64, f64)> {
+    let mut rows: Vec<_> = rows.collect();
+    rows.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+    let values: Vec<_> = rows.iter().map(|(_, _, val)| *val).collect();
+    rows.into_iter()
+        .zip(rounded_percents(&values, total))
+        .map(|((name, cost, val), percent)| (name, cost, val, percent))
+        .collect()
+}
+
+/// Each value's share of `total` as a percent rounded down to hundredths, with the hundredths
+/// lost to rounding handed back to the values with the largest remainders so the shares add
+/// up to exactly 100.
+fn rounded_percents(values: &[f64], total: f64) -> Vec<f64> {
+    if total == 0.0 {
+        return vec![0.0; values.len()];
+    }
+
+    let exact: Vec<_> = values.iter().map(|val| val / total * 10_000.0).collect();
+    let mut hundredths: Vec<_> = exact.iter().map(|share| share.floor() as i64).collect();
+    let leftover = 10_000 - hundredths.iter().sum::<i64>();
+
+    let mut by_remainder: Vec<_> = (0..values.len()).collect();
+    by_remainder
+        .sort_by(|&a, &b| (exact[b] - exact[b].floor()).total_cmp(&(exact[a] - exact[a].floor())));
+    for &i in by_remainder.iter().take(leftover.max(0) as usize) {
+        hundredths[i] += 1;
+    }
+
+    hundredths
+        .into_iter()
+        .map(|share| share as f64 / 100.0)
+        .collect()
+}
+
+/// How far one asset class is from its target, in dollars.
+pub struct Rebalance {
+    asset_class: AssetClass,
+    current_value: f64,
+    current_percent: f64,
+    target_percent: f64,
+    target_value: f64,
+    delta: f64,
+}
+
+impl Assets {
+    /// The market value held in `asset_class`, or 0 if none is held.
+    pub fn class_market_value(&self, asset_class: &AssetClass) -> f64 {
+        self.class_map.get(asset_class).map_or(0.0, |(_, val)| *val)
+    }
+
+    /// Works out how much of each asset class to buy (positive delta) or sell (negative)
+    /// to reach `targets`. Idle account cash isn't a Cash holding to rebalance; with
+    /// `deploy_cash` it's invested along the way, otherwise it's left out of the total.
+    /// Held classes without a target are sold off.
+    pub fn rebalance(&self, targets: &Targets, deploy_cash: bool) -> Vec<Rebalance> {
+        let idle_cash = self.cash_balance;
+        let total = if deploy_cash {
+            self.total_market_values
+        } else {
+            self.total_market_values - idle_cash
+        };
+
+        let mut classes = targets.classes();
+        let mut untargeted: Vec<_> = self
+            .class_map
+            .keys()
+            .filter(|held| classes.iter().all(|(asset_class, _)| asset_class != *held))
+            .cloned()
+            .collect();
+        untargeted.sort();
+        classes.extend(untargeted.into_iter().map(|asset_class| (asset_class, 0.0)));
+
+        classes
+            .into_iter()
+            .map(|(asset_class, target_percent)| {
+                let mut current_value = self.class_market_value(&asset_class);
+                if asset_class.name() == AssetClass::CASH {
+                    current_value -= idle_cash;
+                }
+                let current_percent = if total == 0.0 {
+                    0.0
+                } else {
+                    current_value / total * 100.0
+                };
+                let target_value = target_percent / 100.0 * total;
+
+                Rebalance {
+                    asset_class,
+                    current_value,
+                    current_percent,
+                    target_percent,
+                    target_value,
+                    delta: target_value - current_value,
+                }
+            })
+            .collect()
+    }
+
+    /// Prints the buy/sell suggestion per asset class, with `deploy_cash` investing the idle
+    /// account cash along the way, and with `explain` the arithmetic behind it.
+    pub fn display_rebalance(&self, explain: bool, deploy_cash: bool) {
+        let idle_cash = self.cash_balance;
+        let title = format!("{}Rebalance{}", "-".repeat(25), "-".repeat(25));
+        println!("{}", title.cyan());
+        println!();
+
+        let rebalance = self.rebalance(&self.targets, deploy_cash);
+
+        if explain {
+            println!(
+                "{:<10} | {:<15} | {:<10} | {:<10} | {:<15} | {:>15}",
+                "Asset", "Current Value", "Current %", "Target %", "Target Value", "Delta"
+            );
+            println!("{}", "-".repeat(89));
+            for row in &rebalance {
+                println!(
+                    "{:<10} | {:<15.2} | {:<10.2} | {:<10.2} | {:<15.2} | {:>15.2}",
+                    self.colour_asset(&row.asset_class),
+                    row.current_value,
+                    row.current_percent,
+                    row.target_percent,
+                    row.target_value,
+                    row.delta
+                );
+            }
+            println!("{}", "=".repeat(89));
+            if idle_cash == 0.0 {
+                println!(
+                    "Target value = target % × total market value ({:.2}); delta = target value − current value",
+                    self.total_market_values
+                );
+            } else if deploy_cash {
+                println!(
+                    "Target value = target % × total market value ({:.2}, including {:.2} idle cash); delta = target value − current value",
+                    self.total_market_values, idle_cash
+                );
+            } else {
+                println!(
+                    "Target value = target % × total market value ({:.2}, excluding {:.2} idle cash); delta = target value − current value",
+                    self.total_market_values - idle_cash, idle_cash
+                );
+            }
+            println!();
+        }
+
+        for row in &rebalance {
+            match row.delta {
+                delta if delta.abs() < 0.01 => {
+                    println!("{}: on target", self.colour_asset(&row.asset_class))
+                }
+                delta if delta > 0.0 => println!(
+                    "Buy ${:.2} of {}",
+                    delta,
+                    self.colour_asset(&row.asset_class)
+                ),
+                delta => println!(
+                    "Sell ${:.2} of {}",
+                    -delta,
+                    self.colour_asset(&row.asset_class)
+                ),
+            }
+        }
+        if deploy_cash && idle_cash != 0.0 {
+            println!("Deploys ${:.2} of idle cash", idle_cash);
+        }
+        println!();
+    }
+
+    /// Prints the buy (+) or sell (-) amount per asset class under each named strategy, one
+    /// column per strategy.
+    pub fn display_rebalance_comparison(&self, strategies: &[(&str, Targets)]) {
+        let width = 11 + strategies.len() * 23;
+        let title = format!("{}Rebalance Comparison{}", "-".repeat(20), "-".repeat(20));
+        println!("{}", title.cyan());
+        println!();
+
+        print!("{:<10}", "Asset");
+        for (name, _) in strategies {
+            print!(" | {:>20}", name);
+        }
+        println!();
+        println!("{}", "-".repeat(width));
+
+        let plans: Vec<_> = strategies
+            .iter()
+            .map(|(_, targets)| self.rebalance(targets, false))
+            .collect();
+
+        // Strategies can target different classes, so list every class any of them mentions.
+        let mut classes: Vec<&AssetClass> = Vec::new();
+        for row in plans.iter().flatten() {
+            if !classes.contains(&&row.asset_class) {
+                classes.push(&row.asset_class);
+            }
+        }
+
+        for asset_class in classes {
+            print!("{:<10}", self.colour_asset(asset_class));
+            for plan in &plans {
+                let cell = match plan.iter().find(|row| &row.asset_class == asset_class) {
+                    Some(row) => format!("{:+.2} ({}%)", row.delta, row.target_percent),
+                    None => String::from("+0.00 (0%)"),
+                };
+                print!(" | {:>20}", cell);
+            }
+            println!();
+        }
+        println!("{}", "=".repeat(width));
+        println!();
     }
 }
 
 impl fmt::Display for Assets {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let title = format!("{}Portfolio Summary{}", "-".repeat(21), "-".repeat(21));
-        write!(f, "{}\n", title.cyan())?;
+        writeln!(f, "{}", title.cyan())?;
+        if self.total_market_values == 0.0 {
+            return writeln!(f, "\nNo holdings yet");
+        }
         self.display_asset_comp(f)?;
-        self.display_simplified_comp(f)?;
+        write!(f, "\n{}", self.simplified_comp_table())?;
+        self.display_pnl(f)?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(symbol: &str, value: f64) -> asset_tracker::Position {
+        serde_json::from_value(serde_json::json!({
+            "symbol": symbol,
+            "symbolId": 1,
+            "openQuantity": 1.0,
+            "closedQuantity": 0.0,
+            "currentMarketValue": value,
+            "currentPrice": value,
+            "averageEntryPrice": value,
+            "closedPnl": 0.0,
+            "openPnl": 0.0,
+            "totalCost": value,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_asset_classes_file() {
+        let asset_classes: HashMap<String, AssetClass> = toml::from_str(
+            r#"
+            "VEQT.TO" = "Stocks"
+            "VAB.TO" = "Bonds"
+            "#,
+        )
+        .unwrap();
+
+        let mut assets = Assets::new(Targets::default(), Layout::Fixed, asset_classes);
+        assets.add_positions(
+            &vec![
+                position("VEQT.TO", 100.0),
+                position("VAB.TO", 50.0),
+                position("CASH.TO", 25.0),
+            ],
+            |_| 1.0,
+        );
+
+        assert_eq!(assets.class_map[&AssetClass::stocks()], (100.0, 100.0));
+        assert_eq!(assets.class_map[&AssetClass::bonds()], (50.0, 50.0));
+        assert_eq!(assets.class_map[&AssetClass::cash()], (25.0, 25.0));
+    }
+
+    #[test]
+    fn serializable_composition_matches_summary() {
+        let mut assets = Assets::new(Targets::default(), Layout::Fixed, default_asset_classes());
+        assets.add_positions(
+            &vec![position("XEQT.TO", 300.0), position("ZAG.TO", 100.0)],
+            |_| 1.0,
+        );
+
+        let serializable = assets.to_serializable();
+        assert_eq!(serializable.total_market_value, 400.0);
+        assert_eq!(
+            serializable.asset_classes[0],
+            Composition {
+                name: String::from("Stocks"),
+                book_cost: 300.0,
+                market_value: 300.0,
+                percent: 75.0,
+            }
+        );
+        assert_eq!(serializable.symbols[1].name, "ZAG.TO");
+        assert_eq!(serializable.symbols[1].percent, 25.0);
+    }
+
+    #[test]
+    fn printed_class_percentages_total_100() {
+        colored::control::set_override(false);
+        let asset_classes: HashMap<String, AssetClass> = toml::from_str(
+            r#"
+            "XEQT.TO" = "Stocks"
+            "ZAG.TO" = "Bonds"
+            "#,
+        )
+        .unwrap();
+        let mut assets = Assets::new(Targets::default(), Layout::Fixed, asset_classes);
+        assets.add_positions(
+            &vec![
+                position("XEQT.TO", 100.0),
+                position("ZAG.TO", 100.0),
+                position("CASH.TO", 100.0),
+            ],
+            |_| 1.0,
+        );
+
+        let printed = assets.to_string();
+        let class_rows = printed
+            .lines()
+            .skip_while(|line| !line.starts_with("Asset"))
+            .skip(2)
+            .take_while(|line| !line.starts_with('='));
+        let total_hundredths: i64 = class_rows
+            .map(|line| {
+                let percent = line.rsplit('|').next().unwrap().trim();
+                percent.replace('.', "").parse::<i64>().unwrap()
+            })
+            .sum();
+
+        assert_eq!(total_hundredths, 10_000);
+    }
+
+    #[test]
+    fn class_pnl_adds_up_to_the_total() {
+        colored::control::set_override(false);
+        let mut stocks = position("XEQT.TO", 300.0);
+        stocks.total_cost = 250.004;
+        let mut bonds = position("ZAG.TO", 100.0);
+        bonds.total_cost = 120.004;
+        let mut assets = Assets::new(Targets::default(), Layout::Fixed, default_asset_classes());
+        assets.add_positions(&vec![stocks, bonds], |_| 1.0);
+
+        let printed = assets.simplified_comp_table().to_string();
+        let pnl = |row: &str| -> f64 {
+            let line = printed.lines().find(|line| line.starts_with(row)).unwrap();
+            line.split('|').nth(3).unwrap().trim().parse().unwrap()
+        };
+
+        assert_eq!(pnl("Stocks"), 50.0);
+        assert_eq!(pnl("Bonds"), -20.0);
+        assert_eq!(pnl("Total"), 30.0);
+    }
+
+    #[test]
+    fn empty_portfolio_has_no_nan_percentages() {
+        let assets = Assets::new(Targets::default(), Layout::Fixed, default_asset_classes());
+
+        let printed = assets.to_string();
+        assert!(printed.contains("No holdings yet"));
+        assert!(!printed.contains("NaN"));
+        assert!(assets
+            .to_serializable()
+            .asset_classes
+            .iter()
+            .all(|class| !class.percent.is_nan()));
+        assert!(assets
+            .rebalance(&Targets::default(), false)
+            .iter()
+            .all(|row| !row.current_percent.is_nan()));
+    }
+
+    #[test]
+    fn symbols_below_min_value_are_hidden_but_counted() {
+        let positions = vec![
+            position("XEQT.TO", 900.0),
+            position("ZAG.TO", 60.0),
+            position("VFV.TO", 40.0),
+        ];
+        let hiding = |exclude_from_totals| {
+            let mut assets =
+                Assets::new(Targets::default(), Layout::Fixed, default_asset_classes())
+                    .hiding_below(Some(MinValue {
+                        amount: 100.0,
+                        exclude_from_totals,
+                    }));
+            assets.add_positions(&positions, |_| 1.0);
+            assets
+        };
+
+        let (rows, hidden) = hiding(false).shown_asset_comp();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].3, 90.0);
+        assert_eq!(hidden, Some((2, 100.0, 100.0, 10.0)));
+
+        let (rows, hidden) = hiding(true).shown_asset_comp();
+        assert_eq!(rows[0].3, 100.0);
+        assert_eq!(hidden, None);
+        assert_eq!(hiding(true).to_serializable().symbols.len(), 3);
+    }
+
+    #[test]
+    fn cash_counts_toward_the_cash_class_at_face_value() {
+        let mut held = position("XEQT.TO", 300.0);
+        held.total_cost = 250.0;
+        let mut assets = Assets::new(Targets::default(), Layout::Fixed, default_asset_classes());
+        assets.add_positions(&vec![held], |_| 1.0);
+        assets.add_cash(100.0);
+        assets.add_cash(0.0);
+
+        let serializable = assets.to_serializable();
+        assert_eq!(serializable.total_market_value, 400.0);
+        assert_eq!(serializable.unrealized_pnl, 50.0);
+        assert_eq!(serializable.symbols.len(), 2);
+        assert_eq!(assets.class_market_value(&AssetClass::cash()), 100.0);
+    }
+
+    #[test]
+    fn realized_and_unrealized_pnl_are_kept_apart() {
+        let mut held = position("XEQT.TO", 300.0);
+        held.total_cost = 250.0;
+        let mut partly_sold = position("ZAG.TO", 100.0);
+        partly_sold.closed_pnl = -20.0;
+
+        let mut assets = Assets::new(Targets::default(), Layout::Fixed, default_asset_classes());
+        assets.add_positions(&vec![held, partly_sold], |_| 2.0);
+
+        assert_eq!(assets.unrealized_pnl(), 100.0);
+        assert_eq!(assets.realized_pnl, -40.0);
+        let serializable = assets.to_serializable();
+        assert_eq!(serializable.unrealized_pnl, 100.0);
+        assert_eq!(serializable.realized_pnl, -40.0);
+    }
+
+    #[test]
+    fn long_symbols_are_truncated_with_ellipsis() {
+        assert_eq!(truncate_symbol("XEQT.TO", SYMBOL_WIDTH), "XEQT.TO");
+        assert_eq!(truncate_symbol("ABCDEFGHIJ", SYMBOL_WIDTH), "ABCDEFGHIJ");
+        assert_eq!(
+            truncate_symbol("SPY17Jan25C500.00", SYMBOL_WIDTH),
+            "SPY17Jan2…"
+        );
+    }
+
+    #[test]
+    fn rebalance_deltas_move_each_class_to_target() {
+        let mut assets = Assets::new(Targets::default(), Layout::Fixed, default_asset_classes());
+        assets.add_positions(
+            &vec![position("XEQT.TO", 7000.0), position("ZAG.TO", 3000.0)],
+            |_| 1.0,
+        );
+
+        let rebalance = assets.rebalance(&Targets::default(), false);
+
+        assert_eq!(rebalance[0].current_percent, 70.0);
+        assert_eq!(rebalance[0].target_value, 5000.0);
+        assert_eq!(rebalance[0].delta, -2000.0);
+        assert_eq!(rebalance[1].delta, 2000.0);
+        assert_eq!(rebalance[2].delta, 0.0);
+    }
+
+    #[test]
+    fn rebalance_deploys_idle_cash() {
+        let mut assets = Assets::new(Targets::default(), Layout::Fixed, default_asset_classes());
+        assets.add_positions(
+            &vec![position("XEQT.TO", 6000.0), position("ZAG.TO", 2000.0)],
+            |_| 1.0,
+        );
+        assets.add_cash(2000.0);
+        assert_eq!(assets.class_market_value(&AssetClass::bonds()), 2000.0);
+        assert_eq!(assets.class_market_value(&AssetClass::cash()), 2000.0);
+
+        let rebalance = assets.rebalance(&Targets::default(), true);
+
+        assert_eq!(rebalance[0].target_value, 5000.0);
+        assert_eq!(rebalance[0].delta, -1000.0);
+        assert_eq!(rebalance[1].delta, 3000.0);
+        assert_eq!(rebalance[2].current_value, 0.0);
+    }
+
+    #[test]
+    fn rebalance_leaves_idle_cash_alone_unless_deployed() {
+        let mut assets = Assets::new(Targets::default(), Layout::Fixed, default_asset_classes());
+        assets.add_positions(
+            &vec![position("XEQT.TO", 6000.0), position("ZAG.TO", 2000.0)],
+            |_| 1.0,
+        );
+        assets.add_cash(2000.0);
+
+        let rebalance = assets.rebalance(&Targets::default(), false);
+
+        assert_eq!(rebalance[0].current_percent, 75.0);
+        assert_eq!(rebalance[0].target_value, 4000.0);
+        assert_eq!(rebalance[0].delta, -2000.0);
+        assert_eq!(rebalance[1].delta, 2000.0);
+        assert_eq!(rebalance[2].current_value, 0.0);
+        assert_eq!(rebalance[2].delta, 0.0);
+    }
+
+    #[test]
+    fn rebalance_covers_configured_and_untargeted_classes() {
+        let asset_classes: HashMap<String, AssetClass> = toml::from_str(
+            r#"
+            "XEQT.TO" = "Stocks"
+            "VRE.TO" = "REITs"
+            "BTCC.TO" = "Crypto"
+            "#,
+        )
+        .unwrap();
+        let targets: Targets = toml::from_str(
+            r#"
+            stocks = 80.0
+            bonds = 0.0
+            cash = 0.0
+
+            [classes]
+            REITs = 20.0
+            "#,
+        )
+        .unwrap();
+
+        let mut assets = Assets::new(targets.clone(), Layout::Fixed, asset_classes);
+        assets.add_positions(
+            &vec![
+                position("XEQT.TO", 700.0),
+                position("VRE.TO", 200.0),
+                position("BTCC.TO", 100.0),
+            ],
+            |_| 1.0,
+        );
+
+        let rebalance = assets.rebalance(&targets, false);
+        let deltas: Vec<_> = rebalance
+            .iter()
+            .map(|row| (row.asset_class.name(), row.delta))
+            .collect();
+        assert_eq!(
+            deltas,
+            vec![
+                ("Stocks", 100.0),
+                ("Bonds", 0.0),
+                ("Cash", 0.0),
+                ("REITs", 0.0),
+                ("Crypto", -100.0),
+            ]
+        );
+        assert!(assets
+            .class_to_colour_map
+            .contains_key(&AssetClass::new("Crypto")));
+    }
+}
@@ -0,0 +1,516 @@
+use crate::{
+    asset_tracker::ErrorPolicy,
+    assets::{self, AssetClass, MinValue},
+    db, questrade_api,
+    table::Layout,
+    theme::ThemeName,
+};
+use chrono::TimeDelta;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+pub const DEFAULT_CONFIG_PATH: &str = "questrade_asset_tracker.toml";
+/// The asset classes file looked for next to the database unless configured otherwise.
+const ASSET_CLASSES_FILE_NAME: &str = "asset_classes.toml";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    IOError(std::io::Error),
+    TOMLError(toml::de::Error),
+    InvalidTargets(String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigError::IOError(err) => write!(f, "IO error: {}", err),
+            ConfigError::TOMLError(err) => write!(f, "TOML error: {}", err),
+            ConfigError::InvalidTargets(name) => {
+                write!(f, "the targets in {} must add up to 100", name)
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(err: std::io::Error) -> Self {
+        ConfigError::IOError(err)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> Self {
+        ConfigError::TOMLError(err)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    aliases: HashMap<String, String>,
+    pub profiles: BTreeMap<String, Profile>,
+    pub display_currency: String,
+    pub targets: Targets,
+    pub strategies: BTreeMap<String, Targets>,
+    request_pacing_ms: u64,
+    /// How many times a failed request is retried before giving up.
+    pub request_retries: u32,
+    database_max_connections: u32,
+    database_busy_timeout_ms: u64,
+    symbol_cache_hours: i64,
+    pub tax: Tax,
+    pub columns_width: Layout,
+    pub theme: ThemeName,
+    pub colours: Colours,
+    pub extended_quotes: Option<ExtendedQuotes>,
+    /// Defaults to `asset_classes.toml` next to the database.
+    asset_classes_path: Option<PathBuf>,
+    /// Loaded from `asset_classes_path` rather than the config file itself.
+    #[serde(skip)]
+    pub asset_classes: HashMap<String, AssetClass>,
+    /// Set from `--fail-fast`/`--best-effort` rather than the config file.
+    #[serde(skip)]
+    pub error_policy: ErrorPolicy,
+    /// Set from `--refresh-symbols` rather than the config file.
+    #[serde(skip)]
+    pub refresh_symbols: bool,
+    /// Set from `--account-type` rather than the config file.
+    #[serde(skip)]
+    pub account_type: Option<String>,
+    /// Set from `--min-value` rather than the config file.
+    #[serde(skip)]
+    pub min_value: Option<f64>,
+    /// Set from `--exclude-hidden` rather than the config file.
+    #[serde(skip)]
+    pub exclude_hidden: bool,
+    /// Set from `--mask-accounts` rather than the config file.
+    #[serde(skip)]
+    pub mask_accounts: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            aliases: HashMap::new(),
+            profiles: BTreeMap::new(),
+            display_currency: String::from("CAD"),
+            targets: Targets::default(),
+            strategies: BTreeMap::new(),
+            request_pacing_ms: 100,
+            request_retries: questrade_api::DEFAULT_RETRIES,
+            database_max_connections: db::PoolOptions::default().max_connections,
+            database_busy_timeout_ms: db::PoolOptions::default().busy_timeout.as_millis() as u64,
+            symbol_cache_hours: 24,
+            tax: Tax::default(),
+            columns_width: Layout::default(),
+            theme: ThemeName::default(),
+            colours: Colours::default(),
+            extended_quotes: None,
+            asset_classes_path: None,
+            asset_classes: HashMap::new(),
+            error_policy: ErrorPolicy::default(),
+            refresh_symbols: false,
+            account_type: None,
+            min_value: None,
+            exclude_hidden: false,
+            mask_accounts: false,
+        }
+    }
+}
+
+/// Colours that replace the theme's, as names like `blue` or hex codes like `#1f77b4`.
+/// `classes` maps asset class names to their colour in the allocation tables.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Colours {
+    pub gain: Option<String>,
+    pub loss: Option<String>,
+    pub classes: HashMap<String, String>,
+}
+
+/// Rates used to estimate tax on unrealized gains in non-registered accounts, as percents.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct Tax {
+    pub marginal_rate: Option<f64>,
+    pub inclusion_rate: f64,
+}
+
+impl Default for Tax {
+    fn default() -> Self {
+        Tax {
+            marginal_rate: None,
+            inclusion_rate: 50.0,
+        }
+    }
+}
+
+/// Target allocation percentages per asset class, and how far off target (in percentage
+/// points) an allocation can drift before it's shown as a warning (yellow) or error (red).
+/// Other asset classes are targeted by name in a `classes` table, e.g. `REITs = 10.0` under
+/// `[targets.classes]`. Unknown keys are rejected so a typo like `stoks` isn't taken for a
+/// class of its own.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Targets {
+    #[serde(default)]
+    pub stocks: f64,
+    #[serde(default)]
+    pub bonds: f64,
+    #[serde(default)]
+    pub cash: f64,
+    #[serde(default = "default_warning_margin")]
+    pub warning_margin: f64,
+    #[serde(default = "default_error_margin")]
+    pub error_margin: f64,
+    #[serde(rename = "classes", default)]
+    pub other: BTreeMap<String, f64>,
+}
+
+fn default_warning_margin() -> f64 {
+    2.5
+}
+
+fn default_error_margin() -> f64 {
+    5.0
+}
+
+impl Default for Targets {
+    fn default() -> Self {
+        Targets {
+            stocks: 50.0,
+            bonds: 50.0,
+            cash: 0.0,
+            warning_margin: default_warning_margin(),
+            error_margin: default_error_margin(),
+            other: BTreeMap::new(),
+        }
+    }
+}
+
+impl Targets {
+    pub fn sum_to_100(&self) -> bool {
+        let total: f64 = self.classes().iter().map(|(_, target)| target).sum();
+        (total - 100.0).abs() < 0.01
+    }
+
+    /// Every targeted asset class, the built-in three first.
+    pub fn classes(&self) -> Vec<(AssetClass, f64)> {
+        let mut classes = vec![
+            (AssetClass::stocks(), self.stocks),
+            (AssetClass::bonds(), self.bonds),
+            (AssetClass::cash(), self.cash),
+        ];
+        classes.extend(
+            self.other
+                .iter()
+                .map(|(name, target)| (AssetClass::new(name), *target)),
+        );
+        classes
+    }
+
+    /// The target for `asset_class`, 0 if it has none.
+    pub fn target(&self, asset_class: &AssetClass) -> f64 {
+        match asset_class.name() {
+            AssetClass::STOCKS => self.stocks,
+            AssetClass::BONDS => self.bonds,
+            AssetClass::CASH => self.cash,
+            name => self.other.get(name).copied().unwrap_or(0.0),
+        }
+    }
+}
+
+/// A secondary quote source used outside regular hours, since Questrade's `currentPrice` doesn't
+/// move after the close. `url` is requested with `{symbol}` replaced by each held symbol and
+/// should return JSON like `{"price": 31.2}`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExtendedQuotes {
+    pub url: String,
+}
+
+/// A separate Questrade login, e.g. a spouse's, kept in its own database. A relative
+/// `db_path` is taken from the config file's directory.
+#[derive(Debug, Deserialize)]
+pub struct Profile {
+    pub db_path: PathBuf,
+}
+
+impl Config {
+    /// Loads the config at `path` and the asset classes file it points to, or the one next to
+    /// the database at `db_path`, falling back to the defaults for either if the file doesn't
+    /// exist.
+    pub fn load(path: &Path, db_path: &Path) -> Result<Self, ConfigError> {
+        let contents = if path.exists() {
+            std::fs::read_to_string(path)?
+        } else {
+            String::new()
+        };
+
+        let mut config = Self::parse(&contents)?;
+        let asset_classes_path = match &config.asset_classes_path {
+            Some(path) => path.clone(),
+            None => db_path
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join(ASSET_CLASSES_FILE_NAME),
+        };
+        config.asset_classes = assets::load_asset_classes(&asset_classes_path)?;
+
+        let config_dir = path.parent().unwrap_or(Path::new(""));
+        for profile in config.profiles.values_mut() {
+            if profile.db_path.is_relative() {
+                profile.db_path = config_dir.join(&profile.db_path);
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let mut config = toml::from_str::<Config>(contents)?;
+
+        for (alias, command) in default_aliases() {
+            config.aliases.entry(alias).or_insert(command);
+        }
+
+        if !config.targets.sum_to_100() {
+            return Err(ConfigError::InvalidTargets(String::from("[targets]")));
+        }
+        if let Some((name, _)) = config
+            .strategies
+            .iter()
+            .find(|(_, targets)| !targets.sum_to_100())
+        {
+            return Err(ConfigError::InvalidTargets(format!(
+                "[strategies.{}]",
+                name
+            )));
+        }
+
+        Ok(config)
+    }
+
+    /// The minimum gap between requests while loading data, 100ms (10 requests a second)
+    /// unless configured otherwise.
+    pub fn request_pacing(&self) -> Duration {
+        Duration::from_millis(self.request_pacing_ms)
+    }
+
+    /// The database pool's size and lock wait, 5 connections waiting up to 5s unless
+    /// configured otherwise.
+    pub fn database_options(&self) -> db::PoolOptions {
+        db::PoolOptions {
+            max_connections: self.database_max_connections,
+            busy_timeout: Duration::from_millis(self.database_busy_timeout_ms),
+        }
+    }
+
+    /// How long cached symbol details are reused, 24 hours unless configured otherwise, or
+    /// not at all with `--refresh-symbols`.
+    pub fn symbol_cache_max_age(&self) -> TimeDelta {
+        if self.refresh_symbols {
+            TimeDelta::zero()
+        } else {
+            TimeDelta::hours(self.symbol_cache_hours)
+        }
+    }
+
+    /// Hides holdings worth less than `amount`, or `--min-value` if it's not given.
+    pub fn hide_below(&self, amount: Option<f64>) -> Option<MinValue> {
+        amount.or(self.min_value).map(|amount| MinValue {
+            amount,
+            exclude_from_totals: self.exclude_hidden,
+        })
+    }
+
+    /// Expands the first word of `input` if it's an alias, keeping any arguments after it.
+    /// Input that doesn't start with an alias is returned unchanged.
+    pub fn resolve_alias(&self, input: &str) -> String {
+        let (first, rest) = match input.split_once(char::is_whitespace) {
+            Some((first, rest)) => (first, Some(rest)),
+            None => (input, None),
+        };
+
+        match (self.aliases.get(first), rest) {
+            (Some(command), Some(rest)) => format!("{} {}", command, rest),
+            (Some(command), None) => command.clone(),
+            (None, _) => input.to_string(),
+        }
+    }
+}
+
+fn default_aliases() -> HashMap<String, String> {
+    [
+        ("p", "positions"),
+        ("s", "summary"),
+        ("a", "accounts"),
+        ("h", "home"),
+    ]
+    .into_iter()
+    .map(|(alias, command)| (alias.to_string(), command.to_string()))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_profiles() {
+        let config = Config::parse(
+            r#"
+            [profiles.me]
+            db_path = "me.db"
+
+            [profiles.spouse]
+            db_path = "spouse.db"
+            "#,
+        )
+        .unwrap();
+
+        let names: Vec<_> = config.profiles.keys().collect();
+        assert_eq!(names, vec!["me", "spouse"]);
+        assert_eq!(
+            config.profiles["spouse"].db_path,
+            PathBuf::from("spouse.db")
+        );
+    }
+
+    #[test]
+    fn parses_named_strategies() {
+        let config = Config::parse(
+            r#"
+            [strategies.conservative]
+            stocks = 40.0
+            bonds = 55.0
+            cash = 5.0
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.strategies["conservative"].bonds, 55.0);
+        assert_eq!(config.targets, Targets::default());
+    }
+
+    #[test]
+    fn rejects_targets_not_adding_up_to_100() {
+        let config = Config::parse(
+            r#"
+            [targets]
+            stocks = 80.0
+            bonds = 30.0
+            cash = 0.0
+            "#,
+        );
+        assert!(matches!(config, Err(ConfigError::InvalidTargets(_))));
+
+        let config = Config::parse(
+            r#"
+            [targets]
+            stocks = 80.0
+            bonds = 20.0
+            cash = 0.0
+            error_margin = 10.0
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.targets.warning_margin, 2.5);
+        assert_eq!(config.targets.error_margin, 10.0);
+    }
+
+    #[test]
+    fn parses_targets_for_extra_asset_classes() {
+        let config = Config::parse(
+            r#"
+            [targets]
+            stocks = 70.0
+            bonds = 20.0
+
+            [targets.classes]
+            REITs = 10.0
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.targets.target(&AssetClass::new("REITs")), 10.0);
+        assert_eq!(config.targets.target(&AssetClass::cash()), 0.0);
+        assert_eq!(config.targets.target(&AssetClass::new("Crypto")), 0.0);
+        assert_eq!(config.targets.classes().len(), 4);
+    }
+
+    #[test]
+    fn asset_classes_file_defaults_to_next_to_the_database() {
+        let dir = std::env::temp_dir().join(format!(
+            "questrade_asset_tracker_config_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("asset_classes.toml"), r#""VRE.TO" = "REITs""#).unwrap();
+
+        let config = Config::load(&dir.join("missing.toml"), &dir.join("tracker.db")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(config.asset_classes["VRE.TO"], AssetClass::new("REITs"));
+    }
+
+    #[test]
+    fn profile_databases_are_found_next_to_the_config() {
+        let dir = std::env::temp_dir().join(format!(
+            "questrade_asset_tracker_profiles_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("config.toml");
+        std::fs::write(
+            &config_path,
+            "[profiles.me]\ndb_path = \"me.db\"\n\n[profiles.spouse]\ndb_path = \"/data/spouse.db\"\n",
+        )
+        .unwrap();
+
+        let config = Config::load(&config_path, &dir.join("tracker.db")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(config.profiles["me"].db_path, dir.join("me.db"));
+        assert_eq!(
+            config.profiles["spouse"].db_path,
+            PathBuf::from("/data/spouse.db")
+        );
+    }
+
+    #[test]
+    fn rejects_misspelled_targets() {
+        let config = Config::parse(
+            r#"
+            [targets]
+            stoks = 80.0
+            bonds = 20.0
+            "#,
+        );
+
+        assert!(matches!(config, Err(ConfigError::TOMLError(_))));
+    }
+
+    #[test]
+    fn resolves_default_and_configured_aliases() {
+        let config = Config::parse(
+            r#"
+            [aliases]
+            p = "positions account 123"
+            acc = "accounts"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.resolve_alias("p"), "positions account 123");
+        assert_eq!(config.resolve_alias("acc"), "accounts");
+        assert_eq!(config.resolve_alias("s"), "summary");
+        assert_eq!(config.resolve_alias("h extra args"), "home extra args");
+        assert_eq!(config.resolve_alias("summary"), "summary");
+        assert_eq!(config.resolve_alias("unknown"), "unknown");
+    }
+}
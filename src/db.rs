@@ -1,51 +1,299 @@
-use sqlx::{migrate::MigrateDatabase, FromRow};
+use crate::asset_tracker::{
+    Account, AccountID, Balance, Balances, Dataset, Position, Symbol, SymbolID,
+};
+use chrono::{DateTime, Utc};
+use sqlx::{
+    migrate::MigrateDatabase,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions},
+    FromRow, Sqlite, Transaction,
+};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
 
-const DB_URL: &str = "sqlite://questrade_asset_tracker.db";
+const DB_FILE_NAME: &str = "questrade_asset_tracker.db";
+
+/// Where the database lives when no path is given: the current directory if there's already
+/// one there, as older versions always used it, otherwise the per-user data directory.
+pub fn default_path() -> PathBuf {
+    let in_current_dir = PathBuf::from(DB_FILE_NAME);
+    if in_current_dir.exists() {
+        return in_current_dir;
+    }
+
+    match directories::ProjectDirs::from("", "", "questrade-asset-tracker") {
+        Some(dirs) => dirs.data_dir().join(DB_FILE_NAME),
+        None => in_current_dir,
+    }
+}
+
+/// How many recent prices are kept per symbol for the positions trend column.
+pub const PRICE_HISTORY_LEN: i64 = 10;
+
+/// The label given to refresh tokens added without one.
+pub const DEFAULT_TOKEN_LABEL: &str = "default";
+
+/// How the connection pool is set up. The database is always opened in WAL mode, so reads
+/// don't block while a snapshot is being written.
+#[derive(Debug, Clone, Copy)]
+pub struct PoolOptions {
+    pub max_connections: u32,
+    /// How long a connection waits for another's write lock before failing with "database is
+    /// locked".
+    pub busy_timeout: Duration,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        PoolOptions {
+            max_connections: 5,
+            busy_timeout: Duration::from_secs(5),
+        }
+    }
+}
 
 #[derive(Clone, FromRow, Debug)]
 pub struct RefreshToken {
     id: i64,
+    pub label: String,
     pub refresh_token: String,
 }
 
+#[derive(Clone, FromRow, Debug)]
+pub struct Snapshot {
+    pub id: i64,
+    pub taken_at: DateTime<Utc>,
+    dataset: String,
+}
+
+impl Snapshot {
+    pub fn dataset(&self) -> Result<Dataset, serde_json::Error> {
+        serde_json::from_str(&self.dataset)
+    }
+}
+
+/// One position as it stood when a snapshot was taken.
+#[derive(Clone, FromRow, Debug, PartialEq)]
+pub struct PositionSnapshot {
+    pub account_id: AccountID,
+    pub symbol: String,
+    pub symbol_id: SymbolID,
+    pub quantity: f64,
+    pub total_cost: f64,
+    pub market_value: f64,
+    /// The symbol's currency, empty for rows stored before currencies were recorded.
+    pub currency: String,
+}
+
+impl PositionSnapshot {
+    /// Rebuilds a `Position` with the prices and open P&L derived from the stored totals.
+    pub fn to_position(&self) -> Position {
+        let per_share = |total: f64| {
+            if self.quantity == 0.0 {
+                0.0
+            } else {
+                total / self.quantity
+            }
+        };
+
+        Position {
+            symbol: self.symbol.clone(),
+            symbol_id: self.symbol_id,
+            open_quantity: self.quantity,
+            closed_quantity: 0.0,
+            current_market_value: self.market_value,
+            current_price: per_share(self.market_value),
+            average_entry_price: per_share(self.total_cost),
+            closed_pnl: 0.0,
+            open_pnl: self.market_value - self.total_cost,
+            total_cost: self.total_cost,
+        }
+    }
+}
+
+/// One account's combined balance in one currency as it stood when a snapshot was taken.
+#[derive(Clone, FromRow, Debug, PartialEq)]
+pub struct BalanceSnapshot {
+    pub taken_at: DateTime<Utc>,
+    pub account_id: AccountID,
+    pub currency: String,
+    pub cash: f64,
+    pub market_value: f64,
+    pub total_equity: f64,
+}
+
+#[derive(Clone, FromRow, Debug)]
+pub struct Note {
+    pub symbol: String,
+    pub note: String,
+}
+
+/// An asset class set with `classify`, taking precedence over the asset classes file.
+#[derive(Clone, FromRow, Debug, PartialEq)]
+pub struct AssetClassification {
+    pub symbol: String,
+    pub asset_class: String,
+}
+
+/// A symbol on the watchlist, kept by id so a renamed ticker still resolves.
+#[derive(Clone, FromRow, Debug, PartialEq)]
+pub struct WatchedSymbol {
+    pub symbol_id: SymbolID,
+    pub symbol: String,
+}
+
+#[derive(Clone)]
 pub struct DatabaseAPI {
     pool: sqlx::sqlite::SqlitePool,
 }
 
 impl DatabaseAPI {
-    pub async fn new() -> Result<Self, sqlx::Error> {
-        if !sqlx::sqlite::Sqlite::database_exists(DB_URL)
+    /// Opens the database at `path`, creating it and any missing parent directories first.
+    pub async fn new(path: &Path) -> Result<Self, sqlx::Error> {
+        Self::new_with_options(path, PoolOptions::default()).await
+    }
+
+    /// Like `new`, with the pool set up by `options`.
+    pub async fn new_with_options(path: &Path, options: PoolOptions) -> Result<Self, sqlx::Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        Self::open(&format!("sqlite://{}", path.display()), options).await
+    }
+
+    /// Waits for in-flight queries to finish, then closes every connection.
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    pub async fn open(db_url: &str, options: PoolOptions) -> Result<Self, sqlx::Error> {
+        if !sqlx::sqlite::Sqlite::database_exists(db_url)
             .await
             .unwrap_or(false)
         {
-            sqlx::sqlite::Sqlite::create_database(DB_URL).await?;
-            println!("Created a new database");
+            sqlx::sqlite::Sqlite::create_database(db_url).await?;
+            eprintln!("Created a new database");
         }
 
-        let pool = sqlx::sqlite::SqlitePool::connect(DB_URL).await?;
+        let connect_options = SqliteConnectOptions::from_str(db_url)?
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(options.busy_timeout);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(options.max_connections)
+            .connect_with(connect_options)
+            .await?;
 
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS refresh_token (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            refresh_token VARCHAR(64) NOT NULL);",
+        // Databases created before logins were labelled have a single unlabelled token. They
+        // also predate migrations, which create every table only if it doesn't exist yet.
+        let has_unlabelled_tokens: bool = sqlx::query_scalar(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('refresh_token')
+            WHERE NOT EXISTS (
+                SELECT 1 FROM pragma_table_info('refresh_token') WHERE name = 'label'
+            )",
         )
-        .execute(&pool)
+        .fetch_one(&pool)
         .await?;
+        if has_unlabelled_tokens {
+            sqlx::query(
+                "ALTER TABLE refresh_token ADD COLUMN label TEXT NOT NULL DEFAULT 'default'",
+            )
+            .execute(&pool)
+            .await?;
+        }
+
+        sqlx::migrate!().run(&pool).await?;
 
         Ok(Self { pool })
     }
 
+    /// The most recently added refresh token, whatever its label.
     pub async fn get_refresh_token(&self) -> Result<RefreshToken, sqlx::Error> {
-        let token = sqlx::query_as::<_, RefreshToken>("SELECT * FROM refresh_token")
-            .fetch_one(&self.pool)
-            .await?;
+        let token = sqlx::query_as::<_, RefreshToken>(
+            "SELECT id, label, refresh_token FROM refresh_token ORDER BY id DESC LIMIT 1",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(token)
+    }
+
+    pub async fn get_refresh_token_by_label(
+        &self,
+        label: &str,
+    ) -> Result<RefreshToken, sqlx::Error> {
+        let token = sqlx::query_as::<_, RefreshToken>(
+            "SELECT id, label, refresh_token FROM refresh_token WHERE label = ?",
+        )
+        .bind(label)
+        .fetch_one(&self.pool)
+        .await?;
 
         Ok(token)
     }
 
-    pub async fn insert_refresh_token(&self, refresh_token: &str) -> Result<(), sqlx::Error> {
-        sqlx::query("INSERT INTO refresh_token (refresh_token) VALUES (?)")
+    /// All stored refresh tokens, oldest first.
+    pub async fn list_refresh_tokens(&self) -> Result<Vec<RefreshToken>, sqlx::Error> {
+        let tokens = sqlx::query_as::<_, RefreshToken>(
+            "SELECT id, label, refresh_token FROM refresh_token ORDER BY id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tokens)
+    }
+
+    /// Stores `refresh_token` under `label`, replacing any token already stored under it so
+    /// it becomes the most recently added. Each label has at most one active token, since
+    /// Questrade invalidates a refresh token once it's been used.
+    pub async fn insert_refresh_token(
+        &self,
+        label: &str,
+        refresh_token: &str,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM refresh_token WHERE label = ?")
+            .bind(label)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("INSERT INTO refresh_token (label, refresh_token) VALUES (?, ?)")
+            .bind(label)
             .bind(refresh_token)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Deletes the stored token labelled `label`, or every stored token if `label` is `None`.
+    pub async fn clear_refresh_tokens(&self, label: Option<&str>) -> Result<(), sqlx::Error> {
+        match label {
+            Some(label) => {
+                sqlx::query("DELETE FROM refresh_token WHERE label = ?")
+                    .bind(label)
+                    .execute(&self.pool)
+                    .await?
+            }
+            None => {
+                sqlx::query("DELETE FROM refresh_token")
+                    .execute(&self.pool)
+                    .await?
+            }
+        };
+
+        Ok(())
+    }
+
+    /// Deletes every stored token except the one labelled `label`.
+    pub async fn clear_other_refresh_tokens(&self, label: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM refresh_token WHERE label != ?")
+            .bind(label)
             .execute(&self.pool)
             .await?;
 
@@ -65,4 +313,1016 @@ impl DatabaseAPI {
 
         Ok(())
     }
+
+    /// Stores the dataset and its per-position and per-balance rows together, returning the
+    /// new snapshot id.
+    pub async fn insert_snapshot(&self, dataset: &Dataset) -> Result<i64, sqlx::Error> {
+        let json =
+            serde_json::to_string(dataset).map_err(|err| sqlx::Error::Encode(Box::new(err)))?;
+        let mut tx = self.pool.begin().await?;
+
+        let result = sqlx::query(
+            "INSERT INTO snapshots (taken_at, total_cost, total_market_value, dataset)
+            VALUES (?, ?, ?, ?)",
+        )
+        .bind(Utc::now())
+        .bind(dataset.total_cost())
+        .bind(dataset.total_market_value())
+        .bind(json)
+        .execute(&mut *tx)
+        .await?;
+
+        let snapshot_id = result.last_insert_rowid();
+        Self::insert_position_rows(&mut tx, snapshot_id, &dataset.positions, &dataset.symbols)
+            .await?;
+        Self::insert_balance_rows(&mut tx, snapshot_id, &dataset.balances).await?;
+        tx.commit().await?;
+
+        Ok(snapshot_id)
+    }
+
+    /// Starts a snapshot whose positions are added account by account, for when the whole
+    /// dataset can't be held in memory. Such snapshots have no cached dataset, so they're
+    /// never served by `get_latest_snapshot`, and aren't read back at all until
+    /// `finish_snapshot` marks them complete.
+    pub async fn begin_snapshot(&self) -> Result<i64, sqlx::Error> {
+        let result = sqlx::query(
+            "INSERT INTO snapshots (taken_at, total_cost, total_market_value, dataset, complete)
+            VALUES (?, 0, 0, '', 0)",
+        )
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    pub async fn insert_position_snapshots(
+        &self,
+        snapshot_id: i64,
+        positions: &HashMap<AccountID, Vec<Position>>,
+        symbols: &HashMap<SymbolID, Symbol>,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        Self::insert_position_rows(&mut tx, snapshot_id, positions, symbols).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn insert_balance_snapshots(
+        &self,
+        snapshot_id: i64,
+        balances: &HashMap<AccountID, Balances>,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        Self::insert_balance_rows(&mut tx, snapshot_id, balances).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn finish_snapshot(
+        &self,
+        snapshot_id: i64,
+        total_cost: f64,
+        total_market_value: f64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE snapshots SET total_cost = ?, total_market_value = ?, complete = 1
+            WHERE id = ?",
+        )
+        .bind(total_cost)
+        .bind(total_market_value)
+        .bind(snapshot_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Deletes a snapshot along with its position and balance rows, e.g. one that couldn't be
+    /// finished.
+    pub async fn delete_snapshot(&self, snapshot_id: i64) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        for table in ["balance_snapshots", "position_snapshots"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE snapshot_id = ?", table))
+                .bind(snapshot_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        sqlx::query("DELETE FROM snapshots WHERE id = ?")
+            .bind(snapshot_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await
+    }
+
+    async fn insert_position_rows(
+        tx: &mut Transaction<'_, Sqlite>,
+        snapshot_id: i64,
+        positions: &HashMap<AccountID, Vec<Position>>,
+        symbols: &HashMap<SymbolID, Symbol>,
+    ) -> Result<(), sqlx::Error> {
+        for (account_id, acct_positions) in positions.iter() {
+            for position in acct_positions.iter() {
+                let currency = symbols
+                    .get(&position.symbol_id)
+                    .map_or("", |symbol| symbol.currency.as_str());
+                sqlx::query(
+                    "INSERT INTO position_snapshots
+                    (snapshot_id, account_id, symbol, symbol_id, quantity, total_cost, market_value,
+                    currency)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                )
+                .bind(snapshot_id)
+                .bind(account_id)
+                .bind(&position.symbol)
+                .bind(position.symbol_id)
+                .bind(position.open_quantity)
+                .bind(position.total_cost)
+                .bind(position.current_market_value)
+                .bind(currency)
+                .execute(&mut **tx)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn insert_balance_rows(
+        tx: &mut Transaction<'_, Sqlite>,
+        snapshot_id: i64,
+        balances: &HashMap<AccountID, Balances>,
+    ) -> Result<(), sqlx::Error> {
+        for (account_id, acct_balances) in balances.iter() {
+            for balance in acct_balances.combined_balances.iter() {
+                sqlx::query(
+                    "INSERT INTO balance_snapshots
+                    (snapshot_id, account_id, currency, cash, market_value, total_equity)
+                    VALUES (?, ?, ?, ?, ?, ?)",
+                )
+                .bind(snapshot_id)
+                .bind(account_id)
+                .bind(&balance.currency)
+                .bind(balance.cash)
+                .bind(balance.market_value)
+                .bind(balance.total_equity)
+                .execute(&mut **tx)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The stored balance rows of every snapshot taken from `start` up to `end`, oldest first.
+    pub async fn get_snapshots_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<BalanceSnapshot>, sqlx::Error> {
+        let balances = sqlx::query_as::<_, BalanceSnapshot>(
+            "SELECT snapshots.taken_at, account_id, currency, cash, market_value, total_equity
+            FROM balance_snapshots JOIN snapshots ON snapshots.id = balance_snapshots.snapshot_id
+            WHERE snapshots.complete AND snapshots.taken_at >= ? AND snapshots.taken_at < ?
+            ORDER BY snapshots.taken_at, balance_snapshots.id",
+        )
+        .bind(start)
+        .bind(end)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(balances)
+    }
+
+    pub async fn get_position_snapshots(
+        &self,
+        snapshot_id: i64,
+    ) -> Result<Vec<PositionSnapshot>, sqlx::Error> {
+        let positions = sqlx::query_as::<_, PositionSnapshot>(
+            "SELECT account_id, symbol, symbol_id, quantity, total_cost, market_value, currency
+            FROM position_snapshots WHERE snapshot_id = ? ORDER BY id",
+        )
+        .bind(snapshot_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(positions)
+    }
+
+    /// Groups a snapshot's stored positions back into the per-account shape of `Dataset`.
+    pub async fn reconstruct_positions(
+        &self,
+        snapshot_id: i64,
+    ) -> Result<HashMap<AccountID, Vec<Position>>, sqlx::Error> {
+        let mut positions: HashMap<AccountID, Vec<Position>> = HashMap::new();
+
+        for row in self.get_position_snapshots(snapshot_id).await? {
+            positions
+                .entry(row.account_id.clone())
+                .or_default()
+                .push(row.to_position());
+        }
+
+        Ok(positions)
+    }
+
+    /// Rebuilds the accounts, positions and combined balances a snapshot recorded, with each
+    /// held symbol's currency so values can be converted as for a live dataset. Account types
+    /// and the rest of the symbol details aren't recorded, so they're left empty.
+    pub async fn reconstruct_dataset(&self, snapshot_id: i64) -> Result<Dataset, sqlx::Error> {
+        let mut dataset = Dataset::default();
+
+        for row in self.get_position_snapshots(snapshot_id).await? {
+            if !row.currency.is_empty() {
+                dataset.symbols.insert(
+                    row.symbol_id,
+                    Symbol {
+                        symbol: row.symbol.clone(),
+                        symbol_id: row.symbol_id,
+                        dividend: None,
+                        yield_: None,
+                        prev_day_close_price: None,
+                        currency: row.currency.clone(),
+                    },
+                );
+            }
+            dataset
+                .positions
+                .entry(row.account_id.clone())
+                .or_default()
+                .push(row.to_position());
+        }
+
+        let balances = sqlx::query_as::<_, BalanceSnapshot>(
+            "SELECT snapshots.taken_at, account_id, currency, cash, market_value, total_equity
+            FROM balance_snapshots JOIN snapshots ON snapshots.id = balance_snapshots.snapshot_id
+            WHERE snapshot_id = ? ORDER BY balance_snapshots.id",
+        )
+        .bind(snapshot_id)
+        .fetch_all(&self.pool)
+        .await?;
+        for row in balances {
+            let balances = dataset
+                .balances
+                .entry(row.account_id)
+                .or_insert_with(|| Balances {
+                    per_currency_balances: Vec::new(),
+                    combined_balances: Vec::new(),
+                });
+            balances.combined_balances.push(Balance {
+                currency: row.currency,
+                cash: row.cash,
+                market_value: row.market_value,
+                total_equity: row.total_equity,
+            });
+        }
+
+        let mut account_ids: Vec<_> = dataset
+            .positions
+            .keys()
+            .chain(dataset.balances.keys())
+            .cloned()
+            .collect();
+        account_ids.sort();
+        account_ids.dedup();
+        dataset.accounts = account_ids
+            .into_iter()
+            .map(|id| Account {
+                type_: String::new(),
+                id,
+            })
+            .collect();
+
+        Ok(dataset)
+    }
+
+    pub async fn get_latest_snapshot(&self) -> Result<Option<Snapshot>, sqlx::Error> {
+        let snapshot = sqlx::query_as::<_, Snapshot>(
+            "SELECT * FROM snapshots WHERE dataset != '' ORDER BY taken_at DESC LIMIT 1",
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(snapshot)
+    }
+
+    /// The full snapshot taken before the latest one, i.e. the run before the data being shown.
+    pub async fn get_previous_snapshot(&self) -> Result<Option<Snapshot>, sqlx::Error> {
+        let snapshot = sqlx::query_as::<_, Snapshot>(
+            "SELECT * FROM snapshots WHERE dataset != '' ORDER BY taken_at DESC LIMIT 1 OFFSET 1",
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(snapshot)
+    }
+
+    /// The most recent snapshot that has per-position rows stored for it.
+    pub async fn get_latest_position_snapshot(&self) -> Result<Option<Snapshot>, sqlx::Error> {
+        let snapshot = sqlx::query_as::<_, Snapshot>(
+            "SELECT * FROM snapshots
+            WHERE complete AND id IN (SELECT snapshot_id FROM position_snapshots)
+            ORDER BY taken_at DESC LIMIT 1",
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(snapshot)
+    }
+
+    pub async fn get_snapshot_before(
+        &self,
+        before: DateTime<Utc>,
+    ) -> Result<Option<Snapshot>, sqlx::Error> {
+        let snapshot = sqlx::query_as::<_, Snapshot>(
+            "SELECT * FROM snapshots WHERE complete AND taken_at < ?
+            ORDER BY taken_at DESC LIMIT 1",
+        )
+        .bind(before)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(snapshot)
+    }
+
+    /// Deletes snapshots taken before `before` along with their position rows, returning how
+    /// many of each were (or with `dry_run`, would be) removed.
+    pub async fn prune_snapshots(
+        &self,
+        before: DateTime<Utc>,
+        dry_run: bool,
+    ) -> Result<(i64, i64), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let (snapshots,) =
+            sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM snapshots WHERE taken_at < ?")
+                .bind(before)
+                .fetch_one(&mut *tx)
+                .await?;
+        let (positions,) = sqlx::query_as::<_, (i64,)>(
+            "SELECT COUNT(*) FROM position_snapshots
+            WHERE snapshot_id IN (SELECT id FROM snapshots WHERE taken_at < ?)",
+        )
+        .bind(before)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if !dry_run {
+            sqlx::query(
+                "DELETE FROM balance_snapshots
+                WHERE snapshot_id IN (SELECT id FROM snapshots WHERE taken_at < ?)",
+            )
+            .bind(before)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query(
+                "DELETE FROM position_snapshots
+                WHERE snapshot_id IN (SELECT id FROM snapshots WHERE taken_at < ?)",
+            )
+            .bind(before)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query("DELETE FROM snapshots WHERE taken_at < ?")
+                .bind(before)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok((snapshots, positions))
+    }
+
+    /// Records the current price of every held symbol, trimming each symbol's history to the
+    /// last `PRICE_HISTORY_LEN` entries.
+    pub async fn append_prices(&self, dataset: &Dataset) -> Result<(), sqlx::Error> {
+        let prices: HashMap<&str, f64> = dataset
+            .positions
+            .values()
+            .flatten()
+            .map(|position| (position.symbol.as_str(), position.current_price))
+            .collect();
+        let recorded_at = Utc::now();
+        let mut tx = self.pool.begin().await?;
+
+        for (symbol, price) in prices {
+            sqlx::query("INSERT INTO price_history (symbol, recorded_at, price) VALUES (?, ?, ?)")
+                .bind(symbol)
+                .bind(recorded_at)
+                .bind(price)
+                .execute(&mut *tx)
+                .await?;
+
+            sqlx::query(
+                "DELETE FROM price_history WHERE symbol = ? AND id NOT IN (
+                SELECT id FROM price_history WHERE symbol = ? ORDER BY id DESC LIMIT ?)",
+            )
+            .bind(symbol)
+            .bind(symbol)
+            .bind(PRICE_HISTORY_LEN)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await
+    }
+
+    /// Stored prices per symbol, oldest first.
+    pub async fn get_price_history(&self) -> Result<HashMap<String, Vec<f64>>, sqlx::Error> {
+        let rows = sqlx::query_as::<_, (String, f64)>(
+            "SELECT symbol, price FROM price_history ORDER BY id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut history: HashMap<String, Vec<f64>> = HashMap::new();
+        for (symbol, price) in rows {
+            history.entry(symbol).or_default().push(price);
+        }
+
+        Ok(history)
+    }
+
+    /// Cached symbol details fetched at or after `fetched_since`.
+    pub async fn get_cached_symbols(
+        &self,
+        fetched_since: DateTime<Utc>,
+    ) -> Result<HashMap<SymbolID, Symbol>, sqlx::Error> {
+        let symbols = sqlx::query_as::<_, Symbol>(
+            "SELECT symbol_id, symbol, dividend, yield AS yield_, prev_day_close_price, currency
+            FROM symbol_cache WHERE fetched_at >= ?",
+        )
+        .bind(fetched_since)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(symbols
+            .into_iter()
+            .map(|symbol| (symbol.symbol_id, symbol))
+            .collect())
+    }
+
+    pub async fn upsert_cached_symbols(&self, symbols: &[&Symbol]) -> Result<(), sqlx::Error> {
+        let fetched_at = Utc::now();
+        let mut tx = self.pool.begin().await?;
+
+        for symbol in symbols {
+            sqlx::query(
+                "INSERT INTO symbol_cache
+                (symbol_id, symbol, dividend, yield, prev_day_close_price, currency, fetched_at)
+                VALUES (?, ?, ?, ?, ?, ?, ?)
+                ON CONFLICT(symbol_id) DO UPDATE SET
+                symbol = excluded.symbol,
+                dividend = excluded.dividend,
+                yield = excluded.yield,
+                prev_day_close_price = excluded.prev_day_close_price,
+                currency = excluded.currency,
+                fetched_at = excluded.fetched_at",
+            )
+            .bind(symbol.symbol_id)
+            .bind(&symbol.symbol)
+            .bind(symbol.dividend)
+            .bind(symbol.yield_)
+            .bind(symbol.prev_day_close_price)
+            .bind(&symbol.currency)
+            .bind(fetched_at)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await
+    }
+
+    pub async fn get_notes(&self) -> Result<Vec<Note>, sqlx::Error> {
+        let notes = sqlx::query_as::<_, Note>("SELECT * FROM notes ORDER BY symbol")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(notes)
+    }
+
+    pub async fn set_note(&self, symbol: &str, note: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO notes (symbol, note) VALUES (?, ?)
+            ON CONFLICT(symbol) DO UPDATE SET note = excluded.note",
+        )
+        .bind(symbol)
+        .bind(note)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_note(&self, symbol: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM notes WHERE symbol = ?")
+            .bind(symbol)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_asset_classifications(&self) -> Result<Vec<AssetClassification>, sqlx::Error> {
+        let classifications = sqlx::query_as::<_, AssetClassification>(
+            "SELECT symbol, asset_class FROM asset_classification ORDER BY symbol",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(classifications)
+    }
+
+    pub async fn set_asset_classification(
+        &self,
+        symbol: &str,
+        asset_class: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO asset_classification (symbol, asset_class) VALUES (?, ?)
+            ON CONFLICT(symbol) DO UPDATE SET asset_class = excluded.asset_class",
+        )
+        .bind(symbol)
+        .bind(asset_class)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_watchlist(&self) -> Result<Vec<WatchedSymbol>, sqlx::Error> {
+        let watchlist = sqlx::query_as::<_, WatchedSymbol>(
+            "SELECT symbol_id, symbol FROM watchlist ORDER BY symbol",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(watchlist)
+    }
+
+    pub async fn watch_symbol(&self, symbol: &WatchedSymbol) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO watchlist (symbol_id, symbol) VALUES (?, ?)
+            ON CONFLICT(symbol_id) DO UPDATE SET symbol = excluded.symbol",
+        )
+        .bind(symbol.symbol_id)
+        .bind(&symbol.symbol)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Removes `symbol` from the watchlist, ignoring case. Returns whether it was on it.
+    pub async fn unwatch_symbol(&self, symbol: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM watchlist WHERE symbol = ? COLLATE NOCASE")
+            .bind(symbol)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// A database file under the system temp dir for tests, deleted along with its WAL files
+/// when dropped.
+#[cfg(test)]
+pub(crate) struct TestDb {
+    db: DatabaseAPI,
+    path: PathBuf,
+}
+
+#[cfg(test)]
+impl TestDb {
+    /// Where the database for the test `name` lives, unique to this test run.
+    pub(crate) fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "questrade_asset_tracker_{}_{}.db",
+            name,
+            std::process::id()
+        ))
+    }
+
+    /// Opens a fresh database for the test `name`.
+    pub(crate) async fn new(name: &str) -> Self {
+        let path = Self::path(name);
+        let _ = std::fs::remove_file(&path);
+        Self::open(path).await
+    }
+
+    /// Opens the database already at `path`, e.g. one a test set up by hand.
+    pub(crate) async fn open(path: PathBuf) -> Self {
+        let db = DatabaseAPI::open(
+            &format!("sqlite://{}", path.display()),
+            PoolOptions::default(),
+        )
+        .await
+        .unwrap();
+
+        TestDb { db, path }
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestDb {
+    type Target = DatabaseAPI;
+
+    fn deref(&self) -> &DatabaseAPI {
+        &self.db
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut path = self.path.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset_tracker::Balance;
+
+    #[tokio::test]
+    async fn databases_from_before_migrations_are_upgraded() {
+        let path = TestDb::path("unversioned");
+        let _ = std::fs::remove_file(&path);
+        let url = format!("sqlite://{}", path.display());
+        sqlx::sqlite::Sqlite::create_database(&url).await.unwrap();
+        let pool = sqlx::sqlite::SqlitePool::connect(&url).await.unwrap();
+        sqlx::query(
+            "CREATE TABLE refresh_token (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            refresh_token VARCHAR(64) NOT NULL);
+            INSERT INTO refresh_token (refresh_token) VALUES ('old-token');",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        pool.close().await;
+
+        let db = DatabaseAPI::open(&url, PoolOptions::default())
+            .await
+            .unwrap();
+        let token = db.get_refresh_token().await.unwrap();
+        assert_eq!(token.label, DEFAULT_TOKEN_LABEL);
+        assert_eq!(token.refresh_token, "old-token");
+        assert!(db.get_watchlist().await.unwrap().is_empty());
+        db.close().await;
+
+        // Reopening finds every migration already applied.
+        TestDb::open(path).await.close().await;
+    }
+
+    #[tokio::test]
+    async fn databases_are_opened_in_wal_mode() {
+        let db = TestDb::new("wal").await;
+        let journal_mode: String = sqlx::query_scalar("PRAGMA journal_mode")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+
+        assert_eq!(journal_mode, "wal");
+    }
+
+    #[tokio::test]
+    async fn refresh_tokens_by_label() {
+        let db = TestDb::new("refresh_tokens").await;
+        db.insert_refresh_token("me", "token-a").await.unwrap();
+        db.insert_refresh_token("spouse", "token-b").await.unwrap();
+
+        assert_eq!(db.get_refresh_token().await.unwrap().label, "spouse");
+        assert_eq!(
+            db.get_refresh_token_by_label("me")
+                .await
+                .unwrap()
+                .refresh_token,
+            "token-a"
+        );
+        assert!(matches!(
+            db.get_refresh_token_by_label("other").await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+
+        db.insert_refresh_token("me", "token-c").await.unwrap();
+        let tokens = db.list_refresh_tokens().await.unwrap();
+        let labels: Vec<_> = tokens.iter().map(|token| token.label.as_str()).collect();
+        assert_eq!(labels, ["spouse", "me"]);
+        assert_eq!(
+            db.get_refresh_token().await.unwrap().refresh_token,
+            "token-c"
+        );
+    }
+
+    #[tokio::test]
+    async fn inserting_a_token_twice_keeps_only_the_latest() {
+        let db = TestDb::new("refresh_token_twice").await;
+        db.insert_refresh_token("me", "token-a").await.unwrap();
+        db.insert_refresh_token("me", "token-b").await.unwrap();
+
+        let tokens = db.list_refresh_tokens().await.unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(
+            db.get_refresh_token().await.unwrap().refresh_token,
+            "token-b"
+        );
+
+        db.insert_refresh_token("spouse", "token-c").await.unwrap();
+        db.clear_refresh_tokens(Some("spouse")).await.unwrap();
+        assert_eq!(db.get_refresh_token().await.unwrap().label, "me");
+        db.insert_refresh_token("spouse", "token-d").await.unwrap();
+        db.clear_other_refresh_tokens("spouse").await.unwrap();
+        assert_eq!(db.list_refresh_tokens().await.unwrap().len(), 1);
+        assert_eq!(db.get_refresh_token().await.unwrap().label, "spouse");
+        db.clear_refresh_tokens(None).await.unwrap();
+        assert!(matches!(
+            db.get_refresh_token().await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn watchlist_round_trip() {
+        let db = TestDb::new("watchlist").await;
+        let watched = |symbol_id: SymbolID, symbol: &str| WatchedSymbol {
+            symbol_id,
+            symbol: symbol.to_string(),
+        };
+
+        db.watch_symbol(&watched(2, "ZAG.TO")).await.unwrap();
+        db.watch_symbol(&watched(1, "VFV.TO")).await.unwrap();
+        db.watch_symbol(&watched(1, "VFV.TO")).await.unwrap();
+        assert_eq!(
+            db.get_watchlist().await.unwrap(),
+            vec![watched(1, "VFV.TO"), watched(2, "ZAG.TO")]
+        );
+
+        assert!(db.unwatch_symbol("vfv.to").await.unwrap());
+        assert!(!db.unwatch_symbol("vfv.to").await.unwrap());
+        assert_eq!(
+            db.get_watchlist().await.unwrap(),
+            vec![watched(2, "ZAG.TO")]
+        );
+    }
+
+    #[tokio::test]
+    async fn asset_classification_overwrites_the_previous_class() {
+        let db = TestDb::new("asset_classification").await;
+        db.set_asset_classification("SHOP.TO", "Cash")
+            .await
+            .unwrap();
+        db.set_asset_classification("SHOP.TO", "Stocks")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            db.get_asset_classifications().await.unwrap(),
+            vec![AssetClassification {
+                symbol: String::from("SHOP.TO"),
+                asset_class: String::from("Stocks"),
+            }]
+        );
+    }
+
+    fn position(symbol: &str, symbol_id: SymbolID, quantity: f64, value: f64) -> Position {
+        serde_json::from_value(serde_json::json!({
+            "symbol": symbol,
+            "symbolId": symbol_id,
+            "openQuantity": quantity,
+            "closedQuantity": 0.0,
+            "currentMarketValue": value,
+            "currentPrice": value / quantity,
+            "averageEntryPrice": 1.0,
+            "closedPnl": 0.0,
+            "openPnl": 0.0,
+            "totalCost": quantity,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn position_snapshots_round_trip() {
+        let db = TestDb::new("position_snapshots").await;
+        let dataset = Dataset {
+            accounts: Vec::new(),
+            positions: HashMap::from([(
+                String::from("123"),
+                vec![
+                    position("XEQT.TO", 1, 10.0, 300.0),
+                    position("ZAG.TO", 2, 5.0, 70.0),
+                ],
+            )]),
+            balances: HashMap::new(),
+            symbols: HashMap::new(),
+        };
+
+        let snapshot_id = db.insert_snapshot(&dataset).await.unwrap();
+        let rows = db.get_position_snapshots(snapshot_id).await.unwrap();
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].account_id, "123");
+        assert_eq!(rows[0].symbol, "XEQT.TO");
+        assert_eq!(rows[0].quantity, 10.0);
+        assert_eq!(rows[1].market_value, 70.0);
+
+        let positions = db.reconstruct_positions(snapshot_id).await.unwrap();
+        assert_eq!(positions["123"][0].current_price, 30.0);
+        assert_eq!(positions["123"][1].open_pnl, 65.0);
+        assert!(db
+            .get_position_snapshots(snapshot_id + 1)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn snapshots_are_reconstructed_with_currencies_and_balances() {
+        let db = TestDb::new("reconstruct_dataset").await;
+        let balance = |currency: &str, total_equity: f64| Balance {
+            currency: currency.to_string(),
+            cash: 20.0,
+            market_value: total_equity - 20.0,
+            total_equity,
+        };
+        let dataset = Dataset {
+            positions: HashMap::from([(
+                String::from("123"),
+                vec![
+                    position("VFV.TO", 1, 10.0, 300.0),
+                    position("SPY", 2, 1.0, 500.0),
+                ],
+            )]),
+            balances: HashMap::from([(
+                String::from("456"),
+                Balances {
+                    per_currency_balances: Vec::new(),
+                    combined_balances: vec![balance("CAD", 1250.0), balance("USD", 1000.0)],
+                },
+            )]),
+            symbols: HashMap::from([(
+                2,
+                Symbol {
+                    symbol: String::from("SPY"),
+                    symbol_id: 2,
+                    dividend: None,
+                    yield_: None,
+                    prev_day_close_price: None,
+                    currency: String::from("USD"),
+                },
+            )]),
+            ..Dataset::default()
+        };
+
+        let snapshot_id = db.insert_snapshot(&dataset).await.unwrap();
+        let rebuilt = db.reconstruct_dataset(snapshot_id).await.unwrap();
+
+        let ids: Vec<_> = rebuilt.accounts.iter().map(|a| a.id.as_str()).collect();
+        assert_eq!(ids, ["123", "456"]);
+        assert_eq!(rebuilt.positions["123"].len(), 2);
+        assert_eq!(rebuilt.symbols.len(), 1);
+        assert_eq!(rebuilt.symbols[&2].currency, "USD");
+        assert_eq!(rebuilt.balances["456"].combined_balances.len(), 2);
+        assert_eq!(rebuilt.cash("CAD"), 20.0);
+    }
+
+    #[tokio::test]
+    async fn balance_snapshots_between() {
+        let db = TestDb::new("balance_snapshots").await;
+        let balance = |total_equity: f64| Balance {
+            currency: String::from("CAD"),
+            cash: 0.0,
+            market_value: total_equity,
+            total_equity,
+        };
+        let dataset = |total_equity: f64| Dataset {
+            balances: HashMap::from([(
+                String::from("123"),
+                Balances {
+                    per_currency_balances: vec![balance(total_equity)],
+                    combined_balances: vec![balance(total_equity)],
+                },
+            )]),
+            ..Dataset::default()
+        };
+
+        let start = Utc::now();
+        db.insert_snapshot(&dataset(100.0)).await.unwrap();
+        let middle = Utc::now();
+        db.insert_snapshot(&dataset(110.0)).await.unwrap();
+        let end = Utc::now();
+
+        let rows = db.get_snapshots_between(start, end).await.unwrap();
+        let totals: Vec<_> = rows.iter().map(|row| row.total_equity).collect();
+        assert_eq!(totals, [100.0, 110.0]);
+        assert_eq!(rows[0].account_id, "123");
+
+        let rows = db.get_snapshots_between(middle, end).await.unwrap();
+        assert_eq!(rows.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn symbol_cache_returns_only_fresh_rows() {
+        let db = TestDb::new("symbol_cache").await;
+        let symbol = |dividend: f64| Symbol {
+            symbol: String::from("XEQT.TO"),
+            symbol_id: 1,
+            dividend: Some(dividend),
+            yield_: None,
+            prev_day_close_price: Some(30.0),
+            currency: String::from("CAD"),
+        };
+
+        db.upsert_cached_symbols(&[&symbol(0.1)]).await.unwrap();
+        let before_update = Utc::now();
+        db.upsert_cached_symbols(&[&symbol(0.2)]).await.unwrap();
+
+        let cached = db.get_cached_symbols(before_update).await.unwrap();
+        assert_eq!(cached.len(), 1);
+        assert_eq!(cached[&1].dividend, Some(0.2));
+        assert_eq!(cached[&1].yield_, None);
+        assert!(db.get_cached_symbols(Utc::now()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn price_history_keeps_most_recent_prices() {
+        let db = TestDb::new("price_history").await;
+
+        for price in 1..=(PRICE_HISTORY_LEN + 2) {
+            let dataset = Dataset {
+                positions: HashMap::from([(
+                    String::from("123"),
+                    vec![position("XEQT.TO", 1, 1.0, price as f64)],
+                )]),
+                ..Dataset::default()
+            };
+            db.append_prices(&dataset).await.unwrap();
+        }
+
+        let history = db.get_price_history().await.unwrap();
+        let prices = &history["XEQT.TO"];
+        assert_eq!(prices.len(), PRICE_HISTORY_LEN as usize);
+        assert_eq!(prices.first(), Some(&3.0));
+        assert_eq!(prices.last(), Some(&((PRICE_HISTORY_LEN + 2) as f64)));
+    }
+
+    #[tokio::test]
+    async fn prune_removes_only_old_snapshots() {
+        let db = TestDb::new("prune").await;
+        let dataset = Dataset {
+            positions: HashMap::from([(
+                String::from("123"),
+                vec![position("XEQT.TO", 1, 10.0, 300.0)],
+            )]),
+            ..Dataset::default()
+        };
+        let old_id = db.insert_snapshot(&dataset).await.unwrap();
+        let cutoff = Utc::now();
+        let new_id = db.insert_snapshot(&dataset).await.unwrap();
+
+        assert_eq!(db.prune_snapshots(cutoff, true).await.unwrap(), (1, 1));
+        assert_eq!(db.get_position_snapshots(old_id).await.unwrap().len(), 1);
+
+        assert_eq!(db.prune_snapshots(cutoff, false).await.unwrap(), (1, 1));
+        assert!(db.get_position_snapshots(old_id).await.unwrap().is_empty());
+        assert_eq!(db.get_position_snapshots(new_id).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn unfinished_snapshots_are_not_read_back() {
+        let db = TestDb::new("unfinished_snapshot").await;
+        let positions = HashMap::from([(
+            String::from("123"),
+            vec![position("XEQT.TO", 1, 10.0, 300.0)],
+        )]);
+        let later = || Utc::now() + chrono::TimeDelta::seconds(1);
+
+        let abandoned = db.begin_snapshot().await.unwrap();
+        db.insert_position_snapshots(abandoned, &positions, &HashMap::new())
+            .await
+            .unwrap();
+        assert!(db.get_snapshot_before(later()).await.unwrap().is_none());
+        assert!(db.get_latest_position_snapshot().await.unwrap().is_none());
+        db.delete_snapshot(abandoned).await.unwrap();
+        assert!(db
+            .get_position_snapshots(abandoned)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let finished = db.begin_snapshot().await.unwrap();
+        db.insert_position_snapshots(finished, &positions, &HashMap::new())
+            .await
+            .unwrap();
+        db.finish_snapshot(finished, 100.0, 300.0).await.unwrap();
+        let snapshot = db.get_snapshot_before(later()).await.unwrap().unwrap();
+        assert_eq!(snapshot.id, finished);
+    }
 }
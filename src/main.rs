@@ -1,10 +1,55 @@
+mod activities;
 mod asset_tracker;
 mod assets;
+mod config;
 mod db;
 mod questrade_api;
+mod setup;
+mod table;
+mod theme;
+mod tui;
+mod validation;
 
+use asset_tracker::{
+    AssetTracker, CommandError, Dataset, ErrorPolicy, PositionFilter, SymbolCache, Warnings,
+};
+use chrono::{Local, NaiveDate};
+use config::Config;
 use db::DatabaseAPI;
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+    time::{Duration, Instant},
+};
 use structopt::StructOpt;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    time::MissedTickBehavior,
+};
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
+
+const DEFAULT_DAEMON_INTERVAL_MINUTES: u64 = 60;
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown format {}, expected text or json", s)),
+        }
+    }
+}
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -14,73 +59,952 @@ use structopt::StructOpt;
 struct Opt {
     #[structopt(long = "auth")]
     authorization_token: Option<String>,
+
+    /// Read the refresh token to store from a file, e.g. a mounted secret
+    #[structopt(
+        long = "auth-file",
+        parse(from_os_str),
+        conflicts_with = "authorization-token"
+    )]
+    auth_file: Option<PathBuf>,
+
+    /// Forget the stored login named by --label, or every stored login if no label is given,
+    /// e.g. to start over after a token stopped working. With --auth the old logins are only
+    /// forgotten once the new token has logged in; on its own the program exits after clearing
+    #[structopt(long = "reset-token")]
+    reset_token: bool,
+
+    /// Which stored Questrade login to use, or to store --auth under. Defaults to the most
+    /// recently added login
+    #[structopt(long = "label")]
+    label: Option<String>,
+
+    /// The database file to use. Defaults to the per-user data directory, or
+    /// questrade_asset_tracker.db in the current directory if it exists
+    #[structopt(long = "db-path", env = "QAT_DB_PATH", parse(from_os_str))]
+    db_path: Option<PathBuf>,
+
+    #[structopt(long = "config", parse(from_os_str), default_value = config::DEFAULT_CONFIG_PATH)]
+    config_path: PathBuf,
+
+    /// Run a single command (e.g. `summary`) and exit instead of starting the REPL
+    #[structopt(long = "command")]
+    command: Option<String>,
+
+    /// Output format, `text` or `json`. In `json` mode `summary`, `positions` and errors are
+    /// printed as JSON
+    #[structopt(long = "format", default_value = "text")]
+    format: Format,
+
+    /// Size table columns to fit their content (`auto`) or use the preset widths (`fixed`)
+    #[structopt(long = "columns-width")]
+    columns_width: Option<table::Layout>,
+
+    /// Currency the combined balances are emphasized in and the summary totals are expressed
+    /// in, e.g. USD. Defaults to the config file's display_currency (CAD)
+    #[structopt(long = "base-currency")]
+    base_currency: Option<String>,
+
+    /// Colour theme, `default` or `high-contrast`, which keeps allocations and gains/losses
+    /// distinguishable with colour-blindness
+    #[structopt(long = "theme")]
+    theme: Option<theme::ThemeName>,
+
+    /// Browse Home, Accounts, Positions and Summary in a full-screen interface instead of the
+    /// REPL
+    #[structopt(long = "tui", conflicts_with = "command")]
+    tui: bool,
+
+    /// Instead of the REPL, re-fetch and snapshot the portfolio every --interval minutes while
+    /// the market is open, until Ctrl+C is pressed. Can't be combined with --account-type,
+    /// since only snapshots of every account are stored
+    #[structopt(
+        long = "daemon",
+        conflicts_with_all = &["command", "tui", "account-type"]
+    )]
+    daemon: bool,
+
+    /// Minutes between snapshots with --daemon (default 60)
+    #[structopt(long = "interval", requires = "daemon")]
+    interval: Option<u64>,
+
+    /// With --daemon, delete snapshots older than this many days on every tick
+    #[structopt(long = "prune-days", requires = "daemon")]
+    prune_days: Option<i64>,
+
+    /// Don't print the welcome banner and command list when starting the REPL
+    #[structopt(long = "no-banner")]
+    no_banner: bool,
+
+    /// Print plain text without colours. Also set by a non-empty NO_COLOR environment variable
+    #[structopt(long = "no-color")]
+    no_color: bool,
+
+    /// With --command, re-fetch from Questrade instead of reusing the last cached dataset
+    #[structopt(long = "fresh")]
+    fresh: bool,
+
+    /// Abort if any account or symbol fails to load (the default with --command)
+    #[structopt(long = "fail-fast", conflicts_with = "best-effort")]
+    fail_fast: bool,
+
+    /// Skip accounts and symbols that fail to load with a warning (the default in the REPL)
+    #[structopt(long = "best-effort")]
+    best_effort: bool,
+
+    /// Re-fetch every symbol's details instead of reusing ones cached in the last day
+    #[structopt(long = "refresh-symbols")]
+    refresh_symbols: bool,
+
+    /// Only fetch and show accounts of this type, e.g. TFSA or RRSP (case-insensitive)
+    #[structopt(long = "account-type")]
+    account_type: Option<String>,
+
+    /// Hide positions worth less than this from the summary's symbol table and the positions
+    /// tables. They're still counted in the totals, in a row of their own
+    #[structopt(long = "min-value")]
+    min_value: Option<f64>,
+
+    /// With --min-value, leave hidden positions out of the totals too
+    #[structopt(long = "exclude-hidden", requires = "min-value")]
+    exclude_hidden: bool,
+
+    /// Print account numbers as just their last 3 digits, e.g. ***456, for screenshots
+    #[structopt(long = "mask-accounts")]
+    mask_accounts: bool,
+
+    /// Load, print and snapshot one account at a time to reduce memory use, then exit
+    #[structopt(long = "low-memory")]
+    low_memory: bool,
+
+    /// Print live account balances with the allocation from the last snapshot, then exit
+    #[structopt(long = "accounts-only-summary")]
+    accounts_only_summary: bool,
+
+    /// Log in, print the API server and how long until the access token expires, then exit.
+    /// The tokens themselves are never printed
+    #[structopt(long = "debug-token", hidden = true)]
+    debug_token: bool,
 }
 
 #[tokio::main]
-async fn main() {
-    let db = match DatabaseAPI::new().await {
+async fn main() -> ExitCode {
+    let opt = Opt::from_args();
+    // Logs go to stderr so they never mix with command output. Set RUST_LOG to see more than
+    // errors, e.g. `RUST_LOG=questrade_asset_tracker=debug`. The daemon logs each snapshot, so
+    // it shows info logs unless RUST_LOG says otherwise.
+    let env_filter = match std::env::var_os("RUST_LOG") {
+        None if opt.daemon => EnvFilter::new("questrade_asset_tracker=info"),
+        _ => EnvFilter::from_default_env(),
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(env_filter)
+        .with_writer(std::io::stderr)
+        .init();
+    if opt.no_color || std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+        colored::control::set_override(false);
+    }
+
+    let db_path = opt.db_path.clone().unwrap_or_else(db::default_path);
+    let mut config = match Config::load(&opt.config_path, &db_path) {
+        Ok(config) => config,
+        Err(err) => {
+            return fail(
+                opt.format,
+                "config",
+                format!(
+                    "Error loading config {}: {}",
+                    opt.config_path.display(),
+                    err
+                ),
+            )
+        }
+    };
+
+    let db = match DatabaseAPI::new_with_options(&db_path, config.database_options()).await {
         Ok(db) => db,
         Err(err) => {
-            eprintln!("Error creating DatabaseAPI: {}", err);
-            return;
+            return fail(
+                opt.format,
+                "database",
+                format!("Error creating DatabaseAPI: {}", err),
+            )
         }
     };
 
-    let opt = Opt::from_args();
-    if let Some(token) = opt.authorization_token {
-        match db.insert_refresh_token(&token).await {
-            Ok(_) => {}
-            Err(err) => {
-                eprintln!("Error inserting refresh token: {}", err);
-                return;
+    let token = match (opt.authorization_token, &opt.auth_file) {
+        (Some(token), _) => Some(token),
+        (None, Some(path)) => match read_token_file(path) {
+            Ok(token) => Some(token),
+            Err(message) => return fail(opt.format, "auth_file", message),
+        },
+        (None, None) => None,
+    };
+
+    // Logging in with the token before keeping it means a mistyped one is rejected now,
+    // rather than stored and failing on the next run. It also replaces any token stored
+    // under the same label, so a reset only has the other labels left to clear.
+    match token {
+        Some(token) => {
+            let label = opt.label.as_deref().unwrap_or(db::DEFAULT_TOKEN_LABEL);
+            if let Err(err) =
+                questrade_api::QuestradeAPI::from_refresh_token(&db, label, &token).await
+            {
+                return fail(
+                    opt.format,
+                    err.kind(),
+                    format!("Error logging in with the authorization token: {}", err),
+                );
+            }
+            if opt.reset_token && opt.label.is_none() {
+                if let Err(err) = db.clear_other_refresh_tokens(label).await {
+                    return fail(
+                        opt.format,
+                        "database",
+                        format!("Error clearing refresh tokens: {}", err),
+                    );
+                }
+            }
+        }
+        None if opt.reset_token => {
+            if let Err(err) = db.clear_refresh_tokens(opt.label.as_deref()).await {
+                return fail(
+                    opt.format,
+                    "database",
+                    format!("Error clearing refresh tokens: {}", err),
+                );
+            }
+            match &opt.label {
+                Some(label) => println!("Forgot the stored login {}", label),
+                None => println!("Forgot every stored login"),
             }
+            return ExitCode::SUCCESS;
         }
+        None => {}
+    }
+
+    let run_setup = match opt.command.as_deref() {
+        Some("setup") => true,
+        _ => matches!(db.get_refresh_token().await, Err(sqlx::Error::RowNotFound)),
+    };
+
+    if run_setup {
+        if let Err(err) = setup::run(&db, &opt.config_path, &config.targets).await {
+            return fail(opt.format, "setup", format!("Error during setup: {}", err));
+        }
+
+        if opt.command.is_some() {
+            return ExitCode::SUCCESS;
+        }
+
+        config = match Config::load(&opt.config_path, &db_path) {
+            Ok(config) => config,
+            Err(err) => {
+                return fail(
+                    opt.format,
+                    "config",
+                    format!(
+                        "Error loading config {}: {}",
+                        opt.config_path.display(),
+                        err
+                    ),
+                )
+            }
+        };
     }
 
-    let questrade_api = match questrade_api::QuestradeAPI::new(db).await {
-        Ok(api) => api,
+    if let Some(layout) = opt.columns_width {
+        config.columns_width = layout;
+    }
+    if let Some(theme) = opt.theme {
+        config.theme = theme;
+    }
+    if let Some(currency) = &opt.base_currency {
+        config.display_currency = currency.to_uppercase();
+    }
+    theme::set(theme::Theme::new(config.theme, &config.colours));
+
+    config.error_policy = if opt.fail_fast || (opt.command.is_some() && !opt.best_effort) {
+        ErrorPolicy::FailFast
+    } else {
+        ErrorPolicy::BestEffort
+    };
+
+    config.refresh_symbols = opt.refresh_symbols;
+    config.account_type = opt.account_type.clone();
+    config.min_value = opt.min_value;
+    config.exclude_hidden = opt.exclude_hidden;
+    config.mask_accounts = opt.mask_accounts;
+
+    if opt.debug_token {
+        return match questrade_api::QuestradeAPI::new(&db, opt.label.as_deref()).await {
+            Ok(api) => {
+                let token = api.token().await;
+                println!("API server: {}", token.api_server());
+                println!(
+                    "Access token expires in: {}s",
+                    token.time_until_expiry().as_secs()
+                );
+                ExitCode::SUCCESS
+            }
+            Err(err) => fail(opt.format, err.kind(), format!("Error logging in: {}", err)),
+        };
+    }
+
+    if opt.low_memory || opt.accounts_only_summary {
+        let result = match questrade_api::QuestradeAPI::new(&db, opt.label.as_deref())
+            .await
+            .map(|api| {
+                api.with_retries(config.request_retries)
+                    .with_masked_accounts(config.mask_accounts)
+            }) {
+            Ok(api) if opt.low_memory => AssetTracker::display_streamed(&db, &api, &config).await,
+            Ok(api) => AssetTracker::display_accounts_only_summary(&db, &api, &config).await,
+            Err(err) => Err(err.into()),
+        };
+
+        return match result {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => fail(
+                opt.format,
+                err.kind(),
+                format!("Error loading accounts: {}", err),
+            ),
+        };
+    }
+
+    let cached = if opt.command.is_some() && !opt.fresh {
+        match db.get_latest_snapshot().await {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                return fail(
+                    opt.format,
+                    "database",
+                    format!("Error reading cached dataset: {}", err),
+                )
+            }
+        }
+    } else {
+        None
+    };
+
+    let cached_dataset = cached.and_then(|snapshot| match snapshot.dataset() {
+        Ok(dataset) => Some((snapshot.taken_at, dataset)),
         Err(err) => {
-            eprintln!("Error creating QuestradeAPI client: {}", err);
-            return;
+            eprintln!("Ignoring unreadable cached dataset: {}", err);
+            None
+        }
+    });
+
+    let (questrade_api, dataset) = match cached_dataset {
+        Some((taken_at, mut dataset)) => {
+            eprintln!(
+                "Showing cached data from {} (use --fresh to re-fetch)",
+                taken_at.with_timezone(&Local).format("%Y-%m-%d %H:%M")
+            );
+            if let Some(account_type) = &config.account_type {
+                dataset.retain_account_type(account_type);
+            }
+            (None, dataset)
+        }
+        None => {
+            let questrade_api = match questrade_api::QuestradeAPI::new(&db, opt.label.as_deref())
+                .await
+                .map(|api| {
+                    api.with_retries(config.request_retries)
+                        .with_masked_accounts(config.mask_accounts)
+                }) {
+                Ok(api) => api,
+                Err(err) => {
+                    return fail(
+                        opt.format,
+                        err.kind(),
+                        format!("Error creating QuestradeAPI client: {}", err),
+                    )
+                }
+            };
+
+            let dataset = match Dataset::fetch(
+                &questrade_api,
+                config.request_pacing(),
+                config.error_policy,
+                Some(SymbolCache::new(&db, config.symbol_cache_max_age())),
+                config.account_type.as_deref(),
+                config.mask_accounts,
+                &Warnings::default(),
+            )
+            .await
+            {
+                Ok(dataset) => dataset,
+                Err(err) => {
+                    return fail(
+                        opt.format,
+                        err.kind(),
+                        format!("Error starting Asset Tracker: {}", err),
+                    )
+                }
+            };
+
+            // A snapshot of some of the accounts would throw off the history and cached totals.
+            if config.account_type.is_none() {
+                if let Err(err) = db.insert_snapshot(&dataset).await {
+                    eprintln!("Error caching dataset: {}", err);
+                }
+            }
+
+            if let Err(err) = db.append_prices(&dataset).await {
+                eprintln!("Error recording price history: {}", err);
+            }
+
+            (Some(questrade_api), dataset)
         }
     };
 
-    let asset_tracker = match asset_tracker::AssetTracker::new(questrade_api).await {
-        Ok(api) => api,
+    let mut asset_tracker = match AssetTracker::new(db, questrade_api, dataset, &config).await {
+        Ok(tracker) => tracker,
         Err(err) => {
-            eprintln!("Error starting Asset Tracker: {}", err);
-            return;
+            return fail(
+                opt.format,
+                err.kind(),
+                format!("Error starting Asset Tracker: {}", err),
+            )
         }
     };
+    if let Err(err) = asset_tracker.check_base_currency() {
+        asset_tracker.close().await;
+        return fail(
+            opt.format,
+            "config",
+            format!("Invalid base currency: {}", err),
+        );
+    }
+
+    if let Some(command) = opt.command {
+        let result = run_command(
+            &mut asset_tracker,
+            &config,
+            opt.format,
+            &config.resolve_alias(&command),
+            &mut io::stdout(),
+        )
+        .await;
+        asset_tracker.close().await;
+        return match result {
+            Ok(()) => ExitCode::SUCCESS,
+            Err((kind, message)) => fail(opt.format, kind, message),
+        };
+    }
+
+    if opt.daemon {
+        let minutes = opt.interval.unwrap_or(DEFAULT_DAEMON_INTERVAL_MINUTES);
+        if minutes == 0 {
+            asset_tracker.close().await;
+            return fail(
+                opt.format,
+                "config",
+                String::from("--interval must be at least 1 minute"),
+            );
+        }
+        if opt.prune_days.is_some_and(|days| days < 0) {
+            asset_tracker.close().await;
+            return fail(
+                opt.format,
+                "config",
+                String::from("--prune-days can't be negative"),
+            );
+        }
+        run_daemon(
+            &mut asset_tracker,
+            Duration::from_secs(minutes * 60),
+            opt.prune_days,
+        )
+        .await;
+        asset_tracker.close().await;
+        return ExitCode::SUCCESS;
+    }
+
+    if opt.tui {
+        let result = tui::run(&mut asset_tracker).await;
+        asset_tracker.close().await;
+        return match result {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => fail(opt.format, "io", format!("Error running the TUI: {}", err)),
+        };
+    }
+
+    if !opt.no_banner {
+        println!("Welcome to the Questrade Asset Tracker!");
+        println!("You can quit at anytime by pressing Ctrl+C or supplying the `quit` command");
+        display_help();
+    }
+
+    // Ctrl+C is only handled while waiting for input, so a command that's running, e.g. one
+    // writing a snapshot, finishes before the loop ends.
+    let mut ctrl_c = std::pin::pin!(tokio::signal::ctrl_c());
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        let line = tokio::select! {
+            _ = &mut ctrl_c => {
+                println!();
+                break;
+            }
+            line = lines.next_line() => line,
+        };
+        // Stop at the end of input, e.g. when stdin is closed under cron, rather than
+        // treating it as an endless stream of empty commands.
+        let input = match line {
+            Ok(Some(input)) => input,
+            Ok(None) => break,
+            Err(err) => {
+                eprintln!("Error reading input: {}", err);
+                break;
+            }
+        };
+        let input = config.resolve_alias(input.trim());
+
+        if input == "quit" {
+            break;
+        }
+
+        if let Err((_, message)) = run_command(
+            &mut asset_tracker,
+            &config,
+            opt.format,
+            &input,
+            &mut io::stdout(),
+        )
+        .await
+        {
+            eprintln!("{}", message);
+        }
+    }
+
+    asset_tracker.close().await;
+    println!("Goodbye!");
+    ExitCode::SUCCESS
+}
 
-    println!("Welcome to the Questrade Asset Tracker!");
-    println!("You can quit at anytime by pressing Ctrl+C or supplying the `quit` command");
-    display_help();
+/// Re-fetches and snapshots the portfolio every `interval` until Ctrl+C is pressed, first
+/// deleting snapshots older than `prune_days` if given. Ticks while the market is closed
+/// take no snapshot, since prices won't have moved. Access tokens are renewed as they near
+/// expiry, so the session can run for days.
+async fn run_daemon(asset_tracker: &mut AssetTracker, interval: Duration, prune_days: Option<i64>) {
+    info!(interval = ?interval, "snapshotting in the background, press Ctrl+C to stop");
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    // The first tick is immediate, and startup has just taken a snapshot.
+    ticks.tick().await;
 
+    // As in the REPL, Ctrl+C is only handled between snapshots so one is never cut short.
+    let mut ctrl_c = std::pin::pin!(tokio::signal::ctrl_c());
     loop {
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input).unwrap();
-        let input = input.trim();
+        tokio::select! {
+            _ = &mut ctrl_c => break,
+            _ = ticks.tick() => {}
+        }
+
+        if let Some(keep_days) = prune_days {
+            match asset_tracker.prune_snapshots(keep_days, false).await {
+                Ok((0, _)) => {}
+                Ok((snapshots, positions)) => {
+                    info!(snapshots, positions, keep_days, "pruned old snapshots")
+                }
+                Err(err) => error!(error = %err, "error pruning snapshots"),
+            }
+        }
+
+        if asset_tracker.is_market_open() == Some(false) {
+            info!("market closed, skipping snapshot");
+            continue;
+        }
+
+        let start = Instant::now();
+        match asset_tracker.reload().await {
+            Ok(()) => info!(
+                equity = asset_tracker.total_equity(),
+                duration = ?start.elapsed(),
+                "took snapshot"
+            ),
+            Err(err) => error!(error = %err, "error taking snapshot"),
+        }
+    }
+    info!("stopped");
+}
+
+/// Reads a refresh token from `path`, ignoring surrounding whitespace.
+fn read_token_file(path: &Path) -> Result<String, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| format!("Error reading token file {}: {}", path.display(), err))?;
+
+    match contents.trim() {
+        "" => Err(format!("Token file {} is empty", path.display())),
+        token => Ok(token.to_string()),
+    }
+}
+
+/// Reports a fatal error and returns the failing exit code. With `--format json` the error is
+/// written to stderr as `{"error": {"kind": ..., "message": ...}}` so scripts can tell failures
+/// apart.
+fn fail(format: Format, kind: &str, message: String) -> ExitCode {
+    match format {
+        Format::Text => eprintln!("{}", message),
+        Format::Json => eprintln!(
+            "{}",
+            serde_json::json!({ "error": { "kind": kind, "message": message } })
+        ),
+    }
+
+    ExitCode::FAILURE
+}
+
+/// A failed write to the command's output, reported rather than panicking as `println!`
+/// would.
+fn write_error(err: io::Error) -> CommandError {
+    ("io", format!("Error writing output: {}", err))
+}
+
+fn print_json<T: serde::Serialize>(out: &mut impl Write, value: &T) -> Result<(), CommandError> {
+    let json = serde_json::to_string_pretty(value)
+        .map_err(|err| ("json", format!("Error serializing output: {}", err)))?;
+    writeln!(out, "{}", json).map_err(write_error)
+}
+
+/// The error for a command given the wrong arguments.
+fn usage(usage: &str) -> CommandError {
+    ("invalid_command", format!("Usage: {}", usage))
+}
+
+/// Runs a single command, failing with an `invalid_command` error if it isn't recognized or
+/// its arguments are wrong. The dashboards, `home`, `accounts`, `positions` and `summary`, are
+/// written to `out`. With `--format json`, `summary` and `positions` print their data as JSON
+/// instead.
+async fn run_command(
+    asset_tracker: &mut AssetTracker,
+    config: &Config,
+    format: Format,
+    input: &str,
+    out: &mut impl Write,
+) -> Result<(), CommandError> {
+    let mut args = input.split_whitespace();
+
+    match args.next().unwrap_or("") {
+        "help" => display_help(),
+        "home" => asset_tracker.display_home(out).map_err(write_error)?,
+        "accounts" => asset_tracker
+            .display_accounts(out, args.next())
+            .map_err(write_error)?,
+        "refresh" => asset_tracker.refresh().await?,
+        "positions" => {
+            let (flags, mut filter): (Vec<_>, Vec<_>) = args.partition(|arg| arg.starts_with("--"));
+            let min_value = match filter.iter().position(|arg| *arg == "min") {
+                Some(i) => match filter.get(i + 1).map(|amount| amount.parse::<f64>()) {
+                    Some(Ok(amount)) => {
+                        filter.drain(i..=i + 1);
+                        Some(config.hide_below(Some(amount)))
+                    }
+                    _ => None,
+                },
+                None => Some(None),
+            };
+            let filter = match filter.as_slice() {
+                [] => Some(PositionFilter::All),
+                ["account", account_id] => Some(PositionFilter::Account(account_id)),
+                [symbol] if *symbol != "account" => Some(PositionFilter::Symbol(symbol)),
+                _ => None,
+            };
+
+            let consolidate = flags.contains(&"--consolidate");
+            match (filter, min_value, format) {
+                (Some(filter), Some(min_value), Format::Json)
+                    if flags.iter().all(|flag| *flag == "--consolidate") =>
+                {
+                    print_json(
+                        out,
+                        &asset_tracker
+                            .positions_data(filter, consolidate, min_value)
+                            .unwrap_or_default(),
+                    )?;
+                }
+                (Some(filter), Some(min_value), Format::Text)
+                    if flags
+                        .iter()
+                        .all(|flag| ["--consolidate", "--after-tax", "--live"].contains(flag)) =>
+                {
+                    // Live quotes cost a request per 100 symbols, so they're only fetched when
+                    // asked for.
+                    let day_changes = if flags.contains(&"--live") {
+                        match asset_tracker.fetch_day_changes().await {
+                            Ok(day_changes) => Some(day_changes),
+                            Err(err) => {
+                                eprintln!("Error fetching live quotes: {}", err);
+                                None
+                            }
+                        }
+                    } else {
+                        None
+                    };
+                    asset_tracker
+                        .display_market_status(out)
+                        .map_err(write_error)?;
+                    asset_tracker
+                        .display_positions_with_dividends(
+                            out,
+                            filter,
+                            consolidate,
+                            min_value,
+                            day_changes.as_ref(),
+                        )
+                        .map_err(write_error)?;
+                    if flags.contains(&"--after-tax") {
+                        asset_tracker.display_after_tax_estimate();
+                    }
+                }
+                _ => return Err(usage(
+                    "positions [<symbol> | account <id>] [min <amount>] [--consolidate] [--after-tax] [--live]",
+                )),
+            }
+        }
+        "summary" => match (args.next(), args.next()) {
+            (None, _) if matches!(format, Format::Json) => {
+                print_json(out, &asset_tracker.summary_data())?
+            }
+            (None, _) => {
+                asset_tracker
+                    .display_market_status(out)
+                    .map_err(write_error)?;
+                asset_tracker.display_summary(out).map_err(write_error)?
+            }
+            (Some("--sum-only"), None) => asset_tracker.display_total_equity(),
+            (Some("--as-of"), Some(date)) => match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+                Ok(date) => asset_tracker.display_summary_as_of(date).await?,
+                Err(_) => {
+                    return Err((
+                        "invalid_command",
+                        format!("Invalid date {}, expected YYYY-MM-DD", date),
+                    ))
+                }
+            },
+            _ => return Err(usage("summary [--as-of YYYY-MM-DD | --sum-only]")),
+        },
+        "allocation" => match (args.next(), args.next()) {
+            (None, _) => asset_tracker.display_allocation(false),
+            (Some("by-account"), None) => asset_tracker.display_allocation(true),
+            _ => return Err(usage("allocation [by-account]")),
+        },
+        "rebalance" => {
+            let args: Vec<_> = args.collect();
+            match args.as_slice() {
+                ["--compare"] => return Err(usage("rebalance --compare <strategy>...")),
+                ["--compare", names @ ..] => {
+                    asset_tracker.display_rebalance_comparison(&config.strategies, names)?
+                }
+                flags
+                    if flags
+                        .iter()
+                        .all(|flag| ["--explain", "--cash"].contains(flag)) =>
+                {
+                    asset_tracker
+                        .display_rebalance(flags.contains(&"--explain"), flags.contains(&"--cash"))
+                }
+                _ => {
+                    return Err(usage(
+                        "rebalance [--explain] [--cash] | --compare <strategy>...",
+                    ))
+                }
+            }
+        }
+        "activities" => match args.next().map(str::parse::<i64>).unwrap_or(Ok(30)) {
+            Ok(days) if days > 0 => asset_tracker.display_activities(days).await?,
+            _ => return Err(usage("activities [days]")),
+        },
+        "performance" => match args.next().map(str::parse::<i64>).unwrap_or(Ok(1825)) {
+            Ok(days) if days > 0 => asset_tracker.display_performance(days).await?,
+            _ => return Err(usage("performance [days]")),
+        },
+        "wash-check" => match args.next().map(str::parse::<i64>).unwrap_or(Ok(365)) {
+            Ok(days) if days > 0 => asset_tracker.display_wash_check(days).await?,
+            _ => return Err(usage("wash-check [days]")),
+        },
+        "search" => {
+            let prefix = args.collect::<Vec<_>>().join(" ");
+            if prefix.is_empty() {
+                return Err(usage("search <prefix>"));
+            }
+            asset_tracker.display_search(&prefix).await?;
+        }
+        "quote" => match args.next() {
+            Some(ticker) => asset_tracker.display_quote(ticker).await?,
+            None => return Err(usage("quote <symbol>")),
+        },
+        "diff" => match args.next().map(str::parse::<i64>).unwrap_or(Ok(1)) {
+            Ok(days) if days >= 0 => asset_tracker.display_diff(days).await?,
+            _ => return Err(usage("diff [days]")),
+        },
+        "history" => match args.next().map(str::parse::<i64>).unwrap_or(Ok(30)) {
+            Ok(days) if days > 0 => asset_tracker.display_history(days).await?,
+            _ => return Err(usage("history [days]")),
+        },
+        "prune" => {
+            let flags: Vec<_> = args.collect();
+            let dry_run = flags.contains(&"--dry-run");
+            let keep_days = match flags.iter().position(|flag| *flag == "--keep-days") {
+                Some(i) => flags.get(i + 1).and_then(|days| days.parse::<i64>().ok()),
+                None => Some(365),
+            };
 
-        match input {
-            "quit" => break,
-            "help" => display_help(),
-            "home" => asset_tracker.display_home(),
-            "accounts" => asset_tracker.display_accounts(),
-            "positions" => asset_tracker.display_positions_with_dividends(None),
-            "summary" => asset_tracker.display_summary(),
-            _ => println!("Invalid command. Please try again."),
+            match keep_days {
+                Some(days) if days >= 0 => asset_tracker.prune(days, dry_run).await?,
+                _ => return Err(usage("prune [--keep-days N] [--dry-run]")),
+            }
+        }
+        "export" => match (args.next(), args.next()) {
+            (Some("positions"), Some(path)) => {
+                let rows = asset_tracker
+                    .export_positions(Path::new(path))
+                    .map_err(|err| {
+                        (
+                            "io",
+                            format!("Error exporting positions to {}: {}", path, err),
+                        )
+                    })?;
+                println!("Wrote {} positions to {}", rows, path);
+                println!();
+            }
+            (Some("json"), Some(path)) => {
+                asset_tracker.export_json(Path::new(path)).map_err(|err| {
+                    (
+                        "io",
+                        format!("Error exporting snapshot to {}: {}", path, err),
+                    )
+                })?;
+                println!("Wrote snapshot to {}", path);
+                println!();
+            }
+            _ => return Err(usage("export positions|json <path>")),
+        },
+        "household" => asset_tracker.display_household(&config.profiles).await?,
+        "dividends" => asset_tracker.display_dividend_income(),
+        "breakeven" => asset_tracker.display_breakeven(),
+        "notes" => asset_tracker.display_notes(),
+        "watchlist" => asset_tracker.display_watchlist().await?,
+        "watch" => match args.next() {
+            Some(ticker) => asset_tracker.watch(ticker).await?,
+            None => return Err(usage("watch <symbol>")),
+        },
+        "unwatch" => match args.next() {
+            Some(ticker) => asset_tracker.unwatch(ticker).await?,
+            None => return Err(usage("unwatch <symbol>")),
+        },
+        "validate" => asset_tracker.display_validation(),
+        "classify" => {
+            let symbol = args.next();
+            let asset_class = args.collect::<Vec<_>>().join(" ");
+            match symbol {
+                Some(symbol) if !asset_class.is_empty() => {
+                    asset_tracker.classify(symbol, &asset_class).await?
+                }
+                _ => return Err(usage("classify <symbol> <class>")),
+            }
         }
+        "set-note" => match args.next() {
+            Some(symbol) => {
+                let note = args.collect::<Vec<_>>().join(" ");
+                asset_tracker
+                    .set_note(symbol, note.trim_matches('"'))
+                    .await
+                    .map_err(|err| (err.kind(), format!("Error saving note: {}", err)))?;
+            }
+            None => return Err(usage("set-note <symbol> \"text\"")),
+        },
+        command => return Err(("invalid_command", format!("Invalid command: {}", command))),
     }
+
+    Ok(())
 }
 
 fn display_help() {
     println!("Below is a list of commands and their arguments:");
+    println!("(run with `--command setup` to redo the first-run setup)");
     println!();
     println!("`quit` — Quit the program");
     println!("`help` — Display these instructions again");
     println!("`home` — Display the home dashboard");
-    println!("`accounts` — Display all accounts and their balances");
-    println!("`positions` — Display all positions and their dividends");
-    println!("`summary` — Display a high-level summary of your portfolio");
+    println!("`accounts [type]` — Display all accounts and their balances, or just accounts of one type, e.g. `accounts TFSA`");
+    println!("`refresh` — Re-fetch accounts, balances and positions from Questrade");
+    println!("`positions [<symbol> | account <id>] [min <amount>] [--consolidate] [--after-tax] [--live]` — Display positions and their dividends, all or just one symbol or account, optionally hiding ones worth less than an amount, merged by symbol across accounts, with estimated after-tax P&L or with today's move from live quotes");
+    println!("`summary [--as-of YYYY-MM-DD | --sum-only]` — Display a high-level summary of your portfolio, optionally from a past snapshot or as just the total equity");
+    println!("`allocation [by-account]` — Display the asset class breakdown, portfolio-wide or for each account on its own");
+    println!("`rebalance [--explain] [--cash] | --compare <strategy>...` — Suggest buys and sells to reach your target allocation, optionally showing the arithmetic, deploying idle account cash, or comparing named strategies from the config");
+    println!("`watchlist` — Display live quotes for every watched symbol");
+    println!(
+        "`watch <symbol>` / `unwatch <symbol>` — Add a symbol to, or remove it from, the watchlist"
+    );
+    println!("`search <prefix>` — List symbols whose ticker or name starts with the prefix");
+    println!("`quote <symbol>` — Display the live bid, ask, last price, volume and day range for a symbol");
+    println!(
+        "`history [days]` — Show total equity at each snapshot in the last [days] (default 30)"
+    );
+    println!("`diff [days]` — Show how each symbol's quantity and market value changed since the snapshot from [days] ago (default 1), including new and closed positions");
+    println!("`prune [--keep-days N] [--dry-run]` — Delete snapshots older than N days (default 365), or just count them");
+    println!("`export positions <path>` — Write all positions across accounts to a CSV file");
+    println!(
+        "`export json <path>` — Write accounts, balances, positions and composition to a JSON file"
+    );
+    println!("`household` — Display a combined summary across all configured profiles");
+    println!("`dividends` — Project annual and monthly dividend income by account and asset class");
+    println!("`breakeven` — Show how far each symbol's price must rise to get back to its average entry price");
+    println!("`notes` — Display the notes saved for your holdings");
+    println!(
+        "`set-note <symbol> \"text\"` — Save a note for a symbol, or clear it if no text is given"
+    );
+    println!("`classify <symbol> <class>` — Set a symbol's asset class, e.g. `classify SHOP.TO Stocks`, saved for future runs");
+    println!("`validate` — Check the loaded positions and balances for suspicious values");
+    println!("`activities [days]` — List trades, dividends, deposits and fees in the last [days] (default 30) across accounts");
+    println!("`performance [days]` — Estimate the gain on money deposited in the last [days] (default 1825) from current equity minus net deposits");
+    println!("`wash-check [days]` — Flag possible superficial losses in the last [days] (default 365) of trades");
+    println!();
+    println!("Shortcuts: `p` (positions), `s` (summary), `a` (accounts), `h` (home)");
+    println!("More aliases can be defined under [aliases] in the config file");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn one_shot_commands_run_against_cached_data() {
+        let db = db::TestDb::new("one_shot").await;
+        let config = Config::default();
+        let dataset = Dataset {
+            accounts: vec![asset_tracker::Account {
+                type_: String::from("TFSA"),
+                id: String::from("12345678"),
+            }],
+            ..Dataset::default()
+        };
+        let mut asset_tracker = AssetTracker::new(db.clone(), None, dataset, &config)
+            .await
+            .unwrap();
+
+        for command in ["home", "accounts", "positions", "summary"] {
+            let mut out = Vec::new();
+            assert_eq!(
+                run_command(&mut asset_tracker, &config, Format::Text, command, &mut out).await,
+                Ok(()),
+                "{}",
+                command
+            );
+            assert!(!out.is_empty(), "{} printed nothing", command);
+        }
+        let (kind, _) = run_command(
+            &mut asset_tracker,
+            &config,
+            Format::Text,
+            "bogus",
+            &mut Vec::new(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(kind, "invalid_command");
+    }
 }
@@ -1,15 +1,79 @@
-use crate::db::{DatabaseAPI, RefreshToken};
+use crate::{
+    asset_tracker::{display_account_id, SymbolID},
+    db::DatabaseAPI,
+};
+use chrono::{DateTime, FixedOffset};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fmt::Display};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::{Mutex, RwLock, RwLockReadGuard, Semaphore},
+    time::Instant,
+};
+use tracing::{debug, instrument};
 
 const LOGIN_URL: &str = "https://login.questrade.com/oauth2/token";
 
+/// How many requests may be waiting on Questrade at once while loading concurrently.
+const MAX_IN_FLIGHT_REQUESTS: usize = 8;
+
+/// How long before the access token expires to log in again, so requests already on their
+/// way don't get rejected.
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+/// How many times a request is retried after a connection error or 5xx by default.
+pub const DEFAULT_RETRIES: u32 = 3;
+
+/// The wait before the first retry, doubled for each one after.
+const RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum QuestradeAPIError {
     RequestError(reqwest::Error),
     JSONError(serde_json::Error),
     APIError(String),
+    HTTPError(reqwest::StatusCode, String),
     DBError(sqlx::Error),
+    /// Writing the output failed, e.g. because stdout was closed.
+    IOError(std::io::Error),
+}
+
+impl QuestradeAPIError {
+    /// A short, stable name for the kind of error, for machine-readable output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            QuestradeAPIError::RequestError(_) => "request",
+            QuestradeAPIError::JSONError(_) => "json",
+            QuestradeAPIError::APIError(_) => "api",
+            QuestradeAPIError::HTTPError(_, _) => "http",
+            QuestradeAPIError::DBError(_) => "database",
+            QuestradeAPIError::IOError(_) => "io",
+        }
+    }
+
+    /// Whether Questrade rejected the access token, e.g. because it expired.
+    pub fn is_unauthorized(&self) -> bool {
+        matches!(self, QuestradeAPIError::HTTPError(status, _) if *status == reqwest::StatusCode::UNAUTHORIZED)
+    }
+
+    /// Whether the request might succeed if sent again: the connection failed or timed out,
+    /// or Questrade had a server error.
+    fn is_transient(&self) -> bool {
+        match self {
+            QuestradeAPIError::RequestError(err) => err.is_connect() || err.is_timeout(),
+            QuestradeAPIError::HTTPError(status, _) => status.is_server_error(),
+            _ => false,
+        }
+    }
+
+    /// Whether Questrade refused the request because the token can't access the resource.
+    pub fn is_permission_denied(&self) -> bool {
+        matches!(self, QuestradeAPIError::HTTPError(status, _) if *status == reqwest::StatusCode::FORBIDDEN)
+    }
 }
 
 impl Display for QuestradeAPIError {
@@ -18,7 +82,11 @@ impl Display for QuestradeAPIError {
             QuestradeAPIError::RequestError(err) => write!(f, "Request error: {}", err),
             QuestradeAPIError::JSONError(err) => write!(f, "JSON error: {}", err),
             QuestradeAPIError::APIError(msg) => write!(f, "Questrade API error: {}", msg),
+            QuestradeAPIError::HTTPError(status, body) => {
+                write!(f, "Questrade API error ({}): {}", status, body)
+            }
             QuestradeAPIError::DBError(err) => write!(f, "DB error: {}", err),
+            QuestradeAPIError::IOError(err) => write!(f, "IO error: {}", err),
         }
     }
 }
@@ -41,6 +109,28 @@ impl From<sqlx::Error> for QuestradeAPIError {
     }
 }
 
+impl From<std::io::Error> for QuestradeAPIError {
+    fn from(err: std::io::Error) -> Self {
+        QuestradeAPIError::IOError(err)
+    }
+}
+
+/// The JSON body Questrade sends with an error response.
+#[derive(Debug, Deserialize)]
+struct ApiErrorBody {
+    code: u32,
+    message: String,
+}
+
+/// Formats an error response body as `code: message`, or returns it as is if it isn't
+/// Questrade's usual error JSON.
+fn describe_error_body(body: String) -> String {
+    match serde_json::from_str::<ApiErrorBody>(&body) {
+        Ok(error) => format!("{}: {}", error.code, error.message),
+        Err(_) => body,
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OAuth2Token {
     access_token: String,
@@ -48,62 +138,711 @@ pub struct OAuth2Token {
     expires_in: u16,
     pub refresh_token: String,
     api_server: String,
+    #[serde(skip, default = "Instant::now")]
+    obtained_at: Instant,
+}
+
+impl OAuth2Token {
+    /// Whether the access token has expired or will within `TOKEN_EXPIRY_MARGIN`.
+    fn is_expired(&self) -> bool {
+        Instant::now() + TOKEN_EXPIRY_MARGIN >= self.expires_at()
+    }
+
+    fn expires_at(&self) -> Instant {
+        self.obtained_at + Duration::from_secs(self.expires_in.into())
+    }
+
+    /// The server requests are sent to, which Questrade picks per login.
+    pub fn api_server(&self) -> &str {
+        &self.api_server
+    }
+
+    /// How long until the access token expires, zero if it already has.
+    pub fn time_until_expiry(&self) -> Duration {
+        self.expires_at().saturating_duration_since(Instant::now())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ServerTime {
+    time: DateTime<FixedOffset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SymbolSearch {
+    symbols: Vec<SymbolSearchResult>,
+}
+
+/// One match from `v1/symbols/search`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolSearchResult {
+    pub symbol: String,
+    pub symbol_id: SymbolID,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub security_type: String,
+    #[serde(default)]
+    pub listing_exchange: String,
+    #[serde(default)]
+    pub currency: String,
+}
+
+/// Spaces out consecutive requests so they're at least `interval` apart, even when they're
+/// issued concurrently.
+pub struct Pacer {
+    interval: Duration,
+    last: Mutex<Option<Instant>>,
+}
+
+impl Pacer {
+    pub fn new(interval: Duration) -> Self {
+        Pacer {
+            interval,
+            last: Mutex::new(None),
+        }
+    }
+
+    pub async fn wait(&self) {
+        let mut last = self.last.lock().await;
+        if let Some(last) = *last {
+            tokio::time::sleep_until(last + self.interval).await;
+        }
+
+        *last = Some(Instant::now());
+    }
+}
+
+/// The request allowance Questrade reported on its latest response.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RateLimit {
+    remaining: u64,
+    /// When the allowance refills.
+    reset: SystemTime,
+}
+
+impl RateLimit {
+    fn from_headers(headers: &reqwest::header::HeaderMap) -> Option<Self> {
+        let header = |name: &str| headers.get(name)?.to_str().ok()?.parse::<u64>().ok();
+
+        Some(RateLimit {
+            remaining: header("X-RateLimit-Remaining")?,
+            reset: UNIX_EPOCH + Duration::from_secs(header("X-RateLimit-Reset")?),
+        })
+    }
+
+    /// How long to hold off before the next request, if the allowance has run out.
+    fn wait_time(&self, now: SystemTime) -> Option<Duration> {
+        if self.remaining > 0 {
+            return None;
+        }
+
+        self.reset.duration_since(now).ok()
+    }
 }
 
 pub struct QuestradeAPI {
     client: reqwest::Client,
-    token: OAuth2Token,
+    token: RwLock<OAuth2Token>,
+    /// Where the rotated refresh token is saved after logging in again. `None` for test
+    /// clients, which can't log in again.
+    db: Option<DatabaseAPI>,
+    /// The label of the stored login this client uses.
+    label: String,
+    /// Where to log in again; Questrade's login server except in tests.
+    login_url: String,
+    in_flight: Semaphore,
+    rate_limit: Mutex<Option<RateLimit>>,
+    retries: u32,
+    /// Whether account numbers in logged request paths are masked, as with `--mask-accounts`.
+    mask_accounts: bool,
 }
 
 impl QuestradeAPI {
-    pub async fn new(db: DatabaseAPI) -> Result<Self, QuestradeAPIError> {
+    /// Logs in with the stored refresh token labelled `label`, or the most recently added one
+    /// if `label` is `None`.
+    pub async fn new(db: &DatabaseAPI, label: Option<&str>) -> Result<Self, QuestradeAPIError> {
+        Self::log_in(db, label, LOGIN_URL).await
+    }
+
+    /// Like `new`, logging in at `login_url`.
+    async fn log_in(
+        db: &DatabaseAPI,
+        label: Option<&str>,
+        login_url: &str,
+    ) -> Result<Self, QuestradeAPIError> {
         let client = reqwest::Client::new();
-        let old_refresh_token = match db.get_refresh_token().await {
-            Ok(token) => token,
-            Err(err) => {
+        let stored = match label {
+            Some(label) => db.get_refresh_token_by_label(label).await,
+            None => db.get_refresh_token().await,
+        };
+        let old_refresh_token = match (stored, label) {
+            (Ok(token), _) => token,
+            (Err(sqlx::Error::RowNotFound), Some(label)) => {
+                let labels: Vec<_> = db
+                    .list_refresh_tokens()
+                    .await?
+                    .into_iter()
+                    .map(|token| token.label)
+                    .collect();
+                return Err(QuestradeAPIError::APIError(format!(
+                    "no login labelled {}, stored logins: {}",
+                    label,
+                    labels.join(", ")
+                )));
+            }
+            (Err(err), _) => {
                 eprintln!("Refresh token not found in database. Run --help to see how to add one.");
                 return Err(QuestradeAPIError::DBError(err));
             }
         };
 
-        let token = Self::get_oauth2_token(&client, &old_refresh_token).await?;
+        let token =
+            Self::get_oauth2_token(&client, login_url, &old_refresh_token.refresh_token).await?;
         db.update_refresh_token(&old_refresh_token, &token.refresh_token)
             .await?;
+        validate_api_server(&token.api_server)?;
+
+        Ok(Self {
+            client,
+            token: RwLock::new(token),
+            db: Some(db.clone()),
+            label: old_refresh_token.label,
+            login_url: login_url.to_string(),
+            in_flight: Semaphore::new(MAX_IN_FLIGHT_REQUESTS),
+            rate_limit: Mutex::new(None),
+            retries: DEFAULT_RETRIES,
+            mask_accounts: false,
+        })
+    }
+
+    /// The current login, for diagnostics.
+    pub async fn token(&self) -> RwLockReadGuard<'_, OAuth2Token> {
+        self.token.read().await
+    }
+
+    /// Retries requests that fail with a connection error or 5xx up to `retries` times.
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Masks the account numbers in the request paths it logs, the way they're printed.
+    pub fn with_masked_accounts(mut self, mask_accounts: bool) -> Self {
+        self.mask_accounts = mask_accounts;
+        self
+    }
+
+    /// Logs in again with the stored refresh token if the access token is still `stale`.
+    /// Concurrent requests that were all rejected with the same token only log in once,
+    /// since Questrade's refresh tokens can be used only once.
+    async fn refresh_after(&self, stale_access_token: &str) -> Result<(), QuestradeAPIError> {
+        let mut token = self.token.write().await;
+        if token.access_token != stale_access_token {
+            return Ok(());
+        }
+
+        let Some(db) = self.db.as_ref() else {
+            return Err(QuestradeAPIError::APIError(String::from(
+                "access token expired and this client can't log in again",
+            )));
+        };
+
+        debug!(label = %self.label, "access token expired, logging in again");
+        let old_refresh_token = db.get_refresh_token_by_label(&self.label).await?;
+        let new_token = Self::get_oauth2_token(
+            &self.client,
+            &self.login_url,
+            &old_refresh_token.refresh_token,
+        )
+        .await?;
+        db.update_refresh_token(&old_refresh_token, &new_token.refresh_token)
+            .await?;
+        validate_api_server(&new_token.api_server)?;
+        *token = new_token;
+
+        Ok(())
+    }
+
+    /// Logs in with a refresh token that isn't in the database yet, storing the rotated
+    /// token Questrade hands back under `label` only once the login has succeeded.
+    pub async fn from_refresh_token(
+        db: &DatabaseAPI,
+        label: &str,
+        refresh_token: &str,
+    ) -> Result<Self, QuestradeAPIError> {
+        let client = reqwest::Client::new();
+        let token = Self::get_oauth2_token(&client, LOGIN_URL, refresh_token).await?;
+        db.insert_refresh_token(label, &token.refresh_token).await?;
+        validate_api_server(&token.api_server)?;
+
+        Ok(Self {
+            client,
+            token: RwLock::new(token),
+            db: Some(db.clone()),
+            label: label.to_string(),
+            login_url: LOGIN_URL.to_string(),
+            in_flight: Semaphore::new(MAX_IN_FLIGHT_REQUESTS),
+            rate_limit: Mutex::new(None),
+            retries: DEFAULT_RETRIES,
+            mask_accounts: false,
+        })
+    }
+
+    pub async fn get_server_time(&self) -> Result<DateTime<FixedOffset>, QuestradeAPIError> {
+        let resp = self.make_request(String::from("v1/time")).await?;
+
+        Ok(serde_json::from_str::<ServerTime>(&resp)?.time)
+    }
+
+    /// Symbols whose ticker or description starts with `prefix`.
+    pub async fn search_symbols(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<SymbolSearchResult>, QuestradeAPIError> {
+        let resp = self
+            .make_request(format!(
+                "v1/symbols/search?prefix={}",
+                encode_query_value(&prefix.to_uppercase())
+            ))
+            .await?;
 
-        Ok(Self { client, token })
+        Ok(serde_json::from_str::<SymbolSearch>(&resp)?.symbols)
     }
 
+    /// A client that sends requests straight to `api_server`, for tests against a mock server.
+    #[cfg(test)]
+    pub fn for_server(api_server: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            token: RwLock::new(OAuth2Token {
+                access_token: String::from("access"),
+                token_type: String::from("Bearer"),
+                expires_in: 1800,
+                refresh_token: String::from("refresh"),
+                api_server: api_server.to_string(),
+                obtained_at: Instant::now(),
+            }),
+            db: None,
+            label: String::from(crate::db::DEFAULT_TOKEN_LABEL),
+            login_url: LOGIN_URL.to_string(),
+            in_flight: Semaphore::new(MAX_IN_FLIGHT_REQUESTS),
+            rate_limit: Mutex::new(None),
+            retries: DEFAULT_RETRIES,
+            mask_accounts: false,
+        }
+    }
+
+    /// Trades `refresh_token` for a new login. Questrade rotates the refresh token as soon as
+    /// it answers, so callers must save the new one before checking the rest of the token,
+    /// e.g. with `validate_api_server`, or the stored login is lost.
+    #[instrument(level = "debug", skip_all)]
     async fn get_oauth2_token(
         client: &reqwest::Client,
-        refresh_token: &RefreshToken,
+        login_url: &str,
+        refresh_token: &str,
     ) -> Result<OAuth2Token, QuestradeAPIError> {
         let mut params = HashMap::new();
         params.insert("grant_type", "refresh_token");
-        params.insert("refresh_token", &refresh_token.refresh_token);
-
-        let body = client
-            .get(LOGIN_URL)
-            .form(&params)
-            .send()
-            .await?
-            .text()
-            .await?;
+        params.insert("refresh_token", refresh_token);
+
+        let started = Instant::now();
+        let resp = client.get(login_url).form(&params).send().await?;
+        let status = resp.status();
+        let body = resp.text().await?;
+        debug!(%status, duration = ?started.elapsed(), "login response");
+        if !status.is_success() {
+            return Err(QuestradeAPIError::HTTPError(
+                status,
+                describe_error_body(body),
+            ));
+        }
 
         Ok(serde_json::from_str::<OAuth2Token>(&body)?)
     }
 
+    /// Sends an authenticated GET for `path`, logging in again first if the access token is
+    /// about to expire. If it's rejected anyway, logs in again and retries once; a second
+    /// rejection is returned as an error.
+    #[instrument(
+        level = "debug",
+        skip_all,
+        fields(path = %logged_path(&path, self.mask_accounts))
+    )]
     pub async fn make_request(&self, path: String) -> Result<String, QuestradeAPIError> {
-        let resp = self
-            .client
-            .get(format!("{}{}", self.token.api_server, path))
-            .bearer_auth(&self.token.access_token)
-            .send()
-            .await?;
+        let _permit = self
+            .in_flight
+            .acquire()
+            .await
+            .expect("the in-flight semaphore is never closed");
+
+        let (access_token, expired) = {
+            let token = self.token.read().await;
+            (token.access_token.clone(), token.is_expired())
+        };
+        let access_token = if expired {
+            self.refresh_after(&access_token).await?;
+            self.token.read().await.access_token.clone()
+        } else {
+            access_token
+        };
 
-        if !resp.status().is_success() {
-            return Err(QuestradeAPIError::APIError(resp.text().await?));
+        match self.send_retrying(&path).await {
+            Err(err) if err.is_unauthorized() => {
+                self.refresh_after(&access_token).await?;
+                self.send_retrying(&path).await
+            }
+            result => result,
+        }
+    }
+
+    /// Sends `path`, retrying transient failures with exponential backoff. The last error is
+    /// returned as is once the retries run out.
+    async fn send_retrying(&self, path: &str) -> Result<String, QuestradeAPIError> {
+        let mut backoff = RETRY_BACKOFF;
+
+        for _ in 0..self.retries {
+            match self.send(path).await {
+                Err(err) if err.is_transient() => {
+                    debug!(error = %err, ?backoff, "retrying");
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                result => return result,
+            }
+        }
+
+        self.send(path).await
+    }
+
+    /// Sends `path` once, first waiting for the rate limit to reset if Questrade said the
+    /// last response used up the allowance.
+    async fn send(&self, path: &str) -> Result<String, QuestradeAPIError> {
+        let wait = match *self.rate_limit.lock().await {
+            Some(rate_limit) => rate_limit.wait_time(SystemTime::now()),
+            None => None,
+        };
+        if let Some(wait) = wait {
+            debug!(?wait, "waiting for the rate limit to reset");
+            tokio::time::sleep(wait).await;
+        }
+
+        let request = {
+            let token = self.token.read().await;
+            self.client
+                .get(join_url(&token.api_server, path))
+                .bearer_auth(&token.access_token)
+        };
+        let started = Instant::now();
+        let resp = request.send().await?;
+        debug!(
+            path = %logged_path(path, self.mask_accounts),
+            status = %resp.status(),
+            duration = ?started.elapsed(),
+            "response"
+        );
+        if let Some(rate_limit) = RateLimit::from_headers(resp.headers()) {
+            *self.rate_limit.lock().await = Some(rate_limit);
+        }
+
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(QuestradeAPIError::HTTPError(
+                status,
+                describe_error_body(resp.text().await?),
+            ));
         }
 
         Ok(resp.text().await?)
     }
 }
+
+/// Percent-encodes `value` for use in a query string, so names with spaces or `&` survive.
+fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Checks the API server Questrade handed back is an http(s) URL, so a malformed one fails at
+/// login rather than as a confusing error on every request.
+fn validate_api_server(api_server: &str) -> Result<(), QuestradeAPIError> {
+    match reqwest::Url::parse(api_server) {
+        Ok(url) if ["http", "https"].contains(&url.scheme()) && url.has_host() => Ok(()),
+        _ => Err(QuestradeAPIError::APIError(format!(
+            "login returned an invalid API server {:?}",
+            api_server
+        ))),
+    }
+}
+
+/// `path` as it's logged, with the account number in `v1/accounts/{id}/...` shown the way
+/// it's printed.
+fn logged_path(path: &str, mask_accounts: bool) -> String {
+    let Some(rest) = path.strip_prefix("v1/accounts/") else {
+        return path.to_string();
+    };
+    let end = rest.find(['/', '?']).unwrap_or(rest.len());
+
+    format!(
+        "v1/accounts/{}{}",
+        display_account_id(&rest[..end], mask_accounts),
+        &rest[end..]
+    )
+}
+
+/// Joins the API server and a request path with exactly one slash between them,
+/// whether or not `api_server` ends with one or `path` starts with one.
+fn join_url(api_server: &str, path: &str) -> String {
+    format!(
+        "{}/{}",
+        api_server.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn token_diagnostics_show_server_and_expiry() {
+        let api = QuestradeAPI::for_server("https://api01.iq.questrade.com/");
+        let token = api.token().await;
+
+        assert_eq!(token.api_server(), "https://api01.iq.questrade.com/");
+        assert!(token.time_until_expiry() <= Duration::from_secs(1800));
+        assert!(token.time_until_expiry() > Duration::from_secs(1790));
+    }
+
+    #[tokio::test]
+    async fn pacer_spaces_out_requests() {
+        let pacer = Pacer::new(Duration::from_millis(20));
+        let start = Instant::now();
+
+        pacer.wait().await;
+        assert!(start.elapsed() < Duration::from_millis(20));
+
+        pacer.wait().await;
+        pacer.wait().await;
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn token_expires_within_margin() {
+        let token = |expires_in: u16| OAuth2Token {
+            access_token: String::from("access"),
+            token_type: String::from("Bearer"),
+            expires_in,
+            refresh_token: String::from("refresh"),
+            api_server: String::from("https://api01.iq.questrade.com/"),
+            obtained_at: Instant::now(),
+        };
+
+        assert!(!token(1800).is_expired());
+        assert!(token(20).is_expired());
+    }
+
+    #[test]
+    fn rate_limit_waits_only_when_exhausted() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("X-RateLimit-Remaining", "0".parse().unwrap());
+        headers.insert("X-RateLimit-Reset", "1700000010".parse().unwrap());
+        let rate_limit = RateLimit::from_headers(&headers).unwrap();
+
+        let now = UNIX_EPOCH + Duration::from_secs(1700000000);
+        assert_eq!(rate_limit.wait_time(now), Some(Duration::from_secs(10)));
+        assert_eq!(rate_limit.wait_time(now + Duration::from_secs(20)), None);
+
+        let rate_limit = RateLimit {
+            remaining: 5,
+            ..rate_limit
+        };
+        assert_eq!(rate_limit.wait_time(now), None);
+
+        assert_eq!(
+            RateLimit::from_headers(&reqwest::header::HeaderMap::new()),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_token_without_login_is_an_error() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/v1/accounts"))
+            .respond_with(ResponseTemplate::new(401))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let api = QuestradeAPI::for_server(&server.uri());
+        let err = api
+            .make_request(String::from("v1/accounts"))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, QuestradeAPIError::APIError(_)));
+    }
+
+    #[tokio::test]
+    async fn rotated_refresh_token_is_saved_before_the_api_server_is_checked() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/oauth2/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"access_token": "access", "token_type": "Bearer", "expires_in": 1800,
+                "refresh_token": "rotated", "api_server": "not a url"}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let db = crate::db::TestDb::new("rotated_refresh_token").await;
+        db.insert_refresh_token("me", "original").await.unwrap();
+
+        let login_url = format!("{}/oauth2/token", server.uri());
+        assert!(QuestradeAPI::log_in(&db, None, &login_url).await.is_err());
+        assert_eq!(
+            db.get_refresh_token().await.unwrap().refresh_token,
+            "rotated"
+        );
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/v1/time"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(path("/v1/time"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r#"{"time":"2024-06-03T10:00:00-04:00"}"#),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let api = QuestradeAPI::for_server(&server.uri()).with_retries(2);
+        assert!(api.get_server_time().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/v1/accounts"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let api = QuestradeAPI::for_server(&server.uri());
+        let err = api
+            .make_request(String::from("v1/accounts"))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, QuestradeAPIError::HTTPError(status, _) if status == 404));
+    }
+
+    #[tokio::test]
+    async fn error_bodies_are_shown_as_code_and_message() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/v1/accounts/1/positions"))
+            .respond_with(
+                ResponseTemplate::new(400)
+                    .set_body_string(r#"{"code":1002,"message":"Invalid account number"}"#),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(path("/v1/accounts"))
+            .respond_with(ResponseTemplate::new(404).set_body_string("Not Found"))
+            .mount(&server)
+            .await;
+
+        let api = QuestradeAPI::for_server(&server.uri());
+        let err = api
+            .make_request(String::from("v1/accounts/1/positions"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, QuestradeAPIError::HTTPError(_, body) if body == "1002: Invalid account number")
+        );
+
+        let err = api
+            .make_request(String::from("v1/accounts"))
+            .await
+            .unwrap_err();
+        assert!(matches!(&err, QuestradeAPIError::HTTPError(_, body) if body == "Not Found"));
+    }
+
+    #[test]
+    fn query_values_are_percent_encoded() {
+        assert_eq!(encode_query_value("VFV.TO"), "VFV.TO");
+        assert_eq!(encode_query_value("S&P 500"), "S%26P%20500");
+    }
+
+    #[test]
+    fn logged_paths_mask_account_numbers() {
+        assert_eq!(
+            logged_path("v1/accounts/12345678/positions?offset=0", true),
+            "v1/accounts/***678/positions?offset=0"
+        );
+        assert_eq!(
+            logged_path("v1/accounts/12345678/balances", false),
+            "v1/accounts/12345678/balances"
+        );
+        assert_eq!(logged_path("v1/accounts", true), "v1/accounts");
+    }
+
+    #[test]
+    fn join_url_with_trailing_slash_on_server() {
+        assert_eq!(
+            join_url("https://api01.iq.questrade.com/", "v1/accounts"),
+            "https://api01.iq.questrade.com/v1/accounts"
+        );
+        assert_eq!(
+            join_url("https://api01.iq.questrade.com/", "/v1/accounts"),
+            "https://api01.iq.questrade.com/v1/accounts"
+        );
+    }
+
+    #[test]
+    fn join_url_without_trailing_slash_on_server() {
+        assert_eq!(
+            join_url("https://api01.iq.questrade.com", "v1/accounts"),
+            "https://api01.iq.questrade.com/v1/accounts"
+        );
+        assert_eq!(
+            join_url("https://api01.iq.questrade.com", "/v1/accounts"),
+            "https://api01.iq.questrade.com/v1/accounts"
+        );
+    }
+
+    #[test]
+    fn api_server_must_be_an_http_url() {
+        assert!(validate_api_server("https://api01.iq.questrade.com/").is_ok());
+        assert!(validate_api_server("https://api01.iq.questrade.com").is_ok());
+        assert!(validate_api_server("api01.iq.questrade.com").is_err());
+        assert!(validate_api_server("").is_err());
+        assert!(validate_api_server("file:///etc/passwd").is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use crate::{
+    asset_tracker::Accounts,
+    config::Targets,
+    db::{DatabaseAPI, DEFAULT_TOKEN_LABEL},
+    questrade_api::{QuestradeAPI, QuestradeAPIError},
+};
+use colored::Colorize;
+use std::{
+    fmt::Display,
+    io::{self, Write},
+    path::Path,
+};
+
+#[derive(Debug)]
+pub enum SetupError {
+    IOError(io::Error),
+    TOMLError(String),
+    QuestradeError(QuestradeAPIError),
+    Cancelled,
+}
+
+impl Display for SetupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SetupError::IOError(err) => write!(f, "IO error: {}", err),
+            SetupError::TOMLError(msg) => write!(f, "TOML error: {}", msg),
+            SetupError::QuestradeError(err) => write!(f, "{}", err),
+            SetupError::Cancelled => write!(f, "Setup cancelled"),
+        }
+    }
+}
+
+impl From<io::Error> for SetupError {
+    fn from(err: io::Error) -> Self {
+        SetupError::IOError(err)
+    }
+}
+
+impl From<QuestradeAPIError> for SetupError {
+    fn from(err: QuestradeAPIError) -> Self {
+        SetupError::QuestradeError(err)
+    }
+}
+
+/// Walks a new user through connecting their Questrade login and choosing display
+/// preferences, then saves the token to the DB and the preferences to the config file.
+/// The stocks, bonds and cash targets start from `targets`, the ones already configured, and
+/// their margins and other classes are kept as they are.
+pub async fn run(
+    db: &DatabaseAPI,
+    config_path: &Path,
+    targets: &Targets,
+) -> Result<(), SetupError> {
+    println!("{}", "Questrade Asset Tracker setup".cyan());
+    println!();
+    println!("To connect, log in to Questrade and open App Hub > API centre, register a");
+    println!("personal app, then generate a new token and paste it below.");
+    println!();
+
+    let refresh_token = prompt("Refresh token", None)?;
+    if refresh_token.is_empty() {
+        return Err(SetupError::Cancelled);
+    }
+
+    let label = prompt("Label for this login", Some(DEFAULT_TOKEN_LABEL))?;
+
+    println!("Connecting to Questrade...");
+    let questrade_api = QuestradeAPI::from_refresh_token(db, &label, &refresh_token).await?;
+    let resp = questrade_api
+        .make_request(String::from("v1/accounts"))
+        .await?;
+    let accounts = serde_json::from_str::<Accounts>(&resp)
+        .map_err(QuestradeAPIError::from)?
+        .accounts;
+    println!(
+        "{}",
+        format!("Connected! Found {} account(s)", accounts.len()).green()
+    );
+    println!();
+
+    let display_currency = loop {
+        let currency = prompt("Display currency (CAD or USD)", Some("CAD"))?.to_uppercase();
+        if currency == "CAD" || currency == "USD" {
+            break currency;
+        }
+        println!("Please enter CAD or USD");
+    };
+
+    let targets = loop {
+        let targets = Targets {
+            stocks: prompt_percent("Stocks target %", targets.stocks)?,
+            bonds: prompt_percent("Bonds target %", targets.bonds)?,
+            cash: prompt_percent("Cash target %", targets.cash)?,
+            ..targets.clone()
+        };
+
+        if targets.sum_to_100() {
+            break targets;
+        }
+        if targets.other.is_empty() {
+            println!("Targets must add up to 100, please try again");
+        } else {
+            let other: f64 = targets.other.values().sum();
+            println!(
+                "Targets must add up to 100 with the {}% for other classes in the config, please try again",
+                other
+            );
+        }
+    };
+
+    write_config(config_path, &display_currency, &targets)?;
+    println!();
+    println!("Saved preferences to {}", config_path.display());
+    println!("{}", "Setup complete!".green());
+    println!();
+
+    Ok(())
+}
+
+fn prompt(question: &str, default: Option<&str>) -> Result<String, SetupError> {
+    match default {
+        Some(default) => print!("{} [{}]: ", question, default),
+        None => print!("{}: ", question),
+    }
+    io::stdout().flush()?;
+
+    let mut input = String::new();
+    if io::stdin().read_line(&mut input)? == 0 {
+        return Err(SetupError::Cancelled);
+    }
+
+    let input = input.trim();
+    Ok(match default {
+        Some(default) if input.is_empty() => default.to_string(),
+        _ => input.to_string(),
+    })
+}
+
+fn prompt_percent(question: &str, default: f64) -> Result<f64, SetupError> {
+    loop {
+        match prompt(question, Some(&default.to_string()))?.parse::<f64>() {
+            Ok(percent) if (0.0..=100.0).contains(&percent) => return Ok(percent),
+            _ => println!("Please enter a number between 0 and 100"),
+        }
+    }
+}
+
+/// Updates the setup keys in the config file, keeping any other settings already in it,
+/// including the rest of the `[targets]` table.
+fn write_config(path: &Path, display_currency: &str, targets: &Targets) -> Result<(), SetupError> {
+    let mut table = if path.exists() {
+        std::fs::read_to_string(path)?
+            .parse::<toml::Table>()
+            .map_err(|err| SetupError::TOMLError(err.to_string()))?
+    } else {
+        toml::Table::new()
+    };
+
+    table.insert(
+        String::from("display_currency"),
+        toml::Value::String(display_currency.to_string()),
+    );
+    let targets_table = match table
+        .entry("targets")
+        .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+    {
+        toml::Value::Table(targets_table) => targets_table,
+        _ => return Err(SetupError::TOMLError(String::from("targets isn't a table"))),
+    };
+    for (key, target) in [
+        ("stocks", targets.stocks),
+        ("bonds", targets.bonds),
+        ("cash", targets.cash),
+    ] {
+        targets_table.insert(String::from(key), toml::Value::Float(target));
+    }
+
+    let contents = toml::to_string(&table).map_err(|err| SetupError::TOMLError(err.to_string()))?;
+    std::fs::write(path, contents)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_config_keeps_other_targets() {
+        let path = std::env::temp_dir().join(format!(
+            "questrade_asset_tracker_setup_{}.toml",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "columns_width = 100\n\n[targets]\nstocks = 50.0\nwarning_margin = 1.0\n\n[targets.classes]\nREITs = 10.0\n",
+        )
+        .unwrap();
+        let targets = Targets {
+            stocks: 60.0,
+            bonds: 30.0,
+            cash: 0.0,
+            ..Targets::default()
+        };
+
+        write_config(&path, "USD", &targets).unwrap();
+        let table: toml::Table = std::fs::read_to_string(&path).unwrap().parse().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(table["display_currency"].as_str(), Some("USD"));
+        assert_eq!(table["columns_width"].as_integer(), Some(100));
+        let targets = table["targets"].as_table().unwrap();
+        assert_eq!(targets["stocks"].as_float(), Some(60.0));
+        assert_eq!(targets["bonds"].as_float(), Some(30.0));
+        assert_eq!(targets["warning_margin"].as_float(), Some(1.0));
+        assert_eq!(targets["classes"]["REITs"].as_float(), Some(10.0));
+    }
+}
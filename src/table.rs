@@ -0,0 +1,252 @@
+use colored::{ColoredString, Colorize};
+use serde::Deserialize;
+use std::{fmt, str::FromStr};
+
+/// How table columns are sized: `Auto` fits each column to its widest cell, `Fixed` keeps
+/// the preset widths regardless of content.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    #[default]
+    Auto,
+    Fixed,
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Layout::Auto),
+            "fixed" => Ok(Layout::Fixed),
+            _ => Err(format!(
+                "unknown column width {}, expected auto or fixed",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+struct Column {
+    header: &'static str,
+    width: usize,
+    align: Align,
+    optional: bool,
+}
+
+enum Row {
+    Cells(Vec<ColoredString>),
+    Rule,
+}
+
+/// A `|`-separated table with a `-` rule under the header, printed via `Display`. Optional
+/// columns are left out, rightmost first, when the table is wider than `max_width`.
+pub struct Table {
+    title: Option<&'static str>,
+    layout: Layout,
+    columns: Vec<Column>,
+    rows: Vec<Row>,
+    max_width: Option<usize>,
+}
+
+impl Table {
+    /// A table that fits the terminal's width if stdout is one.
+    pub fn new(layout: Layout) -> Self {
+        Table {
+            title: None,
+            layout,
+            columns: Vec::new(),
+            rows: Vec::new(),
+            max_width: terminal_size::terminal_size().map(|(width, _)| width.0 as usize),
+        }
+    }
+
+    #[cfg(test)]
+    fn max_width(mut self, max_width: usize) -> Self {
+        self.max_width = Some(max_width);
+        self
+    }
+
+    /// Prints `title` centred in a cyan rule as wide as the table.
+    pub fn title(mut self, title: &'static str) -> Self {
+        self.title = Some(title);
+        self
+    }
+
+    /// Adds a column that is `width` wide in the fixed layout.
+    pub fn column(mut self, header: &'static str, width: usize, align: Align) -> Self {
+        self.columns.push(Column {
+            header,
+            width,
+            align,
+            optional: false,
+        });
+        self
+    }
+
+    /// Marks the last added column as one to leave out on narrow terminals.
+    pub fn optional(mut self) -> Self {
+        if let Some(column) = self.columns.last_mut() {
+            column.optional = true;
+        }
+        self
+    }
+
+    /// Adds a row, which may stop short of the last columns.
+    pub fn row(&mut self, cells: Vec<ColoredString>) {
+        self.rows.push(Row::Cells(cells));
+    }
+
+    /// Adds a `=` rule, e.g. above a totals row.
+    pub fn rule(&mut self) {
+        self.rows.push(Row::Rule);
+    }
+
+    fn widths(&self) -> Vec<usize> {
+        self.columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| match self.layout {
+                Layout::Fixed => column.width,
+                Layout::Auto => self
+                    .rows
+                    .iter()
+                    .filter_map(|row| match row {
+                        Row::Cells(cells) => cells.get(i).map(|cell| cell.chars().count()),
+                        Row::Rule => None,
+                    })
+                    .fold(column.header.chars().count(), usize::max),
+            })
+            .collect()
+    }
+}
+
+/// The width of a line holding columns `widths` wide, counting the ` | ` between them.
+fn line_width(widths: &[usize]) -> usize {
+    widths.iter().sum::<usize>() + 3 * widths.len().saturating_sub(1)
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let all_widths = self.widths();
+        let mut shown: Vec<_> = (0..self.columns.len()).collect();
+        if let Some(max_width) = self.max_width {
+            while line_width(&shown.iter().map(|&i| all_widths[i]).collect::<Vec<_>>()) > max_width
+            {
+                match shown.iter().rposition(|&i| self.columns[i].optional) {
+                    Some(position) => shown.remove(position),
+                    None => break,
+                };
+            }
+        }
+        let widths: Vec<_> = shown.iter().map(|&i| all_widths[i]).collect();
+        let line_width = line_width(&widths);
+
+        if let Some(title) = self.title {
+            let padding = "-".repeat(line_width.saturating_sub(title.len()) / 2);
+            writeln!(f, "{}", format!("{}{}{}", padding, title, padding).cyan())?;
+            writeln!(f)?;
+        }
+
+        let write_cells = |f: &mut fmt::Formatter, cells: Vec<(&dyn fmt::Display, Align)>| {
+            let mut line = Vec::new();
+            for ((cell, align), width) in cells.into_iter().zip(&widths) {
+                line.push(match align {
+                    Align::Left => format!("{:<width$}", cell, width = width),
+                    Align::Right => format!("{:>width$}", cell, width = width),
+                });
+            }
+            writeln!(f, "{}", line.join(" | ").trim_end())
+        };
+
+        write_cells(
+            f,
+            shown
+                .iter()
+                .map(|&i| {
+                    let column = &self.columns[i];
+                    (&column.header as &dyn fmt::Display, column.align)
+                })
+                .collect(),
+        )?;
+        writeln!(f, "{}", "-".repeat(line_width))?;
+
+        for row in &self.rows {
+            match row {
+                Row::Cells(cells) => write_cells(
+                    f,
+                    shown
+                        .iter()
+                        .filter_map(|&i| cells.get(i).map(|cell| (i, cell)))
+                        .map(|(i, cell)| (cell as &dyn fmt::Display, self.columns[i].align))
+                        .collect(),
+                )?,
+                Row::Rule => writeln!(f, "{}", "=".repeat(line_width))?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(layout: Layout) -> Table {
+        let mut table = Table::new(layout)
+            .max_width(usize::MAX)
+            .column("Symbol", 10, Align::Left)
+            .column("Value", 10, Align::Right);
+        table.row(vec!["VERYLONGSYMBOL".normal(), "1.00".normal()]);
+        table.rule();
+        table.row(vec!["Total".normal()]);
+        table
+    }
+
+    #[test]
+    fn auto_layout_fits_widest_cell() {
+        colored::control::set_override(false);
+        assert_eq!(
+            table(Layout::Auto).to_string(),
+            "Symbol         | Value\n\
+             ----------------------\n\
+             VERYLONGSYMBOL |  1.00\n\
+             ======================\n\
+             Total\n"
+        );
+    }
+
+    #[test]
+    fn fixed_layout_keeps_preset_widths() {
+        colored::control::set_override(false);
+        let rendered = table(Layout::Fixed).to_string();
+        assert_eq!(rendered.lines().nth(1), Some("-".repeat(23).as_str()));
+        assert_eq!(rendered.lines().next(), Some("Symbol     |      Value"));
+    }
+
+    #[test]
+    fn optional_columns_are_dropped_to_fit() {
+        colored::control::set_override(false);
+        let mut table = Table::new(Layout::Fixed)
+            .max_width(23)
+            .column("Symbol", 10, Align::Left)
+            .column("Trend", 10, Align::Left)
+            .optional()
+            .column("Value", 10, Align::Right);
+        table.row(vec!["XEQT.TO".normal(), "▁▂▃".normal(), "1.00".normal()]);
+
+        assert_eq!(
+            table.to_string(),
+            "Symbol     |      Value\n\
+             -----------------------\n\
+             XEQT.TO    |       1.00\n"
+        );
+    }
+}
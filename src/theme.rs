@@ -0,0 +1,212 @@
+use crate::{assets::AssetClass, config::Colours};
+use colored::{Color, ColoredString, Colorize};
+use serde::Deserialize;
+use std::{collections::HashMap, str::FromStr, sync::OnceLock};
+
+/// A built-in set of colours: `Default` uses the original pink/blue/green allocation colours,
+/// `HighContrast` a palette that stays distinct with colour-blindness and shows gains and
+/// losses in blue and orange instead of green and red.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThemeName {
+    #[default]
+    Default,
+    HighContrast,
+}
+
+impl FromStr for ThemeName {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(ThemeName::Default),
+            "high-contrast" => Ok(ThemeName::HighContrast),
+            _ => Err(format!(
+                "unknown theme {}, expected default or high-contrast",
+                s
+            )),
+        }
+    }
+}
+
+const fn rgb(r: u8, g: u8, b: u8) -> Color {
+    Color::TrueColor { r, g, b }
+}
+
+pub struct Theme {
+    classes: HashMap<AssetClass, Color>,
+    /// Colours for asset classes other than the built-in three, handed out in name order.
+    extra_classes: Vec<Color>,
+    gain: Color,
+    loss: Color,
+}
+
+impl Theme {
+    pub fn built_in(name: ThemeName) -> Self {
+        match name {
+            ThemeName::Default => Theme {
+                classes: HashMap::from([
+                    (AssetClass::stocks(), rgb(245, 169, 184)),
+                    (AssetClass::bonds(), rgb(91, 206, 250)),
+                    (AssetClass::cash(), rgb(186, 218, 85)),
+                ]),
+                extra_classes: vec![
+                    rgb(255, 200, 87),
+                    rgb(178, 132, 230),
+                    rgb(255, 140, 105),
+                    rgb(95, 215, 175),
+                ],
+                gain: Color::Green,
+                loss: Color::Red,
+            },
+            // The Okabe-Ito palette, chosen to be told apart with any kind of colour-blindness.
+            ThemeName::HighContrast => Theme {
+                classes: HashMap::from([
+                    (AssetClass::stocks(), rgb(0, 114, 178)),
+                    (AssetClass::bonds(), rgb(230, 159, 0)),
+                    (AssetClass::cash(), rgb(240, 228, 66)),
+                ]),
+                extra_classes: vec![
+                    rgb(86, 180, 233),
+                    rgb(0, 158, 115),
+                    rgb(213, 94, 0),
+                    rgb(204, 121, 167),
+                ],
+                gain: rgb(86, 180, 233),
+                loss: rgb(230, 159, 0),
+            },
+        }
+    }
+
+    /// The `name` theme with the colours set in the config file's `[colours]` table on top.
+    /// Colours that can't be parsed are reported and the theme's own are kept.
+    pub fn new(name: ThemeName, colours: &Colours) -> Self {
+        let mut theme = Theme::built_in(name);
+        let parse = |key: &str, value: &str| {
+            let colour = parse_colour(value);
+            if colour.is_none() {
+                let warning = format!(
+                    "Ignoring colour {} for {}, expected a name like blue or a hex code like #1f77b4",
+                    value, key
+                );
+                eprintln!("{}", warning.yellow());
+            }
+            colour
+        };
+
+        if let Some(gain) = colours
+            .gain
+            .as_deref()
+            .and_then(|value| parse("gain", value))
+        {
+            theme.gain = gain;
+        }
+        if let Some(loss) = colours
+            .loss
+            .as_deref()
+            .and_then(|value| parse("loss", value))
+        {
+            theme.loss = loss;
+        }
+        for (asset_class, value) in &colours.classes {
+            if let Some(colour) = parse(asset_class, value) {
+                theme.classes.insert(AssetClass::new(asset_class), colour);
+            }
+        }
+
+        theme
+    }
+
+    /// The colour for every asset class in `asset_classes`, giving classes the theme has no
+    /// colour for one from its extra palette.
+    pub fn class_colours(
+        &self,
+        asset_classes: impl IntoIterator<Item = AssetClass>,
+    ) -> HashMap<AssetClass, Color> {
+        let mut colours = self.classes.clone();
+        let mut uncoloured: Vec<_> = asset_classes
+            .into_iter()
+            .filter(|asset_class| !colours.contains_key(asset_class))
+            .collect();
+        uncoloured.sort();
+        uncoloured.dedup();
+        for (asset_class, colour) in uncoloured
+            .into_iter()
+            .zip(self.extra_classes.iter().cycle())
+        {
+            colours.insert(asset_class, *colour);
+        }
+
+        colours
+    }
+
+    /// Colours `text` as a gain if `value` is positive or a loss if it's negative.
+    pub fn gain_or_loss(&self, text: String, value: f64) -> ColoredString {
+        match value {
+            value if value > 0.0 => text.color(self.gain),
+            value if value < 0.0 => text.color(self.loss),
+            _ => text.normal(),
+        }
+    }
+
+    pub fn loss(&self, text: String) -> ColoredString {
+        text.color(self.loss)
+    }
+}
+
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+/// Sets the theme used for the rest of the run. Only the first call has any effect.
+pub fn set(theme: Theme) {
+    let _ = THEME.set(theme);
+}
+
+/// The theme set at startup, or the default one if none was.
+pub fn current() -> &'static Theme {
+    THEME.get_or_init(|| Theme::built_in(ThemeName::Default))
+}
+
+/// Parses a hex code like `#1f77b4` or a colour name like `blue` or `bright magenta`.
+fn parse_colour(value: &str) -> Option<Color> {
+    let Some(hex) = value.strip_prefix('#') else {
+        return Color::from_str(value).ok();
+    };
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some(rgb(channel(0)?, channel(2)?, channel(4)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_codes_and_names() {
+        assert_eq!(parse_colour("#1f77B4"), Some(rgb(31, 119, 180)));
+        assert_eq!(parse_colour("blue"), Some(Color::Blue));
+        assert_eq!(parse_colour("bright magenta"), Some(Color::BrightMagenta));
+        assert_eq!(parse_colour("#12345"), None);
+        assert_eq!(parse_colour("#gg0000"), None);
+        assert_eq!(parse_colour("not a colour"), None);
+    }
+
+    #[test]
+    fn configured_colours_override_the_theme() {
+        let colours = Colours {
+            gain: Some(String::from("blue")),
+            loss: Some(String::from("#zzzzzz")),
+            classes: HashMap::from([(String::from("REITs"), String::from("#ff8c00"))]),
+        };
+        let theme = Theme::new(ThemeName::Default, &colours);
+
+        assert_eq!(theme.gain, Color::Blue);
+        assert_eq!(theme.loss, Color::Red);
+        let classes = theme.class_colours([AssetClass::new("REITs"), AssetClass::new("Crypto")]);
+        assert_eq!(classes[&AssetClass::new("REITs")], rgb(255, 140, 0));
+        assert_eq!(classes[&AssetClass::new("Crypto")], rgb(255, 200, 87));
+        assert_eq!(classes.len(), 5);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::asset_tracker::{AssetTracker, PositionFilter};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Style, Stylize},
+    text::Line,
+    widgets::{Block, Paragraph, Tabs},
+    DefaultTerminal, Frame,
+};
+use std::{io, time::Instant};
+
+const TABS: [&str; 4] = ["Home", "Accounts", "Positions", "Summary"];
+
+/// What a key press asks the event loop to do beyond updating the view.
+#[derive(Debug, PartialEq)]
+enum Action {
+    None,
+    Refresh,
+    Quit,
+}
+
+struct App {
+    tab: usize,
+    /// How many lines the current tab is scrolled down by.
+    scroll: u16,
+    /// Each tab's text, rendered from the tracker's plain-text output.
+    pages: Vec<String>,
+    status: String,
+}
+
+impl App {
+    fn new(pages: Vec<String>) -> Self {
+        App {
+            tab: 0,
+            scroll: 0,
+            pages,
+            status: String::new(),
+        }
+    }
+
+    fn handle(&mut self, code: KeyCode) -> Action {
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return Action::Quit,
+            KeyCode::Char('r') => return Action::Refresh,
+            KeyCode::Right | KeyCode::Tab => self.select((self.tab + 1) % TABS.len()),
+            KeyCode::Left | KeyCode::BackTab => {
+                self.select((self.tab + TABS.len() - 1) % TABS.len())
+            }
+            KeyCode::Down => self.scroll_by(1),
+            KeyCode::Up => self.scroll_by(-1),
+            KeyCode::PageDown => self.scroll_by(10),
+            KeyCode::PageUp => self.scroll_by(-10),
+            _ => {}
+        }
+
+        Action::None
+    }
+
+    fn select(&mut self, tab: usize) {
+        self.tab = tab;
+        self.scroll = 0;
+    }
+
+    /// Scrolls by `lines`, keeping at least the last line of the tab on screen.
+    fn scroll_by(&mut self, lines: i32) {
+        let last_line = self.pages[self.tab].lines().count().saturating_sub(1);
+        self.scroll = (i32::from(self.scroll) + lines).clamp(0, last_line as i32) as u16;
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [tabs_area, page_area, status_area] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let tabs = Tabs::new(TABS)
+            .block(Block::bordered().title(" Questrade Asset Tracker "))
+            .select(self.tab)
+            .highlight_style(Style::new().bold().reversed());
+        frame.render_widget(tabs, tabs_area);
+
+        let page = Paragraph::new(self.pages[self.tab].as_str()).scroll((self.scroll, 0));
+        frame.render_widget(page, page_area);
+
+        let help = "←/→ tabs  ↑/↓ scroll  r refresh  q quit";
+        let status = if self.status.is_empty() {
+            Line::from(help)
+        } else {
+            Line::from(format!("{}  |  {}", self.status, help))
+        };
+        frame.render_widget(status.dim(), status_area);
+    }
+}
+
+/// Renders each tab with the same output as the matching REPL command. Colours are turned off
+/// for the whole TUI session, so the output is plain text.
+fn render_pages(asset_tracker: &AssetTracker) -> io::Result<Vec<String>> {
+    let render = |display: &dyn Fn(&mut Vec<u8>) -> io::Result<()>| {
+        let mut out = Vec::new();
+        display(&mut out)?;
+        Ok::<_, io::Error>(String::from_utf8_lossy(&out).into_owned())
+    };
+
+    Ok(vec![
+        render(&|out| asset_tracker.display_home(out))?,
+        render(&|out| asset_tracker.display_accounts(out, None))?,
+        render(&|out| {
+            asset_tracker.display_market_status(out)?;
+            asset_tracker.display_positions_with_dividends(
+                out,
+                PositionFilter::All,
+                false,
+                None,
+                None,
+            )
+        })?,
+        render(&|out| asset_tracker.display_summary(out))?,
+    ])
+}
+
+/// Shows the tracker's data in a full-screen interface until `q` is pressed. Warnings from
+/// refreshing go to the status line, since printing them would draw over the screen.
+pub async fn run(asset_tracker: &mut AssetTracker) -> io::Result<()> {
+    colored::control::set_override(false);
+    let mut app = App::new(render_pages(asset_tracker)?);
+
+    let mut terminal = ratatui::init();
+    asset_tracker.collect_warnings(true);
+    let result = event_loop(&mut terminal, &mut app, asset_tracker).await;
+    asset_tracker.collect_warnings(false);
+    ratatui::restore();
+
+    result
+}
+
+async fn event_loop(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    asset_tracker: &mut AssetTracker,
+) -> io::Result<()> {
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+
+        // Reading blocks until there's an event, so it's kept off the async worker.
+        let event = tokio::task::spawn_blocking(event::read)
+            .await
+            .map_err(io::Error::other)??;
+        let Event::Key(key) = event else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        // Raw mode turns Ctrl+C into a key press rather than a signal.
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Ok(());
+        }
+
+        match app.handle(key.code) {
+            Action::None => {}
+            Action::Quit => return Ok(()),
+            Action::Refresh => {
+                app.status = String::from("Refreshing...");
+                terminal.draw(|frame| app.draw(frame))?;
+
+                let start = Instant::now();
+                let mut status = match asset_tracker.reload().await {
+                    Ok(()) => {
+                        app.pages = render_pages(asset_tracker)?;
+                        app.scroll = 0;
+                        format!("Refreshed in {:.1}s", start.elapsed().as_secs_f64())
+                    }
+                    Err(err) => format!("Error refreshing: {}", err),
+                };
+                for warning in asset_tracker.take_warnings() {
+                    status.push_str(" | ");
+                    status.push_str(&warning);
+                }
+                app.status = status;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_switch_tabs_and_scroll_within_the_page() {
+        let mut app = App::new(vec![
+            String::from("one\ntwo\nthree"),
+            String::new(),
+            String::new(),
+            String::from("summary"),
+        ]);
+
+        assert_eq!(app.handle(KeyCode::Left), Action::None);
+        assert_eq!(app.tab, 3);
+        app.handle(KeyCode::Right);
+        assert_eq!(app.tab, 0);
+
+        app.handle(KeyCode::PageDown);
+        assert_eq!(app.scroll, 2);
+        app.handle(KeyCode::Up);
+        assert_eq!(app.scroll, 1);
+        app.handle(KeyCode::Right);
+        assert_eq!(app.scroll, 0);
+        app.handle(KeyCode::Up);
+        assert_eq!(app.scroll, 0);
+
+        assert_eq!(app.handle(KeyCode::Char('r')), Action::Refresh);
+        assert_eq!(app.handle(KeyCode::Char('q')), Action::Quit);
+    }
+}
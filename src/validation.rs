@@ -0,0 +1,152 @@
+use crate::asset_tracker::{AccountID, Balances, Position};
+use std::collections::HashMap;
+
+/// How far a position's market value may drift from quantity × price before it's flagged.
+const MARKET_VALUE_TOLERANCE: f64 = 0.05;
+
+#[derive(Debug, PartialEq)]
+pub struct Anomaly {
+    pub account_id: AccountID,
+    pub subject: String,
+    pub issue: String,
+}
+
+/// Scans positions and balances for numbers Questrade shouldn't have returned.
+pub fn find_anomalies(
+    positions: &HashMap<AccountID, Vec<Position>>,
+    balances: &HashMap<AccountID, Balances>,
+) -> Vec<Anomaly> {
+    let mut anomalies = Vec::new();
+
+    for (account_id, acct_positions) in positions.iter() {
+        for position in acct_positions.iter() {
+            let mut flag = |issue: String| {
+                anomalies.push(Anomaly {
+                    account_id: account_id.clone(),
+                    subject: position.symbol.clone(),
+                    issue,
+                })
+            };
+
+            let fields = [
+                ("quantity", position.open_quantity),
+                ("price", position.current_price),
+                ("market value", position.current_market_value),
+                ("book cost", position.total_cost),
+                ("average price", position.average_entry_price),
+                ("P&L", position.open_pnl),
+            ];
+            let non_finite: Vec<_> = fields
+                .iter()
+                .filter(|(_, value)| !value.is_finite())
+                .map(|(name, _)| *name)
+                .collect();
+            if !non_finite.is_empty() {
+                flag(format!("non-finite {}", non_finite.join(", ")));
+                continue;
+            }
+
+            if position.open_quantity <= 0.0 {
+                continue;
+            }
+
+            if position.current_price <= 0.0 {
+                flag(format!(
+                    "price of {} on a long position",
+                    position.current_price
+                ));
+            }
+
+            if position.current_market_value < 0.0 {
+                flag(format!(
+                    "negative market value {:.2} on a long position",
+                    position.current_market_value
+                ));
+            }
+
+            let expected = position.open_quantity * position.current_price;
+            if expected > 0.0
+                && ((position.current_market_value - expected) / expected).abs()
+                    > MARKET_VALUE_TOLERANCE
+            {
+                flag(format!(
+                    "market value {:.2} differs from quantity × price {:.2}",
+                    position.current_market_value, expected
+                ));
+            }
+        }
+    }
+
+    for (account_id, acct_balances) in balances.iter() {
+        let all_balances = acct_balances
+            .per_currency_balances
+            .iter()
+            .chain(acct_balances.combined_balances.iter());
+
+        for balance in all_balances {
+            if [balance.cash, balance.market_value, balance.total_equity]
+                .iter()
+                .any(|value| !value.is_finite())
+            {
+                anomalies.push(Anomaly {
+                    account_id: account_id.clone(),
+                    subject: format!("{} balance", balance.currency),
+                    issue: String::from("non-finite balance value"),
+                });
+            }
+        }
+    }
+
+    anomalies.sort_by(|a, b| (&a.account_id, &a.subject).cmp(&(&b.account_id, &b.subject)));
+    anomalies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(symbol: &str, quantity: f64, price: f64, market_value: f64) -> Position {
+        Position {
+            symbol: symbol.to_string(),
+            symbol_id: 1,
+            open_quantity: quantity,
+            closed_quantity: 0.0,
+            current_market_value: market_value,
+            current_price: price,
+            average_entry_price: price,
+            closed_pnl: 0.0,
+            open_pnl: 0.0,
+            total_cost: market_value,
+        }
+    }
+
+    #[test]
+    fn flags_bad_prices_and_values() {
+        let positions = HashMap::from([(
+            String::from("1"),
+            vec![
+                position("GOOD", 10.0, 5.0, 50.0),
+                position("ZERO", 10.0, 0.0, 0.0),
+                position("NEG", 10.0, 5.0, -50.0),
+                position("OFF", 10.0, 5.0, 500.0),
+                position("NAN", 10.0, f64::NAN, 50.0),
+                position("CLOSED", 0.0, 0.0, 0.0),
+            ],
+        )]);
+
+        let anomalies = find_anomalies(&positions, &HashMap::new());
+        let subjects: Vec<_> = anomalies.iter().map(|a| a.subject.as_str()).collect();
+
+        assert_eq!(subjects, vec!["NAN", "NEG", "NEG", "OFF", "ZERO"]);
+    }
+
+    #[test]
+    fn clean_data_has_no_anomalies() {
+        let positions = HashMap::from([(
+            String::from("1"),
+            vec![position("XEQT.TO", 100.0, 30.0, 3000.5)],
+        )]);
+
+        assert!(find_anomalies(&positions, &HashMap::new()).is_empty());
+    }
+}